@@ -0,0 +1,83 @@
+//! The rules that decide the colors and decorations of a cell.
+//! These are shared by the GL renderer in xwin and the headless
+//! renderer so that both draw the terminal in the same way.
+
+use std::mem;
+use term::color::{ColorAttribute, ColorPalette, RgbColor};
+use term::{self, CellAttributes, SearchHighlight, Underline};
+
+/// Resolve the text and background colors of cells with these
+/// attributes.  `style_fg` is the foreground color of the matching
+/// font style in the configuration, which takes the place of the
+/// default foreground.
+pub fn attribute_colors(
+    attrs: &CellAttributes,
+    style_fg: Option<RgbColor>,
+    palette: &ColorPalette,
+) -> (RgbColor, RgbColor) {
+    let mut fg_color = &attrs.foreground;
+    let mut bg_color = &attrs.background;
+    if attrs.reverse() {
+        mem::swap(&mut fg_color, &mut bg_color);
+    }
+
+    let fg = match fg_color {
+        &ColorAttribute::Foreground => match style_fg {
+            Some(fg) => fg,
+            None => palette.resolve(fg_color),
+        },
+        &ColorAttribute::PaletteIndex(idx) if idx < 8 => {
+            // For compatibility purposes, switch to a brighter version
+            // of one of the standard ANSI colors when Bold is enabled.
+            // This lifts black to dark grey.
+            let idx = if attrs.intensity() == term::Intensity::Bold { idx + 8 } else { idx };
+            palette.resolve(&ColorAttribute::PaletteIndex(idx))
+        }
+        _ => palette.resolve(fg_color),
+    };
+
+    (fg, palette.resolve(bg_color))
+}
+
+/// Adjust the colors of a cell for the cursor, the selection and the
+/// search matches.  While unfocused the cursor is a hollow box, which
+/// the renderer draws in the returned text color.
+pub fn cell_colors(
+    (fg, bg): (RgbColor, RgbColor),
+    palette: &ColorPalette,
+    selected: bool,
+    is_cursor: bool,
+    focused: bool,
+    highlight: Option<&SearchHighlight>,
+) -> (RgbColor, RgbColor) {
+    match (selected, is_cursor, highlight) {
+        // Normally, render the cell as configured
+        (false, false, None) => (fg, bg),
+        // Cursor cell always renders with background over cursor color
+        (_, true, _) if focused => (palette.background, palette.cursor),
+        (_, true, _) => (palette.cursor, bg),
+        // Selection text colors the background
+        // TODO: configurable selection color
+        (true, false, _) => (fg, palette.cursor),
+        // Search matches are yellow, with the current match
+        // in the brighter shade.
+        // TODO: configurable search highlight colors
+        (false, false, Some(highlight)) => {
+            (palette.background, palette.colors[if highlight.current { 11 } else { 3 }])
+        }
+    }
+}
+
+/// Returns the number of underlines (0, 1 or 2) and whether the text
+/// is struck through.  The hyperlink under the mouse is underlined,
+/// or double underlined if it is already underlined.
+pub fn decorations(attrs: &CellAttributes, is_highlighted_hyperlink: bool) -> (usize, bool) {
+    let underline = match (is_highlighted_hyperlink, attrs.underline()) {
+        (true, Underline::Single) => 2,
+        (true, _) => 1,
+        (false, Underline::None) => 0,
+        (false, Underline::Single) => 1,
+        (false, Underline::Double) => 2,
+    };
+    (underline, attrs.strikethrough())
+}
@@ -2,8 +2,10 @@
 
 use failure::Error;
 use std;
+use std::env;
 use std::fs;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use toml;
 
 use term;
@@ -33,8 +35,25 @@ pub struct Config {
 
     /// How many lines of scrollback you want to retain
     pub scrollback_lines: Option<usize>,
+
+    /// A template for the window title.  `{title}` is replaced by
+    /// the title set by the application and `{cwd}` by its working
+    /// directory, eg: "{title} - {cwd}"
+    pub window_title: Option<String>,
+
+    /// What applications may do with the clipboard via OSC 52:
+    /// "deny", "allow_write" (the default), "allow_read" or "ask"
+    #[serde(default)]
+    pub clipboard_access: term::ClipboardAccess,
+
+    /// The largest image file, in bytes, that applications may
+    /// display inline via OSC 1337 or the kitty graphics protocol
+    pub max_image_size: Option<usize>,
 }
 
+/// How many lines of scrollback to retain if the config doesn't say
+pub const DEFAULT_SCROLLBACK_LINES: usize = 3500;
+
 fn default_font_size() -> f64 {
     10.0
 }
@@ -52,8 +71,118 @@ impl Default for Config {
             font_rules: Vec::new(),
             colors: None,
             scrollback_lines: None,
+            window_title: None,
+            clipboard_access: term::ClipboardAccess::default(),
+            max_image_size: None,
+        }
+    }
+}
+
+impl Config {
+    /// Determine which file to load the configuration from.
+    /// An explicitly specified path always wins; otherwise we look in
+    /// `$XDG_CONFIG_HOME/miro/miro.toml` (defaulting to `~/.config`)
+    /// followed by `~/.miro.toml`, returning the first that exists.
+    pub fn find_file(explicit: Option<&Path>) -> Option<PathBuf> {
+        if let Some(path) = explicit {
+            return Some(path.to_path_buf());
+        }
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let xdg = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| home.as_ref().map(|h| h.join(".config")));
+
+        let mut candidates = Vec::new();
+        if let Some(xdg) = xdg {
+            candidates.push(xdg.join("miro").join("miro.toml"));
+        }
+        if let Some(home) = home {
+            candidates.push(home.join(".miro.toml"));
+        }
+
+        candidates.into_iter().find(|p| p.is_file())
+    }
+
+    /// Read and parse the configuration from the specified file.
+    /// The error describes the file and, where possible, the line
+    /// and key that caused the problem.
+    pub fn load_file(path: &Path) -> Result<Config, Error> {
+        let mut file = fs::File::open(path)
+            .map_err(|e| format_err!("Error opening {}: {}", path.display(), e))?;
+        let mut s = String::new();
+        file.read_to_string(&mut s)
+            .map_err(|e| format_err!("Error reading {}: {}", path.display(), e))?;
+
+        toml::from_str(&s).map_err(|e| match e.line_col() {
+            Some(_) => format_err!("Error parsing {}: {}", path.display(), e),
+            None => {
+                // toml only knows the position of syntax errors; for type
+                // errors it gives us the key, so find its line ourselves.
+                match error_key(&e).and_then(|key| find_key_line(&s, &key)) {
+                    Some(line) => {
+                        format_err!("Error parsing {}: {} at line {}", path.display(), e, line + 1)
+                    }
+                    None => format_err!("Error parsing {}: {}", path.display(), e),
+                }
+            }
+        })
+    }
+
+    /// Load the configuration, falling back to the defaults if there is no
+    /// config file or if it fails to load.  Returns the path that was used
+    /// (if any) so that the caller can watch it for changes.
+    pub fn load(explicit: Option<&Path>) -> (Config, Option<PathBuf>) {
+        match Self::find_file(explicit) {
+            Some(path) => match Self::load_file(&path) {
+                Ok(config) => (config, Some(path)),
+                Err(err) => {
+                    eprintln!("{}; using the default configuration", err);
+                    (Config::default(), Some(path))
+                }
+            },
+            None => (Config::default(), None),
+        }
+    }
+}
+
+/// Extract the dotted key name from a toml deserialization error.
+/// The key isn't exposed programmatically, but the Display impl renders it
+/// as "... for key `a.b` ...".
+fn error_key(err: &toml::de::Error) -> Option<String> {
+    let msg = err.to_string();
+    let start = msg.find("for key `")? + "for key `".len();
+    let len = msg[start..].find('`')?;
+    Some(msg[start..start + len].to_owned())
+}
+
+/// Find the zero based line number at which the dotted `key` is assigned
+/// in the toml source `s`.  This understands just enough toml to track
+/// `[table]` and `[[array]]` headers; array indices in the key (eg:
+/// `font_rules.0.italic`) are ignored, so the first match wins.
+fn find_key_line(s: &str, key: &str) -> Option<usize> {
+    let key: Vec<&str> = key.split('.').filter(|k| k.parse::<usize>().is_err()).collect();
+    let (name, table) = key.split_last()?;
+    let mut current: Vec<&str> = Vec::new();
+
+    for (idx, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = line.trim_matches(|c| c == '[' || c == ']');
+            current = header.split('.').map(str::trim).collect();
+            if current.as_slice() == key.as_slice() {
+                return Some(idx);
+            }
+            continue;
+        }
+        if let Some(eq) = line.find('=') {
+            if current.as_slice() == table && line[..eq].trim() == *name {
+                return Some(idx);
+            }
         }
     }
+    None
 }
 
 /// Represents textual styling.
@@ -1,6 +1,7 @@
 use failure::{self, Error};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::mem;
 use std::rc::Rc;
 use std::slice;
 use unicode_width::UnicodeWidthStr;
@@ -55,6 +56,225 @@ impl FontConfiguration {
         self.cached_font(&self.config.font)
     }
 
+    /// Compute the cell dimensions and the descender for the default font.
+    /// Returns (cell_height, cell_width, descender) in pixels.
+    pub fn cell_metrics(&self) -> Result<(usize, usize, isize), Error> {
+        let (cell_height, cell_width, descender) = {
+            // Urgh, this is a bit repeaty, but we need to satisfy the borrow checker
+            let font = self.default_font()?;
+            let tuple = font.borrow_mut().get_metrics()?;
+            tuple
+        };
+
+        let descender = if descender.is_positive() {
+            ((descender as f64) / 64.0).ceil() as isize
+        } else {
+            ((descender as f64) / 64.0).floor() as isize
+        };
+        debug!("METRICS: h={} w={} d={}", cell_height, cell_width, descender);
+
+        // The descender isn't always reliable.  If it looks implausible then we
+        // cook up something more reasonable.  For example, if the descender pulls
+        // the basline up into the top half of the cell then it is probably bad
+        // so we halve that distance, putting it closer to the bottom quarter instead.
+        let descender = if descender.abs() >= cell_height as isize / 2 {
+            let alt_desc = descender / 2;
+            eprintln!(
+                "descender {} is >=50% the cell height, using {} instead",
+                descender, alt_desc
+            );
+            alt_desc
+        } else {
+            descender
+        };
+
+        Ok((cell_height.ceil() as usize, cell_width.ceil() as usize, descender))
+    }
+
+    /// A little helper for shaping text.
+    /// This is needed to dance around interior mutability concerns,
+    /// as the font caches things.
+    pub fn shape_text(&self, s: &str, style: &TextStyle) -> Result<Vec<GlyphInfo>, Error> {
+        let font = self.cached_font(style)?;
+        let mut font = font.borrow_mut();
+        font.shape(0, s)
+    }
+
+    /// Load a glyph and render it to an RGBA bitmap, computing the
+    /// scaling and offsets required to fit it into the terminal cells.
+    pub fn rasterize_glyph(
+        &self,
+        info: &GlyphInfo,
+        style: &TextStyle,
+    ) -> Result<RasterizedGlyph, Error> {
+        let (has_color, ft_glyph, cell_width, cell_height) = {
+            let font = self.cached_font(style)?;
+            let mut font = font.borrow_mut();
+            let (height, width, _) = font.get_metrics()?;
+            let has_color = font.has_color(info.font_idx)?;
+            // This clone is conceptually unsafe, but ok in practice as we are
+            // single threaded and don't load any other glyphs in the body of
+            // this rasterize_glyph() function.
+            let ft_glyph = font.load_glyph(info.font_idx, info.glyph_pos)?.clone();
+            (has_color, ft_glyph, width, height)
+        };
+
+        let scale = if (info.x_advance / info.num_cells as f64).floor() > cell_width {
+            info.num_cells as f64 * (cell_width / info.x_advance)
+        } else if ft_glyph.bitmap.rows as f64 > cell_height {
+            cell_height / ft_glyph.bitmap.rows as f64
+        } else {
+            1.0f64
+        };
+        let (x_offset, y_offset) = if scale != 1.0 {
+            (info.x_offset * scale, info.y_offset * scale)
+        } else {
+            (info.x_offset, info.y_offset)
+        };
+
+        let glyph = if ft_glyph.bitmap.width == 0 || ft_glyph.bitmap.rows == 0 {
+            // a whitespace glyph
+            RasterizedGlyph {
+                bitmap: None,
+                has_color,
+                x_offset: x_offset as isize,
+                y_offset: y_offset as isize,
+                bearing_x: 0,
+                bearing_y: 0,
+                scale,
+            }
+        } else {
+            let mode: ftwrap::FT_Pixel_Mode =
+                unsafe { mem::transmute(ft_glyph.bitmap.pixel_mode as u32) };
+
+            // pitch is the number of bytes per source row
+            let pitch = ft_glyph.bitmap.pitch.abs() as usize;
+            let data = unsafe {
+                slice::from_raw_parts_mut(
+                    ft_glyph.bitmap.buffer,
+                    ft_glyph.bitmap.rows as usize * pitch,
+                )
+            };
+
+            let bitmap = match mode {
+                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_LCD => {
+                    let width = ft_glyph.bitmap.width as usize / 3;
+                    let height = ft_glyph.bitmap.rows as usize;
+                    let size = (width * height * 4) as usize;
+                    let mut rgba = Vec::with_capacity(size);
+                    rgba.resize(size, 0u8);
+                    for y in 0..height {
+                        let src_offset = y * pitch as usize;
+                        let dest_offset = y * width * 4;
+                        for x in 0..width {
+                            let blue = data[src_offset + (x * 3) + 0];
+                            let green = data[src_offset + (x * 3) + 1];
+                            let red = data[src_offset + (x * 3) + 2];
+                            let alpha = red | green | blue;
+                            rgba[dest_offset + (x * 4) + 0] = red;
+                            rgba[dest_offset + (x * 4) + 1] = green;
+                            rgba[dest_offset + (x * 4) + 2] = blue;
+                            rgba[dest_offset + (x * 4) + 3] = alpha;
+                        }
+                    }
+
+                    GlyphBitmap { width, height, data: rgba }
+                }
+                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_BGRA => {
+                    let width = ft_glyph.bitmap.width as usize;
+                    let height = ft_glyph.bitmap.rows as usize;
+                    let size = (width * height * 4) as usize;
+                    let mut rgba = Vec::with_capacity(size);
+                    rgba.resize(size, 0u8);
+                    for y in 0..height {
+                        let src_offset = y * pitch as usize;
+                        let dest_offset = y * width * 4;
+                        for x in 0..width {
+                            let blue = data[src_offset + (x * 4) + 0];
+                            let green = data[src_offset + (x * 4) + 1];
+                            let red = data[src_offset + (x * 4) + 2];
+                            let alpha = data[src_offset + (x * 4) + 3];
+
+                            rgba[dest_offset + (x * 4) + 0] = red;
+                            rgba[dest_offset + (x * 4) + 1] = green;
+                            rgba[dest_offset + (x * 4) + 2] = blue;
+                            rgba[dest_offset + (x * 4) + 3] = alpha;
+                        }
+                    }
+
+                    GlyphBitmap { width, height, data: rgba }
+                }
+                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_GRAY => {
+                    let width = ft_glyph.bitmap.width as usize;
+                    let height = ft_glyph.bitmap.rows as usize;
+                    let size = (width * height * 4) as usize;
+                    let mut rgba = Vec::with_capacity(size);
+                    rgba.resize(size, 0u8);
+                    for y in 0..height {
+                        let src_offset = y * pitch;
+                        let dest_offset = y * width * 4;
+                        for x in 0..width {
+                            let gray = data[src_offset + x];
+
+                            rgba[dest_offset + (x * 4) + 0] = gray;
+                            rgba[dest_offset + (x * 4) + 1] = gray;
+                            rgba[dest_offset + (x * 4) + 2] = gray;
+                            rgba[dest_offset + (x * 4) + 3] = gray;
+                        }
+                    }
+                    GlyphBitmap { width, height, data: rgba }
+                }
+                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_MONO => {
+                    let width = ft_glyph.bitmap.width as usize;
+                    let height = ft_glyph.bitmap.rows as usize;
+                    let size = (width * height * 4) as usize;
+                    let mut rgba = Vec::with_capacity(size);
+                    rgba.resize(size, 0u8);
+                    for y in 0..height {
+                        let src_offset = y * pitch;
+                        let dest_offset = y * width * 4;
+                        let mut x = 0;
+                        for i in 0..pitch {
+                            if x >= width {
+                                break;
+                            }
+                            let mut b = data[src_offset + i];
+                            for _ in 0..8 {
+                                if x >= width {
+                                    break;
+                                }
+                                if b & 0x80 == 0x80 {
+                                    for j in 0..4 {
+                                        rgba[dest_offset + (x * 4) + j] = 0xff;
+                                    }
+                                }
+                                b = b << 1;
+                                x += 1;
+                            }
+                        }
+                    }
+                    GlyphBitmap { width, height, data: rgba }
+                }
+                mode @ _ => bail!("unhandled pixel mode: {:?}", mode),
+            };
+
+            let bearing_x = (ft_glyph.bitmap_left as f64 * scale) as isize;
+            let bearing_y = (ft_glyph.bitmap_top as f64 * scale) as isize;
+
+            RasterizedGlyph {
+                bitmap: Some(bitmap),
+                has_color,
+                x_offset: x_offset as isize,
+                y_offset: y_offset as isize,
+                bearing_x,
+                bearing_y,
+                scale,
+            }
+        };
+
+        Ok(glyph)
+    }
+
     /// Apply the defined font_rules from the user configuration to
     /// produce the text style that best matches the supplied input
     /// cell attributes.
@@ -92,6 +312,28 @@ impl FontConfiguration {
     }
 }
 
+/// An RGBA bitmap holding a rendered glyph
+#[derive(Debug)]
+pub struct GlyphBitmap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// A glyph that has been rendered by freetype, along with the information
+/// needed to position it within the terminal cells.
+#[derive(Debug)]
+pub struct RasterizedGlyph {
+    pub has_color: bool,
+    pub x_offset: isize,
+    pub y_offset: isize,
+    pub bearing_x: isize,
+    pub bearing_y: isize,
+    pub scale: f64,
+    /// The image data is None for whitespace glyphs
+    pub bitmap: Option<GlyphBitmap>,
+}
+
 /// Holds information about a shaped glyph
 #[derive(Clone, Debug)]
 pub struct GlyphInfo {
@@ -0,0 +1,434 @@
+//! Renders the terminal model to an RGBA image on the CPU.
+//! This doesn't require an X server or a GPU, which makes it
+//! suitable for producing frames for visual regression tests.
+
+use crate::cellstyle;
+use crate::config::TextStyle;
+use crate::font::{FontConfiguration, GlyphBitmap, GlyphInfo, RasterizedGlyph};
+use failure::Error;
+use png::{self, HasParameters};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use term::color::{ColorPalette, RgbColor};
+use term::hyperlink::Hyperlink;
+use term::{self, CursorPosition, ImageSlice, Line, SearchHighlight, Terminal, TerminalHost};
+
+/// A TerminalHost that isn't connected to anything.
+/// Responses from the terminal are discarded and there is no clipboard.
+pub struct HeadlessHost {
+    sink: io::Sink,
+}
+
+impl HeadlessHost {
+    pub fn new() -> Self {
+        Self { sink: io::sink() }
+    }
+}
+
+impl TerminalHost for HeadlessHost {
+    fn writer(&mut self) -> &mut Write {
+        &mut self.sink
+    }
+
+    fn get_clipboard(&mut self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+
+    fn set_clipboard(&mut self, _clip: Option<String>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn set_current_dir(&mut self, _cwd: &str) {}
+
+    fn click_link(&mut self, _link: &Rc<Hyperlink>) {}
+}
+
+/// An RGBA image with 8 bits per channel
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Create an image filled with the specified color
+    pub fn new(width: usize, height: usize, color: RgbColor) -> Image {
+        let mut data = Vec::with_capacity(width * height * 4);
+        for _ in 0..width * height {
+            data.extend_from_slice(&[color.red, color.green, color.blue, 0xff]);
+        }
+        Image { width, height, data }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: RgbColor) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                let offset = (y * self.width + x) * 4;
+                self.data[offset] = color.red;
+                self.data[offset + 1] = color.green;
+                self.data[offset + 2] = color.blue;
+                self.data[offset + 3] = 0xff;
+            }
+        }
+    }
+
+    /// Composite a glyph bitmap with its top left corner at (left, top).
+    /// Color glyphs (emoji) use their own pre-multiplied colors, while the
+    /// others are treated as a per-channel coverage mask for `color`.
+    fn draw_glyph(
+        &mut self,
+        left: isize,
+        top: isize,
+        bitmap: &GlyphBitmap,
+        scale: f64,
+        has_color: bool,
+        color: RgbColor,
+    ) {
+        let width = (bitmap.width as f64 * scale) as isize;
+        let height = (bitmap.height as f64 * scale) as isize;
+        let fg = [color.red, color.green, color.blue];
+
+        for dy in 0..height {
+            let y = top + dy;
+            if y < 0 || y >= self.height as isize {
+                continue;
+            }
+            let src_y = ((dy as f64 / scale) as usize).min(bitmap.height - 1);
+            for dx in 0..width {
+                let x = left + dx;
+                if x < 0 || x >= self.width as isize {
+                    continue;
+                }
+                let src_x = ((dx as f64 / scale) as usize).min(bitmap.width - 1);
+                let src = &bitmap.data[(src_y * bitmap.width + src_x) * 4..][..4];
+                let offset = (y as usize * self.width + x as usize) * 4;
+                let dest = &mut self.data[offset..offset + 4];
+
+                for i in 0..3 {
+                    let d = dest[i] as u32;
+                    let value = if has_color {
+                        src[i] as u32 + d * (255 - src[3] as u32) / 255
+                    } else {
+                        let coverage = src[i] as u32;
+                        (fg[i] as u32 * coverage + d * (255 - coverage)) / 255
+                    };
+                    dest[i] = value.min(255) as u8;
+                }
+            }
+        }
+    }
+
+    /// Composite the rows of an application's image that are shown by
+    /// `slice`, with their top left corner at (left, top)
+    fn draw_image(&mut self, left: usize, top: usize, slice: &ImageSlice) {
+        let image = &slice.image;
+        for dy in 0..slice.height.min(self.height.saturating_sub(top)) {
+            for dx in 0..image.width().min(self.width.saturating_sub(left)) {
+                let src = image.pixel(dx, slice.top + dy);
+                let alpha = src[3] as u32;
+                let offset = ((top + dy) * self.width + left + dx) * 4;
+                let dest = &mut self.data[offset..offset + 4];
+                for i in 0..3 {
+                    let value = (src[i] as u32 * alpha + dest[i] as u32 * (255 - alpha)) / 255;
+                    dest[i] = value as u8;
+                }
+            }
+        }
+    }
+
+    /// Save the image in PNG format
+    pub fn write_png(&self, path: &Path) -> Result<(), Error> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
+
+/// Describes how to paint a single cell
+struct CellPaint {
+    x: usize,
+    y: usize,
+    fg: RgbColor,
+    bg: RgbColor,
+    underline: usize,
+    strikethrough: bool,
+    /// Draw a box around the cell, for the unfocused cursor
+    outline: bool,
+    /// The glyph that starts in this cell, along with its position
+    /// relative to the top left of the cell
+    glyph: Option<(Rc<RasterizedGlyph>, isize, isize)>,
+}
+
+pub struct HeadlessRenderer<'a> {
+    fonts: &'a FontConfiguration,
+    cell_height: usize,
+    cell_width: usize,
+    descender: isize,
+    glyph_cache: HashMap<(usize, u32, TextStyle), Rc<RasterizedGlyph>>,
+}
+
+impl<'a> HeadlessRenderer<'a> {
+    pub fn new(fonts: &'a FontConfiguration) -> Result<HeadlessRenderer<'a>, Error> {
+        let (cell_height, cell_width, descender) = fonts.cell_metrics()?;
+        Ok(HeadlessRenderer {
+            fonts,
+            cell_height,
+            cell_width,
+            descender,
+            glyph_cache: HashMap::new(),
+        })
+    }
+
+    /// Returns the (width, height) in pixels of a cell.  The terminal
+    /// needs to know this in order to lay out images.
+    pub fn cell_size(&self) -> (usize, usize) {
+        (self.cell_width, self.cell_height)
+    }
+
+    /// Returns the (width, height) in pixels of an image of the terminal
+    pub fn pixel_size(&self, rows: usize, cols: usize) -> (usize, usize) {
+        (cols * self.cell_width, rows * self.cell_height)
+    }
+
+    fn cached_glyph(
+        &mut self,
+        info: &GlyphInfo,
+        style: &TextStyle,
+    ) -> Result<Rc<RasterizedGlyph>, Error> {
+        let key = (info.font_idx, info.glyph_pos, style.clone());
+        if let Some(entry) = self.glyph_cache.get(&key) {
+            return Ok(Rc::clone(entry));
+        }
+        let glyph = Rc::new(self.fonts.rasterize_glyph(info, style)?);
+        self.glyph_cache.insert(key, Rc::clone(&glyph));
+        Ok(glyph)
+    }
+
+    /// Render the visible portion of the terminal
+    pub fn render(&mut self, terminal: &mut Terminal) -> Result<Image, Error> {
+        terminal.refresh_search();
+        let (rows, cols) = {
+            let screen = terminal.screen();
+            (screen.physical_rows, screen.physical_cols)
+        };
+        let (width, height) = self.pixel_size(rows, cols);
+        // Copied because the terminal is borrowed mutably below
+        let palette = terminal.palette().clone();
+        let mut image = Image::new(width, height, palette.background);
+
+        let cursor = terminal.cursor_pos();
+        let current_highlight = terminal.current_highlight();
+        let focused = terminal.has_focus();
+
+        // Every line needs to be painted, so pretend that they are all dirty
+        // in order to pick up the selection ranges.
+        terminal.make_all_lines_dirty();
+        let mut cells = Vec::new();
+        for (line_idx, line, selrange, highlights) in terminal.get_dirty_lines() {
+            self.layout_line(
+                line_idx,
+                line,
+                selrange,
+                &highlights,
+                &cursor,
+                focused,
+                &palette,
+                &current_highlight,
+                cols,
+                &mut cells,
+            )?;
+        }
+        terminal.clean_dirty_lines();
+
+        // As with the GL renderer, paint the backgrounds first so that
+        // glyphs that overhang their cell are not clipped by their
+        // neighbors, then the glyphs and then the line decorations.
+        for cell in &cells {
+            image.fill_rect(cell.x, cell.y, self.cell_width, self.cell_height, cell.bg);
+        }
+
+        // Images with a negative z-index go beneath the text and the
+        // others above it.  The sort is stable, so images with the same
+        // z-index are drawn in the order in which they were placed.
+        let mut images = terminal.visible_images();
+        images.sort_by_key(|&(_, slice)| slice.z_index);
+        let (beneath, above): (Vec<_>, Vec<_>) =
+            images.into_iter().partition(|&(_, slice)| slice.z_index < 0);
+        for (row, slice) in beneath {
+            image.draw_image(slice.x * self.cell_width, row * self.cell_height, slice);
+        }
+
+        for cell in &cells {
+            if let Some((ref glyph, left, top)) = cell.glyph {
+                if let Some(ref bitmap) = glyph.bitmap {
+                    image.draw_glyph(
+                        cell.x as isize + left,
+                        cell.y as isize + top,
+                        bitmap,
+                        glyph.scale,
+                        glyph.has_color,
+                        cell.fg,
+                    );
+                }
+            }
+        }
+
+        let descender_row = (self.cell_height as isize + self.descender) as usize;
+        let descender_plus_one = (1 + descender_row).min(self.cell_height - 1);
+        let descender_plus_two = (2 + descender_row).min(self.cell_height - 1);
+        let strike_row = descender_row / 2;
+        for cell in &cells {
+            let mut decoration_rows = Vec::new();
+            match cell.underline {
+                1 => decoration_rows.push(descender_plus_one),
+                2 => {
+                    decoration_rows.push(descender_row);
+                    decoration_rows.push(descender_plus_two);
+                }
+                _ => {}
+            }
+            if cell.strikethrough {
+                decoration_rows.push(strike_row);
+            }
+            for row in decoration_rows {
+                image.fill_rect(cell.x, cell.y + row, self.cell_width, 1, cell.fg);
+            }
+            if cell.outline {
+                let (x, y, w, h) = (cell.x, cell.y, self.cell_width, self.cell_height);
+                image.fill_rect(x, y, w, 1, cell.fg);
+                image.fill_rect(x, y + h - 1, w, 1, cell.fg);
+                image.fill_rect(x, y, 1, h, cell.fg);
+                image.fill_rect(x + w - 1, y, 1, h, cell.fg);
+            }
+        }
+
+        for (row, slice) in above {
+            image.draw_image(slice.x * self.cell_width, row * self.cell_height, slice);
+        }
+
+        Ok(image)
+    }
+
+    /// Compute the colors and glyphs for the cells in a line.
+    /// The colors follow the rules in cellstyle, as in TerminalWindow::render_screen_line.
+    fn layout_line(
+        &mut self,
+        line_idx: usize,
+        line: &Line,
+        selection: Range<usize>,
+        highlights: &[SearchHighlight],
+        cursor: &CursorPosition,
+        focused: bool,
+        palette: &ColorPalette,
+        current_highlight: &Option<Rc<Hyperlink>>,
+        num_cols: usize,
+        cells: &mut Vec<CellPaint>,
+    ) -> Result<(), Error> {
+        let y = line_idx * self.cell_height;
+
+        for cluster in line.cluster() {
+            let attrs = &cluster.attrs;
+            let is_highlited_hyperlink = match (&attrs.hyperlink, current_highlight) {
+                (&Some(ref this), &Some(ref highlight)) => this == highlight,
+                _ => false,
+            };
+            let style = self.fonts.match_style(attrs).clone();
+            let colors = cellstyle::attribute_colors(attrs, style.foreground, palette);
+            let (underline, strikethrough) = cellstyle::decorations(attrs, is_highlited_hyperlink);
+
+            let glyph_info = self.fonts.shape_text(&cluster.text, &style)?;
+            for info in glyph_info.iter() {
+                let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
+                let glyph = self.cached_glyph(info, &style)?;
+
+                let left = glyph.x_offset + glyph.bearing_x;
+                let top = (self.cell_height as isize + self.descender)
+                    - (glyph.y_offset + glyph.bearing_y);
+
+                for glyph_idx in 0..info.num_cells as usize {
+                    let cell_idx = cell_idx + glyph_idx;
+                    if cell_idx >= num_cols {
+                        break;
+                    }
+
+                    let selected = term::in_range(cell_idx, &selection);
+                    let is_cursor = line_idx as i64 == cursor.y && cursor.x == cell_idx;
+                    let highlight = highlights.iter().find(|h| term::in_range(cell_idx, &h.cols));
+
+                    let (fg, bg) = cellstyle::cell_colors(
+                        colors, palette, selected, is_cursor, focused, highlight,
+                    );
+
+                    cells.push(CellPaint {
+                        x: cell_idx * self.cell_width,
+                        y,
+                        fg,
+                        bg,
+                        underline: if is_cursor && !focused { 0 } else { underline },
+                        strikethrough,
+                        outline: is_cursor && !focused,
+                        glyph: if glyph_idx == 0 {
+                            Some((Rc::clone(&glyph), left, top))
+                        } else {
+                            None
+                        },
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+
+    fn pixel(image: &Image, x: usize, y: usize) -> RgbColor {
+        let offset = (y * image.width + x) * 4;
+        RgbColor::new(image.data[offset], image.data[offset + 1], image.data[offset + 2])
+    }
+
+    #[test]
+    fn test_render_colors_and_images() {
+        let fonts = FontConfiguration::new(Config::default());
+        let mut renderer = HeadlessRenderer::new(&fonts).unwrap();
+        let (cell_width, cell_height) = renderer.cell_size();
+
+        let mut terminal = Terminal::new(2, 4, 0);
+        terminal.set_cell_size(cell_width, cell_height);
+        let mut host = HeadlessHost::new();
+        // A red background, then reverse video, then the default colors
+        terminal.advance_bytes("\x1b[41m \x1b[0;7m \x1b[0m", &mut host);
+        // A single red pixel in the top left of the second row
+        terminal.advance_bytes("\x1b[2;1H\x1b_Ga=T,f=24,s=1,v=1;/wAA\x1b\\", &mut host);
+        // Leave the cursor on the third cell of the first row
+        terminal.advance_bytes("\x1b[1;3H", &mut host);
+
+        let image = renderer.render(&mut terminal).unwrap();
+        let palette = terminal.palette().clone();
+        assert_eq!((image.width, image.height), renderer.pixel_size(2, 4));
+
+        let center = |col: usize, row: usize| {
+            pixel(&image, col * cell_width + cell_width / 2, row * cell_height + cell_height / 2)
+        };
+        assert_eq!(center(0, 0), palette.colors[1]);
+        assert_eq!(center(1, 0), palette.foreground);
+        assert_eq!(center(3, 0), palette.background);
+        // The focused cursor is a block in the cursor color
+        assert_eq!(center(2, 0), palette.cursor);
+        assert_eq!(pixel(&image, 0, cell_height), RgbColor::new(0xff, 0, 0));
+        assert_eq!(pixel(&image, 1, cell_height + 1), palette.background);
+    }
+}
@@ -0,0 +1,125 @@
+//! Helper for detecting changes to a file using inotify
+
+use failure::Error;
+use libc;
+use mio::event::Evented;
+use mio::unix::EventedFd;
+use mio::{Poll, PollOpt, Ready, Token};
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+/// Watches a single file for modification.
+/// Editors commonly save by writing a new file and renaming it over the
+/// original, which would invalidate a watch on the file itself, so we
+/// watch the containing directory and filter the events by name.
+pub struct FileWatcher {
+    fd: RawFd,
+    name: PathBuf,
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<FileWatcher, Error> {
+        let name = match path.file_name() {
+            Some(name) => PathBuf::from(name),
+            None => bail!("{} does not name a file", path.display()),
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+
+        unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd == -1 {
+                bail!("inotify_init1 failed: {:?}", io::Error::last_os_error());
+            }
+            let watcher = FileWatcher { fd, name };
+
+            let wd =
+                libc::inotify_add_watch(fd, dir.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO);
+            if wd == -1 {
+                bail!("inotify_add_watch {}: {:?}", path.display(), io::Error::last_os_error());
+            }
+
+            Ok(watcher)
+        }
+    }
+
+    /// Consume the pending events, returning true if any of them
+    /// pertain to the watched file.
+    pub fn read_events(&self) -> Result<bool, Error> {
+        const HEADER: usize = mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+
+        loop {
+            let res = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if res == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(changed);
+                }
+                bail!("inotify read failed: {:?}", err);
+            }
+
+            let len = res as usize;
+            let mut offset = 0;
+            while offset + HEADER <= len {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const _) };
+                let name_start = offset + HEADER;
+                let name_end = (name_start + event.len as usize).min(len);
+                // The name is padded out with NUL bytes
+                let name = &buf[name_start..name_end];
+                let name = match name.iter().position(|&b| b == 0) {
+                    Some(nul) => &name[..nul],
+                    None => name,
+                };
+                if Path::new(OsStr::from_bytes(name)) == self.name {
+                    changed = true;
+                }
+                offset = name_end;
+            }
+        }
+    }
+}
+
+/// Glue for working with mio
+impl Evented for FileWatcher {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        EventedFd(&self.fd).deregister(poll)
+    }
+}
@@ -12,11 +12,14 @@ extern crate freetype;
 extern crate harfbuzz_sys;
 extern crate libc;
 extern crate mio;
+extern crate png;
 extern crate resize;
 extern crate serde;
 extern crate unicode_width;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate structopt;
 extern crate palette;
 extern crate term;
 extern crate toml;
@@ -32,14 +35,21 @@ extern crate xcb_util;
 use mio::unix::EventedFd;
 use mio::{Events, Poll, PollOpt, Ready, Token};
 use std::env;
-use std::ffi::CStr;
+use std::ffi::{CStr, OsString};
+use std::fs::File;
+use std::io::Read;
 use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
 use std::process::Command;
 use std::str;
 use std::time::Duration;
+use structopt::StructOpt;
 
+mod cellstyle;
 mod config;
 mod font;
+mod headless;
+mod inotify;
 mod xgfx;
 mod xkeysyms;
 use font::{ftwrap, FontConfiguration};
@@ -69,13 +79,129 @@ fn get_shell() -> Result<String, Error> {
     })
 }
 
-fn run() -> Result<(), Error> {
+/// The initial size of the terminal, measured in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Geometry {
+    cols: u16,
+    rows: u16,
+}
+
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry { cols: 80, rows: 24 }
+    }
+}
+
+/// Parse a geometry specification of the form `COLSxROWS`
+fn parse_geometry(s: &str) -> Result<Geometry, String> {
+    let mut iter = s.splitn(2, |c| c == 'x' || c == 'X');
+    let cols = iter.next().and_then(|c| c.parse::<u16>().ok());
+    let rows = iter.next().and_then(|r| r.parse::<u16>().ok());
+    match (cols, rows) {
+        (Some(cols), Some(rows)) if cols > 0 && rows > 0 => Ok(Geometry { cols, rows }),
+        _ => Err(format!("expected COLSxROWS, for example 80x24, but got {}", s)),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "A terminal emulator")]
+struct Opt {
+    /// Load the configuration from this file rather than from
+    /// $XDG_CONFIG_HOME/miro/miro.toml or ~/.miro.toml
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Start the program in this directory
+    #[structopt(long = "cwd", parse(from_os_str))]
+    cwd: Option<PathBuf>,
+
+    /// The initial size of the terminal, eg: 80x24
+    #[structopt(
+        long = "geometry",
+        value_name = "COLSxROWS",
+        parse(try_from_str = "parse_geometry")
+    )]
+    geometry: Option<Geometry>,
+
+    /// Set the class portion of the WM_CLASS window property
+    #[structopt(long = "class", value_name = "NAME")]
+    class: Option<String>,
+
+    /// Set the initial window title
+    #[structopt(long = "title")]
+    title: Option<String>,
+
+    /// Run PROG with the remaining ARGS instead of the shell.
+    /// This must be the last option on the command line.
+    #[structopt(
+        short = "e",
+        value_name = "PROG ARGS",
+        parse(from_os_str),
+        raw(allow_hyphen_values = "true", multiple = "true")
+    )]
+    exec: Vec<OsString>,
+
+    #[structopt(subcommand)]
+    cmd: Option<SubCommand>,
+}
+
+#[derive(Debug, StructOpt)]
+enum SubCommand {
+    /// Render the terminal output captured in a file to a PNG image,
+    /// without needing a display.  The size of the terminal is taken
+    /// from the --geometry option.
+    #[structopt(name = "render")]
+    Render(RenderCommand),
+}
+
+#[derive(Debug, StructOpt)]
+struct RenderCommand {
+    /// The file holding the bytes to feed to the terminal
+    #[structopt(long = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// Where to write the PNG image
+    #[structopt(long = "out", parse(from_os_str))]
+    out: PathBuf,
+}
+
+/// Feed the input through a terminal and rasterize the result
+fn render(opts: &Opt, render: &RenderCommand) -> Result<(), Error> {
+    let (config, _) = config::Config::load(opts.config.as_ref().map(PathBuf::as_path));
+    let geometry = opts.geometry.unwrap_or_default();
+
+    let mut input = Vec::new();
+    File::open(&render.input)
+        .and_then(|mut f| f.read_to_end(&mut input))
+        .map_err(|e| format_err!("Error reading {}: {}", render.input.display(), e))?;
+
+    let mut terminal = term::Terminal::new(
+        geometry.rows as usize,
+        geometry.cols as usize,
+        config.scrollback_lines.unwrap_or(config::DEFAULT_SCROLLBACK_LINES),
+    );
+    terminal.set_default_palette(
+        config.colors.clone().map(|p| p.into()).unwrap_or_else(term::color::ColorPalette::default),
+    );
+    let fontconfig = FontConfiguration::new(config);
+    let mut renderer = headless::HeadlessRenderer::new(&fontconfig)?;
+    let (cell_width, cell_height) = renderer.cell_size();
+    terminal.set_cell_size(cell_width, cell_height);
+
+    let mut host = headless::HeadlessHost::new();
+    terminal.advance_bytes(&input, &mut host);
+
+    let image = renderer.render(&mut terminal)?;
+    image.write_png(&render.out)
+}
+
+fn run(opts: Opt) -> Result<(), Error> {
     let poll = Poll::new()?;
     let conn = xgfx::Connection::new()?;
 
     let waiter = sigchld::ChildWaiter::new()?;
 
-    let config = config::Config::default();
+    let (config, config_path) = config::Config::load(opts.config.as_ref().map(PathBuf::as_path));
     println!("Using configuration: {:#?}", config);
 
     // First step is to figure out the font metrics so that we know how
@@ -89,15 +215,26 @@ fn run() -> Result<(), Error> {
     // so that we can scale glyphs appropriately
     let (cell_height, cell_width, _) = font.borrow_mut().get_metrics()?;
 
-    let initial_cols = 80u16;
-    let initial_rows = 24u16;
+    let geometry = opts.geometry.unwrap_or_default();
+    let initial_cols = geometry.cols;
+    let initial_rows = geometry.rows;
     let initial_pixel_width = initial_cols * cell_width.ceil() as u16;
     let initial_pixel_height = initial_rows * cell_height.ceil() as u16;
 
     let (master, slave) =
         pty::openpty(initial_rows, initial_cols, initial_pixel_width, initial_pixel_height)?;
 
-    let cmd = Command::new(get_shell()?);
+    let mut cmd = match opts.exec.split_first() {
+        Some((prog, args)) => {
+            let mut cmd = Command::new(prog);
+            cmd.args(args);
+            cmd
+        }
+        None => Command::new(get_shell()?),
+    };
+    if let Some(ref cwd) = opts.cwd {
+        cmd.current_dir(cwd);
+    }
     let child = slave.spawn_command(cmd)?;
     eprintln!("spawned: {:?}", child);
 
@@ -108,10 +245,30 @@ fn run() -> Result<(), Error> {
 
     poll.register(&waiter, Token(2), Ready::readable(), PollOpt::edge())?;
 
-    let terminal = term::Terminal::new(
+    // Watch the config file so that we can pick up changes to it
+    let watcher = match config_path {
+        Some(ref path) => match inotify::FileWatcher::new(path) {
+            Ok(watcher) => {
+                poll.register(&watcher, Token(3), Ready::readable(), PollOpt::edge())?;
+                Some(watcher)
+            }
+            Err(err) => {
+                eprintln!("Unable to watch {} for changes: {}", path.display(), err);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut terminal = term::Terminal::new(
         initial_rows as usize,
         initial_cols as usize,
-        config.scrollback_lines.unwrap_or(3500),
+        config.scrollback_lines.unwrap_or(config::DEFAULT_SCROLLBACK_LINES),
+    );
+    terminal.set_clipboard_access(config.clipboard_access);
+    terminal.set_max_image_size(config.max_image_size.unwrap_or(term::DEFAULT_MAX_IMAGE_SIZE));
+    terminal.set_default_palette(
+        config.colors.map(|p| p.into()).unwrap_or_else(term::color::ColorPalette::default),
     );
     //    let message = "; ❤ 😍🤢\n\x1b[91;mw00t\n\x1b[37;104;m bleet\x1b[0;m.";
     //    terminal.advance_bytes(message);
@@ -125,9 +282,14 @@ fn run() -> Result<(), Error> {
         master,
         child,
         fontconfig,
-        config.colors.map(|p| p.into()).unwrap_or_else(term::color::ColorPalette::default),
     )?;
 
+    window.set_class(opts.class.as_ref().map(String::as_str).unwrap_or("Miro"));
+    window.set_title_format(config.window_title.clone());
+    window.set_config_path(opts.config.clone());
+    if let Some(ref title) = opts.title {
+        window.set_title(title);
+    }
     window.show();
 
     let mut events = Events::with_capacity(8);
@@ -142,7 +304,9 @@ fn run() -> Result<(), Error> {
             }
             conn.flush();
 
-            poll.poll(&mut events, None)?;
+            // If painting was deferred by synchronized output, wake up
+            // in time to paint once the deadline passes.
+            poll.poll(&mut events, window.paint_timeout())?;
         }
 
         for event in &events {
@@ -183,10 +347,29 @@ fn run() -> Result<(), Error> {
                 println!("got sigchld from pid {}", pid);
                 window.test_for_child_exit()?;
             }
+
+            if event.token() == Token(3) {
+                if let (Some(watcher), Some(path)) = (watcher.as_ref(), config_path.as_ref()) {
+                    if watcher.read_events()? {
+                        match config::Config::load_file(path) {
+                            Ok(config) => {
+                                if let Err(err) = window.reconfigure(config) {
+                                    window.show_config_error(&err);
+                                }
+                            }
+                            Err(err) => window.show_config_error(&err),
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
 fn main() {
-    run().unwrap();
+    let opts = Opt::from_args();
+    match opts.cmd {
+        Some(SubCommand::Render(ref cmd)) => render(&opts, cmd).unwrap(),
+        None => run(opts).unwrap(),
+    }
 }
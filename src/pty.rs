@@ -5,9 +5,11 @@ use libc::{self, winsize};
 use mio::event::Evented;
 use mio::unix::EventedFd;
 use mio::{Poll, PollOpt, Ready, Token};
+use std::fs;
 use std::io;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
 use std::ptr;
 
@@ -212,6 +214,17 @@ impl MasterPty {
 
         Ok(())
     }
+
+    /// Returns the working directory of the foreground process group
+    /// of the pty; this is typically the shell or the program that
+    /// it is running.
+    pub fn foreground_process_cwd(&self) -> Option<PathBuf> {
+        let pgrp = unsafe { libc::tcgetpgrp(self.fd) };
+        if pgrp <= 0 {
+            return None;
+        }
+        fs::read_link(format!("/proc/{}/cwd", pgrp)).ok()
+    }
 }
 
 impl AsRawFd for MasterPty {
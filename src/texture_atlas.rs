@@ -216,6 +216,11 @@ impl Atlas {
         Ok(Self { textures: vec![tex] })
     }
 
+    /// Returns the number of pixels in the textures of the atlas
+    pub fn pixels(&self) -> usize {
+        self.textures.iter().map(|tex| tex.width as usize * tex.height as usize).sum()
+    }
+
     // TODO: this is gross, need to tweak this API
     pub fn texture(&self) -> Rc<SrgbTexture2d> {
         Rc::clone(&self.textures[0].texture)
@@ -61,6 +61,14 @@ impl Connection {
         let conn = unsafe { xcb::Connection::from_raw_conn(XGetXCBConnection(display)) };
         unsafe { XSetEventQueueOwner(display, 1) };
 
+        // Without this, a held key repeats as a release followed by a
+        // press, which would be reported to applications as such
+        let mut detectable = 0;
+        unsafe { x11::xlib::XkbSetDetectableAutoRepeat(display, 1, &mut detectable) };
+        if detectable == 0 {
+            eprintln!("The X server can't report key repeats without releasing the key");
+        }
+
         let atom_protocols = xcb::intern_atom(&conn, false, "WM_PROTOCOLS").get_reply()?.atom();
         let atom_delete = xcb::intern_atom(&conn, false, "WM_DELETE_WINDOW").get_reply()?.atom();
         let atom_utf8_string = xcb::intern_atom(&conn, false, "UTF8_STRING").get_reply()?.atom();
@@ -203,6 +211,7 @@ impl<'a> Window<'a> {
                     | xcb::EVENT_MASK_POINTER_MOTION
                     | xcb::EVENT_MASK_BUTTON_MOTION
                     | xcb::EVENT_MASK_KEY_RELEASE
+                    | xcb::EVENT_MASK_FOCUS_CHANGE
                     | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
             )],
         )
@@ -260,6 +269,28 @@ impl<'a> Window<'a> {
         xcb_util::icccm::set_wm_name(self.conn.conn(), self.window_id, title);
     }
 
+    /// Set the WM_CLASS property.  This should be done before the
+    /// window is mapped for the window manager to take notice of it.
+    pub fn set_class(&self, instance: &str, class: &str) {
+        // The property value is the instance and class names, each
+        // terminated by a NUL byte.
+        let mut value = Vec::with_capacity(instance.len() + class.len() + 2);
+        value.extend_from_slice(instance.as_bytes());
+        value.push(0);
+        value.extend_from_slice(class.as_bytes());
+        value.push(0);
+
+        xcb::change_property(
+            self.conn.conn(),
+            xcb::PROP_MODE_REPLACE as u8,
+            self.window_id,
+            xcb::ATOM_WM_CLASS,
+            xcb::ATOM_STRING,
+            8,
+            &value,
+        );
+    }
+
     /// Display the window
     pub fn show(&self) {
         xcb::map_window(self.conn.conn(), self.window_id);
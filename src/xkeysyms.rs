@@ -78,6 +78,20 @@ pub const XK_F9: xcb_keysym_t = 0xffc6;
 pub const XK_F10: xcb_keysym_t = 0xffc7;
 pub const XK_F11: xcb_keysym_t = 0xffc8;
 pub const XK_F12: xcb_keysym_t = 0xffc9;
+pub const XK_F13: xcb_keysym_t = 0xffca;
+pub const XK_F14: xcb_keysym_t = 0xffcb;
+pub const XK_F15: xcb_keysym_t = 0xffcc;
+pub const XK_F16: xcb_keysym_t = 0xffcd;
+pub const XK_F17: xcb_keysym_t = 0xffce;
+pub const XK_F18: xcb_keysym_t = 0xffcf;
+pub const XK_F19: xcb_keysym_t = 0xffd0;
+pub const XK_F20: xcb_keysym_t = 0xffd1;
+pub const XK_F21: xcb_keysym_t = 0xffd2;
+pub const XK_F22: xcb_keysym_t = 0xffd3;
+pub const XK_F23: xcb_keysym_t = 0xffd4;
+pub const XK_F24: xcb_keysym_t = 0xffd5;
+
+pub const XK_ISO_Left_Tab: xcb_keysym_t = 0xfe20;
 
 pub const XK_Shift_L: xcb_keysym_t = 0xffe1;
 pub const XK_Shift_R: xcb_keysym_t = 0xffe2;
@@ -202,28 +216,49 @@ pub fn xcb_keysym_to_keycode(k: xcb_keysym_t) -> KeyCode {
             // This range overlaps with ascii
             KeyCode::Char(k as u8 as char)
         }
-        XK_BackSpace | XK_Tab | XK_Linefeed | XK_Return | XK_Escape => {
-            KeyCode::Char((k & 0xff) as u8 as char)
-        }
+        XK_Linefeed => KeyCode::Char('\n'),
+        XK_BackSpace => KeyCode::Backspace,
+        XK_Tab | XK_ISO_Left_Tab | XK_KP_Tab => KeyCode::Tab,
+        XK_Return => KeyCode::Enter,
+        XK_Escape => KeyCode::Escape,
+        XK_Delete | XK_KP_Delete => KeyCode::Delete,
+        XK_F1...XK_F24 => KeyCode::Function((k - XK_F1 + 1) as u8),
+        XK_KP_F1...XK_KP_F4 => KeyCode::Function((k - XK_KP_F1 + 1) as u8),
+        XK_KP_0...XK_KP_9 => KeyCode::Numpad((k - XK_KP_0) as u8),
+        XK_KP_Space => KeyCode::Char(' '),
+        XK_KP_Decimal => KeyCode::NumpadDecimal,
+        XK_KP_Separator => KeyCode::NumpadSeparator,
+        XK_KP_Add => KeyCode::NumpadAdd,
+        XK_KP_Subtract => KeyCode::NumpadSubtract,
+        XK_KP_Multiply => KeyCode::NumpadMultiply,
+        XK_KP_Divide => KeyCode::NumpadDivide,
+        XK_KP_Equal => KeyCode::NumpadEqual,
+        XK_KP_Enter => KeyCode::NumpadEnter,
         XK_Control_L | XK_Control_R => KeyCode::Control,
         XK_Alt_L | XK_Alt_R => KeyCode::Alt,
         XK_Meta_L | XK_Meta_R => KeyCode::Meta,
         XK_Super_L | XK_Super_R => KeyCode::Super,
         XK_Hyper_L | XK_Hyper_R => KeyCode::Hyper,
         XK_Shift_L | XK_Shift_R => KeyCode::Shift,
-        XK_Home => KeyCode::Home,
-        XK_Left => KeyCode::Left,
-        XK_Up => KeyCode::Up,
-        XK_Right => KeyCode::Right,
-        XK_Down => KeyCode::Down,
-        XK_Page_Up => KeyCode::PageUp,
-        XK_Page_Down => KeyCode::PageDown,
-        XK_End => KeyCode::End,
+        XK_Home | XK_KP_Home => KeyCode::Home,
+        XK_Left | XK_KP_Left => KeyCode::Left,
+        XK_Up | XK_KP_Up => KeyCode::Up,
+        XK_Right | XK_KP_Right => KeyCode::Right,
+        XK_Down | XK_KP_Down => KeyCode::Down,
+        XK_Page_Up | XK_KP_Page_Up => KeyCode::PageUp,
+        XK_Page_Down | XK_KP_Page_Down => KeyCode::PageDown,
+        XK_End | XK_KP_End => KeyCode::End,
         XK_KP_Insert | XK_Insert => KeyCode::Insert,
         _ => KeyCode::Unknown,
     }
 }
 
+/// Whether the keysym is on the numeric keypad, whose keys produce
+/// different keysyms when NumLock is on
+pub fn is_keypad_key(k: xcb_keysym_t) -> bool {
+    XK_KP_Space <= k && k <= XK_KP_Equal
+}
+
 pub fn modifiers_from_state(state: u16) -> KeyModifiers {
     use xcb::xproto::*;
 
@@ -1,5 +1,6 @@
-use crate::config::TextStyle;
-use crate::font::{ftwrap, FontConfiguration, GlyphInfo};
+use crate::cellstyle;
+use crate::config::{Config, TextStyle, DEFAULT_SCROLLBACK_LINES};
+use crate::font::{FontConfiguration, GlyphInfo};
 use crate::pty::MasterPty;
 use crate::xgfx::{self, Connection, Drawable};
 use crate::xkeysyms;
@@ -9,17 +10,19 @@ use glium::texture::SrgbTexture2d;
 use glium::{self, IndexBuffer, Surface, VertexBuffer};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
 use std::io::{Read, Write};
 use std::mem;
 use std::ops::{Deref, Range};
+use std::path::PathBuf;
 use std::process::Child;
 use std::process::Command;
 use std::rc::Rc;
-use std::slice;
+use std::time::{Duration, Instant};
 use term::hyperlink::Hyperlink;
 use term::{
-    self, CursorPosition, KeyCode, KeyModifiers, Line, MouseButton, MouseEvent, MouseEventKind,
-    TerminalHost, Underline,
+    self, Cell, CellAttributes, CursorPosition, ImageData, ImageSlice, KeyCode, KeyModifiers, Line,
+    MouseButton, MouseEvent, MouseEventKind, Pattern, SearchHighlight, TerminalHost,
 };
 use xcb;
 use xcb_util;
@@ -65,6 +68,10 @@ const V_TOP_RIGHT: usize = 1;
 const V_BOT_LEFT: usize = 2;
 const V_BOT_RIGHT: usize = 3;
 
+/// The image atlas is rebuilt once it grows beyond this many pixels,
+/// unless the images that are displayed need more than half of that
+const MAX_IMAGE_ATLAS_PIXELS: usize = 4096 * 4096;
+
 #[derive(Copy, Clone, Debug, Default)]
 struct Vertex {
     // pre-computed by compute_vertices and changed only on resize
@@ -122,7 +129,7 @@ out float o_underline;
 // Offset from the RHS texture coordinate to the LHS.
 // This is an underestimation to avoid the shader interpolating
 // the underline gylph into its neighbor.
-const float underline_offset = (1.0 / 5.0);
+const float underline_offset = (1.0 / 6.0);
 
 void main() {
     o_fg_color = fg_color;
@@ -159,7 +166,7 @@ void main() {
 "#;
 
 /// How many columns the underline texture has
-const U_COLS: f32 = 5.0;
+const U_COLS: f32 = 6.0;
 /// The glyph has no underline or strikethrough
 const U_NONE: f32 = 0.0;
 /// The glyph has a single underline.  This value is actually the texture
@@ -173,6 +180,9 @@ const U_STRIKE: f32 = 3.0 / U_COLS;
 const U_STRIKE_ONE: f32 = 4.0 / U_COLS;
 /// Texture coord for the RHS of the strikethrough + double underline glyph
 const U_STRIKE_TWO: f32 = 5.0 / U_COLS;
+/// Texture coord for the RHS of the box drawn around the cursor while
+/// the window doesn't have the focus
+const U_OUTLINE: f32 = 6.0 / U_COLS;
 
 const FRAGMENT_SHADER: &str = r#"
 #version 300 es
@@ -232,6 +242,14 @@ struct Host<'a> {
     pty: MasterPty,
     timestamp: xcb::xproto::Timestamp,
     clipboard: Option<String>,
+    /// The title to restore when the terminal asks for its default
+    default_title: String,
+    /// The title set by the application; empty if it hasn't set one
+    title: String,
+    /// The working directory reported by the application
+    current_dir: Option<String>,
+    /// The window_title template from the config
+    title_format: Option<String>,
 }
 
 pub struct TerminalWindow<'a> {
@@ -245,14 +263,41 @@ pub struct TerminalWindow<'a> {
     descender: isize,
     terminal: term::Terminal,
     process: Child,
+    /// The other instances of miro started from this window, which are
+    /// reaped when they exit
+    spawned_windows: Vec<Child>,
+    /// The configuration file given on the command line, which is also
+    /// used by the windows started from this one
+    config_path: Option<PathBuf>,
     glyph_cache: RefCell<HashMap<GlyphKey, Rc<CachedGlyph>>>,
-    palette: term::color::ColorPalette,
     program: glium::Program,
     glyph_vertex_buffer: RefCell<VertexBuffer<Vertex>>,
     glyph_index_buffer: IndexBuffer<u32>,
     projection: Transform3D,
     atlas: RefCell<Atlas>,
+    /// Holds the images that applications display.  These are kept
+    /// apart from the glyphs, which must all fit in one texture.
+    image_atlas: RefCell<Atlas>,
+    /// The sprites in image_atlas, keyed by the id of their image
+    image_cache: RefCell<HashMap<usize, Rc<Sprite>>>,
     underline_tex: SrgbTexture2d,
+    search: Option<SearchBar>,
+    /// The reason that the configuration couldn't be reloaded, which
+    /// is shown in place of the bottom row until a key is pressed
+    config_error: Option<String>,
+    /// The keys whose presses were handled by the window rather than
+    /// sent to the terminal, so that their releases are not sent either
+    unsent_keys: Vec<xcb::Keycode>,
+}
+
+/// The search bar that is shown in place of the bottom row of the
+/// terminal while searching the scrollback
+struct SearchBar {
+    pattern: String,
+    /// Interpret the pattern as a regular expression
+    regex: bool,
+    /// The reason that the pattern couldn't be compiled
+    error: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -362,7 +407,40 @@ impl<'a> term::TerminalHost for Host<'a> {
     }
 
     fn set_title(&mut self, title: &str) {
-        self.window.set_title(title);
+        self.title = title.to_string();
+        self.update_title();
+    }
+
+    fn set_current_dir(&mut self, cwd: &str) {
+        self.current_dir = Some(cwd.to_string());
+        self.update_title();
+    }
+}
+
+impl<'a> Host<'a> {
+    /// Returns the working directory of the application.  This is the
+    /// one that it reported via OSC 7 if any, otherwise that of the
+    /// foreground process on the pty.
+    fn current_dir(&self) -> Option<PathBuf> {
+        self.current_dir.as_ref().map(PathBuf::from).or_else(|| self.pty.foreground_process_cwd())
+    }
+
+    /// Set the window title from the title_format template
+    fn update_title(&mut self) {
+        let title = if self.title.is_empty() { &self.default_title } else { &self.title };
+        let title = match self.title_format {
+            Some(ref format) => {
+                let mut formatted = format.replace("{title}", title);
+                if formatted.contains("{cwd}") {
+                    let cwd = self.current_dir();
+                    let cwd = cwd.as_ref().map(|cwd| cwd.to_string_lossy()).unwrap_or_default();
+                    formatted = formatted.replace("{cwd}", &cwd);
+                }
+                formatted
+            }
+            None => title.clone(),
+        };
+        self.window.set_title(&title);
     }
 }
 
@@ -371,120 +449,31 @@ impl<'a> TerminalWindow<'a> {
         conn: &Connection,
         width: u16,
         height: u16,
-        terminal: term::Terminal,
+        mut terminal: term::Terminal,
         pty: MasterPty,
         process: Child,
         fonts: FontConfiguration,
-        palette: term::color::ColorPalette,
     ) -> Result<TerminalWindow, Error> {
-        let (cell_height, cell_width, descender) = {
-            // Urgh, this is a bit repeaty, but we need to satisfy the borrow checker
-            let font = fonts.default_font()?;
-            let tuple = font.borrow_mut().get_metrics()?;
-            tuple
-        };
+        let (cell_height, cell_width, descender) = fonts.cell_metrics()?;
+        terminal.set_cell_size(cell_width, cell_height);
 
         let window = xgfx::Window::new(&conn, width, height)?;
-        window.set_title("miro");
-
-        let descender = if descender.is_positive() {
-            ((descender as f64) / 64.0).ceil() as isize
-        } else {
-            ((descender as f64) / 64.0).floor() as isize
-        };
-        debug!("METRICS: h={} w={} d={}", cell_height, cell_width, descender);
-
-        // The descender isn't always reliable.  If it looks implausible then we
-        // cook up something more reasonable.  For example, if the descender pulls
-        // the basline up into the top half of the cell then it is probably bad
-        // so we halve that distance, putting it closer to the bottom quarter instead.
-        let descender = if descender.abs() >= cell_height as isize / 2 {
-            let alt_desc = descender / 2;
-            eprintln!(
-                "descender {} is >=50% the cell height, using {} instead",
-                descender, alt_desc
-            );
-            alt_desc
-        } else {
-            descender
+        let default_title = "miro".to_string();
+        window.set_title(&default_title);
+
+        let host = Host {
+            window,
+            pty,
+            timestamp: 0,
+            clipboard: None,
+            default_title,
+            title: String::new(),
+            current_dir: None,
+            title_format: None,
         };
 
-        let host = Host { window, pty, timestamp: 0, clipboard: None };
-        let cell_height = cell_height.ceil() as usize;
-        let cell_width = cell_width.ceil() as usize;
-
-        // Create the texture atlas for the line decoration layer.
-        // This is a bitmap with columns to accomodate the U_XXX
-        // constants defined above.
-        let underline_tex = {
-            let width = 5 * cell_width;
-            let mut underline_data = Vec::with_capacity(width * cell_height * 4);
-            underline_data.resize(width * cell_height * 4, 0u8);
-
-            let descender_row = (cell_height as isize + descender) as usize;
-            let descender_plus_one = (1 + descender_row).min(cell_height - 1);
-            let descender_plus_two = (2 + descender_row).min(cell_height - 1);
-            let strike_row = descender_row / 2;
-
-            // First, the single underline.
-            // We place this just under the descender position.
-            {
-                let col = 0;
-                let offset = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
-                for i in 0..4 * cell_width {
-                    underline_data[offset + i] = 0xff;
-                }
-            }
-            // Double underline,
-            // We place this at and just below the descender
-            {
-                let col = 1;
-                let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
-                let offset_two = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
-                for i in 0..4 * cell_width {
-                    underline_data[offset_one + i] = 0xff;
-                    underline_data[offset_two + i] = 0xff;
-                }
-            }
-            // Strikethrough
-            {
-                let col = 2;
-                let offset = (width * 4) * strike_row + (col * 4 * cell_width);
-                for i in 0..4 * cell_width {
-                    underline_data[offset + i] = 0xff;
-                }
-            }
-            // Strikethrough and single underline
-            {
-                let col = 3;
-                let offset_one = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
-                let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
-                for i in 0..4 * cell_width {
-                    underline_data[offset_one + i] = 0xff;
-                    underline_data[offset_two + i] = 0xff;
-                }
-            }
-            // Strikethrough and double underline
-            {
-                let col = 4;
-                let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
-                let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
-                let offset_three = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
-                for i in 0..4 * cell_width {
-                    underline_data[offset_one + i] = 0xff;
-                    underline_data[offset_two + i] = 0xff;
-                    underline_data[offset_three + i] = 0xff;
-                }
-            }
-
-            glium::texture::SrgbTexture2d::new(
-                &host.window,
-                glium::texture::RawImage2d::from_raw_rgba(
-                    underline_data,
-                    (width as u32, cell_height as u32),
-                ),
-            )?
-        };
+        let underline_tex =
+            Self::compute_underline_texture(&host, cell_width, cell_height, descender)?;
 
         let (glyph_vertex_buffer, glyph_index_buffer) = Self::compute_vertices(
             &host,
@@ -498,10 +487,13 @@ impl<'a> TerminalWindow<'a> {
             glium::Program::from_source(&host.window, VERTEX_SHADER, FRAGMENT_SHADER, None)?;
 
         let atlas = RefCell::new(Atlas::new(&host.window)?);
+        let image_atlas = RefCell::new(Atlas::new(&host.window)?);
 
         Ok(TerminalWindow {
             host,
             atlas,
+            image_atlas,
+            image_cache: RefCell::new(HashMap::new()),
             program,
             glyph_vertex_buffer: RefCell::new(glyph_vertex_buffer),
             glyph_index_buffer,
@@ -514,10 +506,14 @@ impl<'a> TerminalWindow<'a> {
             descender,
             terminal,
             process,
+            spawned_windows: Vec::new(),
+            config_path: None,
             glyph_cache: RefCell::new(HashMap::new()),
-            palette,
             projection: Self::compute_projection(width as f32, height as f32),
             underline_tex,
+            search: None,
+            config_error: None,
+            unsent_keys: Vec::new(),
         })
     }
 
@@ -525,6 +521,149 @@ impl<'a> TerminalWindow<'a> {
         self.host.window.show();
     }
 
+    /// Set the class used by the window manager to identify the window
+    pub fn set_class(&self, class: &str) {
+        self.host.window.set_class("miro", class);
+    }
+
+    /// Set the title of the window.  This also becomes the title
+    /// that is restored when the terminal is reset.
+    pub fn set_title(&mut self, title: &str) {
+        self.host.default_title = title.to_string();
+        self.host.update_title();
+    }
+
+    /// Set the configuration file that new windows are to use
+    pub fn set_config_path(&mut self, path: Option<PathBuf>) {
+        self.config_path = path;
+    }
+
+    /// Set the template from the window_title config
+    pub fn set_title_format(&mut self, format: Option<String>) {
+        self.host.title_format = format;
+        self.host.update_title();
+    }
+
+    /// Start another instance of miro in the working directory of
+    /// the application running in this one
+    fn spawn_window(&mut self) {
+        let exe = match env::current_exe() {
+            Ok(exe) => exe,
+            Err(err) => {
+                eprintln!("unable to determine the path to miro: {:?}", err);
+                return;
+            }
+        };
+        let mut cmd = Command::new(exe);
+        if let Some(ref path) = self.config_path {
+            cmd.arg("--config").arg(path);
+        }
+        // The reported directory may be on another host
+        if let Some(cwd) = self.host.current_dir().filter(|cwd| cwd.is_dir()) {
+            cmd.arg("--cwd").arg(cwd);
+        }
+        match cmd.spawn() {
+            Ok(child) => self.spawned_windows.push(child),
+            Err(err) => eprintln!("failed to spawn a new window: {:?}", err),
+        }
+    }
+
+    /// Create the texture atlas for the line decoration layer.
+    /// This is a bitmap with columns to accomodate the U_XXX
+    /// constants defined above.
+    fn compute_underline_texture(
+        host: &Host,
+        cell_width: usize,
+        cell_height: usize,
+        descender: isize,
+    ) -> Result<SrgbTexture2d, Error> {
+        let width = 6 * cell_width;
+        let mut underline_data = Vec::with_capacity(width * cell_height * 4);
+        underline_data.resize(width * cell_height * 4, 0u8);
+
+        let descender_row = (cell_height as isize + descender) as usize;
+        let descender_plus_one = (1 + descender_row).min(cell_height - 1);
+        let descender_plus_two = (2 + descender_row).min(cell_height - 1);
+        let strike_row = descender_row / 2;
+
+        // First, the single underline.
+        // We place this just under the descender position.
+        {
+            let col = 0;
+            let offset = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset + i] = 0xff;
+            }
+        }
+        // Double underline,
+        // We place this at and just below the descender
+        {
+            let col = 1;
+            let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
+            let offset_two = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset_one + i] = 0xff;
+                underline_data[offset_two + i] = 0xff;
+            }
+        }
+        // Strikethrough
+        {
+            let col = 2;
+            let offset = (width * 4) * strike_row + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset + i] = 0xff;
+            }
+        }
+        // Strikethrough and single underline
+        {
+            let col = 3;
+            let offset_one = ((width * 4) * descender_plus_one) + (col * 4 * cell_width);
+            let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset_one + i] = 0xff;
+                underline_data[offset_two + i] = 0xff;
+            }
+        }
+        // Strikethrough and double underline
+        {
+            let col = 4;
+            let offset_one = ((width * 4) * (descender_row)) + (col * 4 * cell_width);
+            let offset_two = ((width * 4) * strike_row) + (col * 4 * cell_width);
+            let offset_three = ((width * 4) * (descender_plus_two)) + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset_one + i] = 0xff;
+                underline_data[offset_two + i] = 0xff;
+                underline_data[offset_three + i] = 0xff;
+            }
+        }
+        // A box around the edge of the cell, for the hollow cursor
+        {
+            let col = 5;
+            let offset_top = col * 4 * cell_width;
+            let offset_bottom = ((width * 4) * (cell_height - 1)) + (col * 4 * cell_width);
+            for i in 0..4 * cell_width {
+                underline_data[offset_top + i] = 0xff;
+                underline_data[offset_bottom + i] = 0xff;
+            }
+            for row in 0..cell_height {
+                let offset_left = ((width * 4) * row) + (col * 4 * cell_width);
+                let offset_right = offset_left + 4 * (cell_width - 1);
+                for i in 0..4 {
+                    underline_data[offset_left + i] = 0xff;
+                    underline_data[offset_right + i] = 0xff;
+                }
+            }
+        }
+
+        Ok(glium::texture::SrgbTexture2d::new(
+            &host.window,
+            glium::texture::RawImage2d::from_raw_rgba(
+                underline_data,
+                (width as u32, cell_height as u32),
+            ),
+        )?)
+    }
+
     /// Compute a vertex buffer to hold the quads that comprise the visible
     /// portion of the screen.   We recreate this when the screen is resized.
     /// The idea is that we want to minimize and heavy lifting and computation
@@ -599,30 +738,7 @@ impl<'a> TerminalWindow<'a> {
     pub fn resize_surfaces(&mut self, width: u16, height: u16) -> Result<bool, Error> {
         if width != self.width || height != self.height {
             debug!("resize {},{}", width, height);
-
-            self.width = width;
-            self.height = height;
-            self.projection = Self::compute_projection(width as f32, height as f32);
-
-            let (glyph_vertex_buffer, glyph_index_buffer) = Self::compute_vertices(
-                &self.host,
-                self.cell_width as f32,
-                self.cell_height as f32,
-                width as f32,
-                height as f32,
-            )?;
-            self.glyph_vertex_buffer = RefCell::new(glyph_vertex_buffer);
-            self.glyph_index_buffer = glyph_index_buffer;
-
-            // The +1 in here is to handle an irritating case.
-            // When we get N rows with a gap of cell_height - 1 left at
-            // the bottom, we can usually squeeze that extra row in there,
-            // so optimistically pretend that we have that extra pixel!
-            let rows = ((height as usize + 1) / self.cell_height) as u16;
-            let cols = ((width as usize + 1) / self.cell_width) as u16;
-            self.host.pty.resize(rows, cols, width, height)?;
-            self.terminal.resize(rows as usize, cols as usize);
-
+            self.apply_size(width, height)?;
             Ok(true)
         } else {
             debug!("ignoring extra resize");
@@ -630,6 +746,88 @@ impl<'a> TerminalWindow<'a> {
         }
     }
 
+    /// Recompute the geometry for the specified window size, and propagate
+    /// the resultant rows and columns to the pty and the terminal.
+    fn apply_size(&mut self, width: u16, height: u16) -> Result<(), Error> {
+        self.width = width;
+        self.height = height;
+        self.projection = Self::compute_projection(width as f32, height as f32);
+
+        let (glyph_vertex_buffer, glyph_index_buffer) = Self::compute_vertices(
+            &self.host,
+            self.cell_width as f32,
+            self.cell_height as f32,
+            width as f32,
+            height as f32,
+        )?;
+        self.glyph_vertex_buffer = RefCell::new(glyph_vertex_buffer);
+        self.glyph_index_buffer = glyph_index_buffer;
+
+        // The +1 in here is to handle an irritating case.
+        // When we get N rows with a gap of cell_height - 1 left at
+        // the bottom, we can usually squeeze that extra row in there,
+        // so optimistically pretend that we have that extra pixel!
+        let rows = ((height as usize + 1) / self.cell_height) as u16;
+        let cols = ((width as usize + 1) / self.cell_width) as u16;
+        self.host.pty.resize(rows, cols, width, height)?;
+        self.terminal.resize(rows as usize, cols as usize);
+
+        Ok(())
+    }
+
+    /// Apply a freshly loaded configuration to the running window.
+    /// The fonts may have changed, so the glyph cache and atlas are
+    /// flushed and the cell metrics recomputed, which may in turn
+    /// change the number of rows and columns in the pty.
+    /// Everything that can fail is built before any state is changed,
+    /// so that an error leaves the window running with the old config.
+    pub fn reconfigure(&mut self, config: Config) -> Result<(), Error> {
+        let fonts = FontConfiguration::new(config.clone());
+        let (cell_height, cell_width, descender) = fonts.cell_metrics()?;
+        let underline_tex =
+            Self::compute_underline_texture(&self.host, cell_width, cell_height, descender)?;
+        let atlas = Atlas::new(&self.host.window)?;
+        let image_atlas = Atlas::new(&self.host.window)?;
+
+        self.config_error = None;
+        self.underline_tex = underline_tex;
+        self.fonts = fonts;
+        self.cell_height = cell_height;
+        self.cell_width = cell_width;
+        self.descender = descender;
+        self.glyph_cache.borrow_mut().clear();
+        self.atlas = RefCell::new(atlas);
+        self.image_cache.borrow_mut().clear();
+        self.image_atlas = RefCell::new(image_atlas);
+        self.terminal.set_cell_size(cell_width, cell_height);
+
+        self.terminal.set_default_palette(
+            config.colors.map(|p| p.into()).unwrap_or_else(term::color::ColorPalette::default),
+        );
+        self.terminal
+            .set_scrollback_size(config.scrollback_lines.unwrap_or(DEFAULT_SCROLLBACK_LINES));
+        self.terminal.set_clipboard_access(config.clipboard_access);
+        self.terminal
+            .set_max_image_size(config.max_image_size.unwrap_or(term::DEFAULT_MAX_IMAGE_SIZE));
+        self.set_title_format(config.window_title.clone());
+
+        let (width, height) = (self.width, self.height);
+        self.apply_size(width, height)?;
+        self.terminal.make_all_lines_dirty();
+
+        Ok(())
+    }
+
+    /// Report a problem with the configuration in place of the bottom
+    /// row, so that it is visible without having to look for the
+    /// output of miro itself.  The terminal contents are left alone.
+    pub fn show_config_error(&mut self, err: &Error) {
+        eprintln!("{}", err);
+        let message = err.to_string().lines().collect::<Vec<_>>().join("  ");
+        self.config_error = Some(message);
+        self.terminal.make_all_lines_dirty();
+    }
+
     pub fn expose(&mut self, _x: u16, _y: u16, _width: u16, _height: u16) -> Result<(), Error> {
         self.paint()
     }
@@ -653,189 +851,152 @@ impl<'a> TerminalWindow<'a> {
 
     /// Perform the load and render of a glyph
     fn load_glyph(&self, info: &GlyphInfo, style: &TextStyle) -> Result<Rc<CachedGlyph>, Error> {
-        let (has_color, ft_glyph, cell_width, cell_height) = {
-            let font = self.fonts.cached_font(style)?;
-            let mut font = font.borrow_mut();
-            let (height, width, _) = font.get_metrics()?;
-            let has_color = font.has_color(info.font_idx)?;
-            // This clone is conceptually unsafe, but ok in practice as we are
-            // single threaded and don't load any other glyphs in the body of
-            // this load_glyph() function.
-            let ft_glyph = font.load_glyph(info.font_idx, info.glyph_pos)?.clone();
-            (has_color, ft_glyph, width, height)
-        };
+        let glyph = self.fonts.rasterize_glyph(info, style)?;
 
-        let scale = if (info.x_advance / info.num_cells as f64).floor() > cell_width {
-            info.num_cells as f64 * (cell_width / info.x_advance)
-        } else if ft_glyph.bitmap.rows as f64 > cell_height {
-            cell_height / ft_glyph.bitmap.rows as f64
-        } else {
-            1.0f64
-        };
-        let (x_offset, y_offset) = if scale != 1.0 {
-            (info.x_offset * scale, info.y_offset * scale)
-        } else {
-            (info.x_offset, info.y_offset)
-        };
-
-        let glyph = if ft_glyph.bitmap.width == 0 || ft_glyph.bitmap.rows == 0 {
-            // a whitespace glyph
-            CachedGlyph {
-                texture: None,
-                has_color,
-                x_offset: x_offset as isize,
-                y_offset: y_offset as isize,
-                bearing_x: 0,
-                bearing_y: 0,
-                scale: scale as f32,
+        let texture = match glyph.bitmap {
+            Some(bitmap) => {
+                let raw_im = glium::texture::RawImage2d::from_raw_rgba(
+                    bitmap.data,
+                    (bitmap.width as u32, bitmap.height as u32),
+                );
+                Some(self.atlas.borrow_mut().allocate(
+                    &self.host.window,
+                    raw_im.width,
+                    raw_im.height,
+                    raw_im,
+                )?)
             }
-        } else {
-            let mode: ftwrap::FT_Pixel_Mode =
-                unsafe { mem::transmute(ft_glyph.bitmap.pixel_mode as u32) };
-
-            // pitch is the number of bytes per source row
-            let pitch = ft_glyph.bitmap.pitch.abs() as usize;
-            let data = unsafe {
-                slice::from_raw_parts_mut(
-                    ft_glyph.bitmap.buffer,
-                    ft_glyph.bitmap.rows as usize * pitch,
-                )
-            };
+            None => None,
+        };
 
-            let raw_im = match mode {
-                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_LCD => {
-                    let width = ft_glyph.bitmap.width as usize / 3;
-                    let height = ft_glyph.bitmap.rows as usize;
-                    let size = (width * height * 4) as usize;
-                    let mut rgba = Vec::with_capacity(size);
-                    rgba.resize(size, 0u8);
-                    for y in 0..height {
-                        let src_offset = y * pitch as usize;
-                        let dest_offset = y * width * 4;
-                        for x in 0..width {
-                            let blue = data[src_offset + (x * 3) + 0];
-                            let green = data[src_offset + (x * 3) + 1];
-                            let red = data[src_offset + (x * 3) + 2];
-                            let alpha = red | green | blue;
-                            rgba[dest_offset + (x * 4) + 0] = red;
-                            rgba[dest_offset + (x * 4) + 1] = green;
-                            rgba[dest_offset + (x * 4) + 2] = blue;
-                            rgba[dest_offset + (x * 4) + 3] = alpha;
-                        }
-                    }
+        Ok(Rc::new(CachedGlyph {
+            texture,
+            has_color: glyph.has_color,
+            x_offset: glyph.x_offset,
+            y_offset: glyph.y_offset,
+            bearing_x: glyph.bearing_x,
+            bearing_y: glyph.bearing_y,
+            scale: glyph.scale as f32,
+        }))
+    }
 
-                    glium::texture::RawImage2d::from_raw_rgba(rgba, (width as u32, height as u32))
-                }
-                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_BGRA => {
-                    let width = ft_glyph.bitmap.width as usize;
-                    let height = ft_glyph.bitmap.rows as usize;
-                    let size = (width * height * 4) as usize;
-                    let mut rgba = Vec::with_capacity(size);
-                    rgba.resize(size, 0u8);
-                    for y in 0..height {
-                        let src_offset = y * pitch as usize;
-                        let dest_offset = y * width * 4;
-                        for x in 0..width {
-                            let blue = data[src_offset + (x * 4) + 0];
-                            let green = data[src_offset + (x * 4) + 1];
-                            let red = data[src_offset + (x * 4) + 2];
-                            let alpha = data[src_offset + (x * 4) + 3];
-
-                            rgba[dest_offset + (x * 4) + 0] = red;
-                            rgba[dest_offset + (x * 4) + 1] = green;
-                            rgba[dest_offset + (x * 4) + 2] = blue;
-                            rgba[dest_offset + (x * 4) + 3] = alpha;
-                        }
-                    }
+    /// Resolve the sprite that holds an image, adding the image to
+    /// the image atlas if it isn't already there.
+    fn cached_image(&self, image: &ImageData) -> Result<Rc<Sprite>, Error> {
+        if let Some(sprite) = self.image_cache.borrow().get(&image.id()) {
+            return Ok(Rc::clone(sprite));
+        }
 
-                    glium::texture::RawImage2d::from_raw_rgba(rgba, (width as u32, height as u32))
-                }
-                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_GRAY => {
-                    let width = ft_glyph.bitmap.width as usize;
-                    let height = ft_glyph.bitmap.rows as usize;
-                    let size = (width * height * 4) as usize;
-                    let mut rgba = Vec::with_capacity(size);
-                    rgba.resize(size, 0u8);
-                    for y in 0..height {
-                        let src_offset = y * pitch;
-                        let dest_offset = y * width * 4;
-                        for x in 0..width {
-                            let gray = data[src_offset + x];
-
-                            rgba[dest_offset + (x * 4) + 0] = gray;
-                            rgba[dest_offset + (x * 4) + 1] = gray;
-                            rgba[dest_offset + (x * 4) + 2] = gray;
-                            rgba[dest_offset + (x * 4) + 3] = gray;
-                        }
-                    }
-                    glium::texture::RawImage2d::from_raw_rgba(rgba, (width as u32, height as u32))
-                }
-                ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_MONO => {
-                    let width = ft_glyph.bitmap.width as usize;
-                    let height = ft_glyph.bitmap.rows as usize;
-                    let size = (width * height * 4) as usize;
-                    let mut rgba = Vec::with_capacity(size);
-                    rgba.resize(size, 0u8);
-                    for y in 0..height {
-                        let src_offset = y * pitch;
-                        let dest_offset = y * width * 4;
-                        let mut x = 0;
-                        for i in 0..pitch {
-                            if x >= width {
-                                break;
-                            }
-                            let mut b = data[src_offset + i];
-                            for _ in 0..8 {
-                                if x >= width {
-                                    break;
-                                }
-                                if b & 0x80 == 0x80 {
-                                    for j in 0..4 {
-                                        rgba[dest_offset + (x * 4) + j] = 0xff;
-                                    }
-                                }
-                                b = b << 1;
-                                x += 1;
-                            }
-                        }
-                    }
-                    glium::texture::RawImage2d::from_raw_rgba(rgba, (width as u32, height as u32))
-                }
-                mode @ _ => bail!("unhandled pixel mode: {:?}", mode),
-            };
+        let raw_im = glium::texture::RawImage2d::from_raw_rgba(
+            image.data().to_vec(),
+            (image.width() as u32, image.height() as u32),
+        );
+        let sprite = Rc::new(self.image_atlas.borrow_mut().allocate(
+            &self.host.window,
+            raw_im.width,
+            raw_im.height,
+            raw_im,
+        )?);
+        self.image_cache.borrow_mut().insert(image.id(), Rc::clone(&sprite));
+        Ok(sprite)
+    }
 
-            let tex = self.atlas.borrow_mut().allocate(
-                &self.host.window,
-                raw_im.width,
-                raw_im.height,
-                raw_im,
-            )?;
+    /// Forget the cached images that are no longer displayed.
+    /// Space in the atlas can't be reclaimed, so once it has grown
+    /// too large it is replaced, and the displayed images are added
+    /// to the new atlas as they are drawn.
+    fn prune_image_cache(&self, images: &[(usize, &ImageSlice)]) -> Result<(), Error> {
+        let mut cache = self.image_cache.borrow_mut();
+        cache.retain(|&id, _| images.iter().any(|&(_, slice)| slice.image.id() == id));
+        let displayed: usize = cache
+            .values()
+            .map(|sprite| sprite.coords.width as usize * sprite.coords.height as usize)
+            .sum();
+        if self.image_atlas.borrow().pixels() > MAX_IMAGE_ATLAS_PIXELS.max(displayed * 2) {
+            cache.clear();
+            *self.image_atlas.borrow_mut() = Atlas::new(&self.host.window)?;
+        }
+        Ok(())
+    }
 
-            let bearing_x = (ft_glyph.bitmap_left as f64 * scale) as isize;
-            let bearing_y = (ft_glyph.bitmap_top as f64 * scale) as isize;
-
-            CachedGlyph {
-                texture: Some(tex),
-                has_color,
-                x_offset: x_offset as isize,
-                y_offset: y_offset as isize,
-                bearing_x,
-                bearing_y,
-                scale: scale as f32,
+    /// Draw images as textured quads, in order of their z-index.
+    /// Unlike the cells, these are not kept in a persistent vertex
+    /// buffer because they can be any size and can overlap.
+    fn render_images(
+        &self,
+        target: &mut glium::Frame,
+        mut images: Vec<(usize, &ImageSlice)>,
+    ) -> Result<(), Error> {
+        // The sort is stable, so images with the same z-index are
+        // drawn in the order in which they were placed
+        images.sort_by_key(|&(_, slice)| slice.z_index);
+
+        // Consecutive quads whose images are in the same texture are
+        // drawn together, which keeps them in order
+        let mut batches: Vec<(Rc<SrgbTexture2d>, Vec<Vertex>, Vec<u32>)> = Vec::new();
+        for (row, slice) in images {
+            let sprite = self.cached_image(&slice.image)?;
+
+            let left = (self.width as f32 / -2.0) + (slice.x * self.cell_width) as f32;
+            let top = (self.height as f32 / -2.0) + (row * self.cell_height) as f32;
+            let right = left + slice.image.width() as f32;
+            let bottom = top + slice.height as f32;
+
+            let tex_width = sprite.texture.width() as f32;
+            let tex_height = sprite.texture.height() as f32;
+            let tex_left = sprite.coords.left as f32 / tex_width;
+            let tex_right = (sprite.coords.left + sprite.coords.width) as f32 / tex_width;
+            let tex_top = (sprite.coords.bottom as usize + slice.top) as f32 / tex_height;
+            let tex_bottom =
+                (sprite.coords.bottom as usize + slice.top + slice.height) as f32 / tex_height;
+
+            if batches.last().map_or(true, |b| !Rc::ptr_eq(&b.0, &sprite.texture)) {
+                batches.push((Rc::clone(&sprite.texture), Vec::new(), Vec::new()));
             }
-        };
+            let (_, verts, indices) = batches.last_mut().unwrap();
+
+            let idx = verts.len() as u32;
+            for &(x, y, tex) in &[
+                (left, top, (tex_left, tex_top)),
+                (right, top, (tex_right, tex_top)),
+                (left, bottom, (tex_left, tex_bottom)),
+                (right, bottom, (tex_right, tex_bottom)),
+            ] {
+                verts.push(Vertex {
+                    position: Point::new(x, y),
+                    tex,
+                    // Draw the texture as-is rather than tinting it
+                    has_color: 1.0,
+                    ..Default::default()
+                });
+            }
+            indices.extend_from_slice(&[idx, idx + 1, idx + 2, idx + 1, idx + 2, idx + 3]);
+        }
 
-        Ok(Rc::new(glyph))
-    }
+        for (texture, verts, indices) in batches {
+            target.draw(
+                &VertexBuffer::new(&self.host.window, &verts)?,
+                &IndexBuffer::new(
+                    &self.host.window,
+                    glium::index::PrimitiveType::TrianglesList,
+                    &indices,
+                )?,
+                &self.program,
+                &uniform! {
+                    projection: self.projection.to_column_arrays(),
+                    glyph_tex: &*texture,
+                    bg_fill: false,
+                    underlining: false,
+                },
+                &glium::DrawParameters {
+                    blend: glium::Blend::alpha_blending(),
+                    dithering: false,
+                    ..Default::default()
+                },
+            )?;
+        }
 
-    /// A little helper for shaping text.
-    /// This is needed to dance around interior mutability concerns,
-    /// as the font caches things.
-    /// TODO: consider pushing this down into the Font impl itself.
-    fn shape_text(&self, s: &str, style: &TextStyle) -> Result<Vec<GlyphInfo>, Error> {
-        let font = self.fonts.cached_font(style)?;
-        let mut font = font.borrow_mut();
-        font.shape(0, s)
+        Ok(())
     }
 
     /// "Render" a line of the terminal screen into the vertex buffer.
@@ -847,6 +1008,7 @@ impl<'a> TerminalWindow<'a> {
         line_idx: usize,
         line: &Line,
         selection: Range<usize>,
+        highlights: &[SearchHighlight],
         cursor: &CursorPosition,
     ) -> Result<(), Error> {
         let num_cols = self.terminal.screen().physical_cols;
@@ -860,6 +1022,8 @@ impl<'a> TerminalWindow<'a> {
         };
 
         let current_highlight = self.terminal.current_highlight();
+        let focused = self.terminal.has_focus();
+        let palette = self.terminal.palette();
         let cell_width = self.cell_width as f32;
         let cell_height = self.cell_height as f32;
 
@@ -873,70 +1037,31 @@ impl<'a> TerminalWindow<'a> {
                 _ => false,
             };
             let style = self.fonts.match_style(attrs);
-
-            let (fg_color, bg_color) = {
-                let mut fg_color = &attrs.foreground;
-                let mut bg_color = &attrs.background;
-
-                if attrs.reverse() {
-                    mem::swap(&mut fg_color, &mut bg_color);
-                }
-
-                (fg_color, bg_color)
+            let colors = cellstyle::attribute_colors(attrs, style.foreground, palette);
+            let (underline, strikethrough) = cellstyle::decorations(attrs, is_highlited_hyperlink);
+
+            // Figure out what we're going to draw for the underline.
+            // If the current cell is part of the current URL highlight
+            // then we want to show the underline.
+            let underline: f32 = match (strikethrough, underline) {
+                (false, 0) => U_NONE,
+                (false, 1) => U_ONE,
+                (false, _) => U_TWO,
+                (true, 0) => U_STRIKE,
+                (true, 1) => U_STRIKE_ONE,
+                (true, _) => U_STRIKE_TWO,
             };
 
-            let bg_color = self.palette.resolve(bg_color).to_linear_tuple_rgba();
-
             // Shape the printable text from this cluster
-            let glyph_info = self.shape_text(&cluster.text, &style)?;
+            let glyph_info = self.fonts.shape_text(&cluster.text, &style)?;
             for info in glyph_info.iter() {
                 let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
                 let glyph = self.cached_glyph(info, &style)?;
 
-                let glyph_color = match fg_color {
-                    &term::color::ColorAttribute::Foreground => {
-                        if let Some(fg) = style.foreground {
-                            fg
-                        } else {
-                            self.palette.resolve(fg_color)
-                        }
-                    }
-                    &term::color::ColorAttribute::PaletteIndex(idx) if idx < 8 => {
-                        // For compatibility purposes, switch to a brighter version
-                        // of one of the standard ANSI colors when Bold is enabled.
-                        // This lifts black to dark grey.
-                        let idx =
-                            if attrs.intensity() == term::Intensity::Bold { idx + 8 } else { idx };
-                        self.palette.resolve(&term::color::ColorAttribute::PaletteIndex(idx))
-                    }
-                    _ => self.palette.resolve(fg_color),
-                }
-                .to_linear_tuple_rgba();
-
                 let left: f32 = glyph.x_offset as f32 + glyph.bearing_x as f32;
                 let top = (self.cell_height as f32 + self.descender as f32)
                     - (glyph.y_offset as f32 + glyph.bearing_y as f32);
 
-                // underline and strikethrough
-                // Figure out what we're going to draw for the underline.
-                // If the current cell is part of the current URL highlight
-                // then we want to show the underline.
-                let underline: f32 =
-                    match (is_highlited_hyperlink, attrs.strikethrough(), attrs.underline()) {
-                        (true, false, Underline::None) => U_ONE,
-                        (true, false, Underline::Single) => U_TWO,
-                        (true, false, Underline::Double) => U_ONE,
-                        (true, true, Underline::None) => U_STRIKE_ONE,
-                        (true, true, Underline::Single) => U_STRIKE_TWO,
-                        (true, true, Underline::Double) => U_STRIKE_ONE,
-                        (false, false, Underline::None) => U_NONE,
-                        (false, false, Underline::Single) => U_ONE,
-                        (false, false, Underline::Double) => U_TWO,
-                        (false, true, Underline::None) => U_STRIKE,
-                        (false, true, Underline::Single) => U_STRIKE_ONE,
-                        (false, true, Underline::Double) => U_STRIKE_TWO,
-                    };
-
                 // Iterate each cell that comprises this glyph.  There is usually
                 // a single cell per glyph but combining characters, ligatures
                 // and emoji can be 2 or more cells wide.
@@ -953,22 +1078,15 @@ impl<'a> TerminalWindow<'a> {
 
                     let selected = term::in_range(cell_idx, &selection);
                     let is_cursor = line_idx as i64 == cursor.y && cursor.x == cell_idx;
+                    let highlight = highlights.iter().find(|h| term::in_range(cell_idx, &h.cols));
+
+                    let (glyph_color, bg_color) = cellstyle::cell_colors(
+                        colors, palette, selected, is_cursor, focused, highlight,
+                    );
+                    let glyph_color = glyph_color.to_linear_tuple_rgba();
+                    let bg_color = bg_color.to_linear_tuple_rgba();
 
-                    let (glyph_color, bg_color) = match (selected, is_cursor) {
-                        // Normally, render the cell as configured
-                        (false, false) => (glyph_color, bg_color),
-                        // Cursor cell always renders with background over cursor color
-                        (_, true) => (
-                            self.palette.background.to_linear_tuple_rgba(),
-                            self.palette.cursor.to_linear_tuple_rgba(),
-                        ),
-                        // Selection text colors the background
-                        (true, false) => (
-                            glyph_color,
-                            // TODO: configurable selection color
-                            self.palette.cursor.to_linear_tuple_rgba(),
-                        ),
-                    };
+                    let underline = if is_cursor && !focused { U_OUTLINE } else { underline };
 
                     let vert_idx = cell_idx * VERTICES_PER_CELL;
                     let vert = &mut vertices[vert_idx..vert_idx + VERTICES_PER_CELL];
@@ -1055,7 +1173,7 @@ impl<'a> TerminalWindow<'a> {
         // open a vim split horizontally.  Backgrounding vim would leave
         // the right pane with its prior contents instead of showing the
         // cleared lines from the shell in the main screen.
-        let bg_color = self.palette.background.to_linear_tuple_rgba();
+        let bg_color = palette.background.to_linear_tuple_rgba();
         let vert_idx = (last_cell_idx + 1) * VERTICES_PER_CELL;
         let vert_slice = &mut vertices[vert_idx..];
         for vert in vert_slice.iter_mut() {
@@ -1080,7 +1198,11 @@ impl<'a> TerminalWindow<'a> {
     }
 
     fn do_paint(&mut self, target: &mut glium::Frame) -> Result<(), Error> {
-        let background_color = self.palette.resolve(&term::color::ColorAttribute::Background);
+        // Bring the search highlights up to date with the output that
+        // arrived since the last paint
+        self.terminal.refresh_search();
+
+        let background_color = self.terminal.palette().background;
         let (r, g, b, a) = background_color.to_linear_tuple_rgba();
         target.clear_color(r, g, b, a);
 
@@ -1088,11 +1210,31 @@ impl<'a> TerminalWindow<'a> {
         {
             let dirty_lines = self.terminal.get_dirty_lines();
 
-            for (line_idx, line, selrange) in dirty_lines {
-                self.render_screen_line(line_idx, line, selrange, &cursor)?;
+            for (line_idx, line, selrange, highlights) in dirty_lines {
+                self.render_screen_line(line_idx, line, selrange, &highlights, &cursor)?;
             }
         }
 
+        if self.terminal.has_pending_clipboard_read() {
+            // The prompt is drawn over the bottom row, like the search bar
+            let row = self.terminal.screen().physical_rows - 1;
+            let (line, cursor_x) = self.clipboard_prompt_line();
+            let cursor = CursorPosition { x: cursor_x, y: row as i64 };
+            self.render_screen_line(row, &line, 0..0, &[], &cursor)?;
+        } else if let Some(ref search) = self.search {
+            // The search bar is drawn over the bottom row of the terminal
+            let row = self.terminal.screen().physical_rows - 1;
+            let (line, cursor_x) = self.search_bar_line(search);
+            let cursor = CursorPosition { x: cursor_x, y: row as i64 };
+            self.render_screen_line(row, &line, 0..0, &[], &cursor)?;
+        } else if let Some(ref err) = self.config_error {
+            let row = self.terminal.screen().physical_rows - 1;
+            let line = self.config_error_line(err);
+            // Place the cursor beyond the end of the line to hide it
+            let cursor = CursorPosition { x: line.cells.len(), y: row as i64 };
+            self.render_screen_line(row, &line, 0..0, &[], &cursor)?;
+        }
+
         let tex = self.atlas.borrow().texture();
 
         // Pass 1: Draw backgrounds
@@ -1114,6 +1256,15 @@ impl<'a> TerminalWindow<'a> {
             },
         )?;
 
+        let images = self.terminal.visible_images();
+        self.prune_image_cache(&images)?;
+        let (beneath, above): (Vec<_>, Vec<_>) =
+            images.into_iter().partition(|&(_, slice)| slice.z_index < 0);
+
+        // Images with a negative z-index are drawn over the backgrounds
+        // and beneath the text
+        self.render_images(target, beneath)?;
+
         // Pass 2: Draw glyphs
         target.draw(
             &*self.glyph_vertex_buffer.borrow(),
@@ -1150,11 +1301,29 @@ impl<'a> TerminalWindow<'a> {
             },
         )?;
 
+        // The remaining images are drawn over the text
+        self.render_images(target, above)?;
+
         self.terminal.clean_dirty_lines();
         Ok(())
     }
 
     pub fn test_for_child_exit(&mut self) -> Result<(), Error> {
+        // Reap the windows that we started, so that they don't linger
+        // as zombies.  Signals may be merged, so check all of them.
+        let spawned = mem::replace(&mut self.spawned_windows, Vec::new());
+        self.spawned_windows = spawned
+            .into_iter()
+            .filter_map(|mut child| match child.try_wait() {
+                Ok(None) => Some(child),
+                Ok(Some(_)) => None,
+                Err(err) => {
+                    eprintln!("failed to wait for a spawned window: {}", err);
+                    None
+                }
+            })
+            .collect();
+
         match self.process.try_wait() {
             Ok(Some(status)) => {
                 bail!("child exited: {}", status);
@@ -1177,18 +1346,229 @@ impl<'a> TerminalWindow<'a> {
             Ok(size) => self.terminal.advance_bytes(&buf[0..size], &mut self.host),
             Err(err) => eprintln!("error reading from pty: {:?}", err),
         }
+        if self.terminal.has_pending_clipboard_read() {
+            // Ensure that the prompt gets painted
+            self.terminal.make_all_lines_dirty();
+        }
     }
 
     pub fn need_paint(&self) -> bool {
-        self.terminal.has_dirty_lines()
+        self.terminal.has_dirty_lines() && self.terminal.synchronized_output_deadline().is_none()
+    }
+
+    /// If painting is being deferred while the application updates the
+    /// screen, returns how long to wait before painting anyway.
+    pub fn paint_timeout(&self) -> Option<Duration> {
+        if !self.terminal.has_dirty_lines() {
+            return None;
+        }
+        self.terminal.synchronized_output_deadline().map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::new(0, 0)
+            }
+        })
     }
 
     fn decode_key(&self, event: &xcb::KeyPressEvent) -> (KeyCode, KeyModifiers) {
         let mods = xkeysyms::modifiers(event);
-        let sym = self.conn.lookup_keysym(event, mods.contains(KeyModifiers::SHIFT));
+        let shift = mods.contains(KeyModifiers::SHIFT);
+        let mut sym = self.conn.lookup_keysym(event, shift);
+        // NumLock swaps the columns of the keypad, and is usually Mod2
+        let num_lock = u32::from(event.state()) & xcb::MOD_MASK_2 != 0;
+        if num_lock && xkeysyms::is_keypad_key(sym) {
+            sym = self.conn.lookup_keysym(event, !shift);
+        }
         (xkeysyms::xcb_keysym_to_keycode(sym), mods)
     }
 
+    /// Compose the content of the search bar.  Returns the line and
+    /// the column following the pattern, which is where the cursor goes.
+    fn search_bar_line(&self, search: &SearchBar) -> (Line, usize) {
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+
+        let prefix = if search.regex { "Regex: " } else { "Search: " };
+        let mut line = Line::from_text(&format!("{}{}", prefix, search.pattern), &attrs);
+        let cursor_x = line.cells.len();
+
+        let status = if let Some(ref err) = search.error {
+            format!("  [{}]", err)
+        } else if search.pattern.is_empty() {
+            String::new()
+        } else {
+            let matches = self.terminal.search_matches();
+            match self.terminal.current_search_match() {
+                Some(current) => {
+                    let idx = matches.iter().position(|m| *m == current).unwrap_or(0);
+                    format!("  [{}/{}]", idx + 1, matches.len())
+                }
+                None => "  [no matches]".to_owned(),
+            }
+        };
+        line.cells.extend(Line::from_text(&status, &attrs).cells);
+
+        let num_cols = self.terminal.screen().physical_cols;
+        line.cells.resize(num_cols.max(cursor_x + 1), Cell::new(" ", &attrs));
+        (line, cursor_x)
+    }
+
+    /// The line that reports a problem with the configuration
+    fn config_error_line(&self, err: &str) -> Line {
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+
+        let mut line = Line::from_text(&format!("Error in the configuration: {}", err), &attrs);
+        let num_cols = self.terminal.screen().physical_cols;
+        line.cells.resize(num_cols, Cell::new(" ", &attrs));
+        line
+    }
+
+    /// The prompt that is shown in place of the bottom row when the
+    /// application has asked to read the clipboard and the
+    /// clipboard_access config is set to ask
+    fn clipboard_prompt_line(&self) -> (Line, usize) {
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+
+        let mut line =
+            Line::from_text("Allow the application to read the clipboard? [y/n] ", &attrs);
+        let cursor_x = line.cells.len();
+        let num_cols = self.terminal.screen().physical_cols;
+        line.cells.resize(num_cols.max(cursor_x + 1), Cell::new(" ", &attrs));
+        (line, cursor_x)
+    }
+
+    /// Keys are routed here while the clipboard prompt is shown.
+    /// `y` sends the clipboard to the application; `n` or Escape
+    /// denies the request.
+    fn clipboard_prompt_key_down(&mut self, key: KeyCode) -> Result<(), Error> {
+        let allow = match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => true,
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Escape => false,
+            _ => return Ok(()),
+        };
+        self.terminal.answer_clipboard_read(allow, &mut self.host)?;
+        // Remove the prompt
+        self.terminal.make_all_lines_dirty();
+        Ok(())
+    }
+
+    fn open_search(&mut self) {
+        self.search = Some(SearchBar { pattern: String::new(), regex: false, error: None });
+        self.terminal.make_all_lines_dirty();
+    }
+
+    fn close_search(&mut self) {
+        self.search = None;
+        self.terminal.clear_search();
+    }
+
+    /// Run the search again after the pattern has been edited
+    fn update_search(&mut self) {
+        let search = match self.search.as_mut() {
+            Some(search) => search,
+            None => return,
+        };
+        search.error = None;
+
+        if search.pattern.is_empty() {
+            self.terminal.clear_search();
+            return;
+        }
+
+        let pattern = if search.regex {
+            match Pattern::regex(&search.pattern) {
+                Ok(pattern) => pattern,
+                Err(err) => {
+                    // The regex error spans multiple lines; the last
+                    // of those is the explanation.
+                    let err = err.to_string();
+                    search.error = Some(err.lines().last().unwrap_or("invalid regex").to_owned());
+                    self.terminal.clear_search();
+                    return;
+                }
+            }
+        } else {
+            Pattern::Literal(search.pattern.clone())
+        };
+        self.terminal.search(&pattern);
+    }
+
+    /// Keys are routed here rather than to the terminal while the
+    /// search bar is open.
+    /// Enter or Up moves to the previous (older) match, Shift-Enter or
+    /// Down moves to the next match, CTRL-R toggles regex mode and
+    /// Escape closes the search bar.
+    fn search_key_down(&mut self, key: KeyCode, mods: KeyModifiers) {
+        let ctrl = mods.contains(KeyModifiers::CTRL);
+        let shift = mods.contains(KeyModifiers::SHIFT);
+        match key {
+            KeyCode::Escape => self.close_search(),
+            KeyCode::Enter if shift => {
+                self.terminal.next_search_match();
+            }
+            KeyCode::Enter | KeyCode::Up => {
+                self.terminal.prev_search_match();
+            }
+            KeyCode::Down => {
+                self.terminal.next_search_match();
+            }
+            KeyCode::Char('r') if ctrl => {
+                if let Some(search) = self.search.as_mut() {
+                    search.regex = !search.regex;
+                }
+                self.update_search();
+            }
+            KeyCode::Backspace => {
+                if let Some(search) = self.search.as_mut() {
+                    search.pattern.pop();
+                }
+                self.update_search();
+            }
+            KeyCode::Char(c) if !ctrl && c >= ' ' => {
+                if let Some(search) = self.search.as_mut() {
+                    search.pattern.push(c);
+                }
+                self.update_search();
+            }
+            _ => {}
+        }
+        // Ensure that the search bar gets repainted
+        self.terminal.make_all_lines_dirty();
+    }
+
+    /// Handles the CTRL-SHIFT key bindings.  Returns false if the key
+    /// isn't bound, in which case it should be sent to the terminal.
+    ///
+    /// F opens the search bar, N opens a new window in the same
+    /// directory, Up and Down scroll to the previous and next prompt
+    /// and O selects the output of the last command.
+    fn shortcut_key_down(&mut self, key: KeyCode) -> Result<bool, Error> {
+        match key {
+            KeyCode::Char('F') => self.open_search(),
+            KeyCode::Char('N') => self.spawn_window(),
+            KeyCode::Up => {
+                self.terminal.scroll_to_prev_prompt();
+            }
+            KeyCode::Down => {
+                self.terminal.scroll_to_next_prompt();
+            }
+            KeyCode::Char('O') => {
+                if let Some(output) = self.terminal.select_last_command_output() {
+                    if output.rows.len() > 0 {
+                        let text = self.terminal.get_selection_text();
+                        self.host.set_clipboard(Some(text))?;
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
     fn clear_selection(&mut self) -> Result<(), Error> {
         self.host.set_clipboard(None)?;
         self.terminal.clear_selection();
@@ -1215,13 +1595,39 @@ impl<'a> TerminalWindow<'a> {
                 let key_press: &xcb::KeyPressEvent = unsafe { xcb::cast_event(&event) };
                 self.host.timestamp = key_press.time();
                 let (code, mods) = self.decode_key(key_press);
-                self.terminal.key_down(code, mods, &mut self.host)?;
+                if self.config_error.take().is_some() {
+                    self.terminal.make_all_lines_dirty();
+                }
+                let sent = if self.terminal.has_pending_clipboard_read() {
+                    self.clipboard_prompt_key_down(code)?;
+                    false
+                } else if self.search.is_some() {
+                    self.search_key_down(code, mods);
+                    false
+                } else if mods != KeyModifiers::CTRL | KeyModifiers::SHIFT
+                    || !self.shortcut_key_down(code)?
+                {
+                    self.terminal.key_down(code, mods, &mut self.host)?;
+                    true
+                } else {
+                    false
+                };
+                if !sent && !self.unsent_keys.contains(&key_press.detail()) {
+                    self.unsent_keys.push(key_press.detail());
+                }
             }
             xcb::KEY_RELEASE => {
                 let key_press: &xcb::KeyPressEvent = unsafe { xcb::cast_event(&event) };
                 self.host.timestamp = key_press.time();
-                let (code, mods) = self.decode_key(key_press);
-                self.terminal.key_up(code, mods, &mut self.host)?;
+                match self.unsent_keys.iter().position(|&key| key == key_press.detail()) {
+                    Some(idx) => {
+                        self.unsent_keys.remove(idx);
+                    }
+                    None => {
+                        let (code, mods) = self.decode_key(key_press);
+                        self.terminal.key_up(code, mods, &mut self.host)?;
+                    }
+                }
             }
             xcb::MOTION_NOTIFY => {
                 let motion: &xcb::MotionNotifyEvent = unsafe { xcb::cast_event(&event) };
@@ -1263,6 +1669,14 @@ impl<'a> TerminalWindow<'a> {
 
                 self.mouse_event(event)?;
             }
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                let focus: &xcb::FocusInEvent = unsafe { xcb::cast_event(&event) };
+                // Ignore the events that describe the pointer moving
+                // between windows that don't have the focus
+                if u32::from(focus.detail()) != xcb::NOTIFY_DETAIL_POINTER {
+                    self.terminal.focus_changed(r == xcb::FOCUS_IN, &mut self.host)?;
+                }
+            }
             xcb::CLIENT_MESSAGE => {
                 let msg: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(&event) };
                 println!("CLIENT_MESSAGE {:?}", msg.data().data32());
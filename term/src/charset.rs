@@ -0,0 +1,116 @@
+//! Character set designation and translation.
+//! See https://vt100.net/docs/vt510-rm/chapter4.html#S4.6 for the
+//! model of the G0-G3 sets and how they are invoked.
+
+/// The character sets that can be designated into G0-G3
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CharSet {
+    Ascii,
+    /// The United Kingdom set; the same as ASCII except that
+    /// `#` is replaced by the pound sign.
+    Uk,
+    /// The line drawing characters used by curses when the terminfo
+    /// entry advertises `acsc`.
+    DecSpecialGraphics,
+}
+
+impl CharSet {
+    /// Map the final byte of a designation sequence such as `ESC ( 0`
+    /// to the corresponding character set.
+    pub fn from_designator(byte: u8) -> Option<CharSet> {
+        match byte {
+            b'B' => Some(CharSet::Ascii),
+            b'A' => Some(CharSet::Uk),
+            b'0' => Some(CharSet::DecSpecialGraphics),
+            _ => None,
+        }
+    }
+
+    /// Translate a character printed while this set is in effect
+    pub fn map(self, c: char) -> char {
+        match self {
+            CharSet::Ascii => c,
+            CharSet::Uk => match c {
+                '#' => '£',
+                c => c,
+            },
+            CharSet::DecSpecialGraphics => match c {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                c => c,
+            },
+        }
+    }
+}
+
+/// Tracks the designated G0-G3 character sets along with the
+/// set that is currently invoked into GL.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CharSets {
+    g: [CharSet; 4],
+    /// The index of the set invoked by a locking shift
+    gl: usize,
+    /// The index of the set invoked for the next character only
+    single_shift: Option<usize>,
+}
+
+impl Default for CharSets {
+    fn default() -> CharSets {
+        CharSets { g: [CharSet::Ascii; 4], gl: 0, single_shift: None }
+    }
+}
+
+impl CharSets {
+    /// Designate `charset` as the G`idx` set
+    pub fn designate(&mut self, idx: usize, charset: CharSet) {
+        self.g[idx] = charset;
+    }
+
+    /// Invoke the G`idx` set until the next locking shift.
+    /// SI is a locking shift to G0, SO to G1, LS2 to G2 and LS3 to G3.
+    pub fn locking_shift(&mut self, idx: usize) {
+        self.gl = idx;
+    }
+
+    /// Invoke the G`idx` set for the next printed character (SS2, SS3)
+    pub fn single_shift(&mut self, idx: usize) {
+        self.single_shift = Some(idx);
+    }
+
+    /// Translate a printed character through the invoked set,
+    /// consuming any pending single shift.
+    pub fn translate(&mut self, c: char) -> char {
+        let idx = self.single_shift.take().unwrap_or(self.gl);
+        self.g[idx].map(c)
+    }
+}
@@ -0,0 +1,77 @@
+//! OSC 52 allows applications to set and query the clipboard, which is
+//! how editors running on a remote host over ssh are able to copy text.
+//! See https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+
+use super::*;
+use base64;
+
+/// The largest decoded payload that an application may place on
+/// the clipboard
+pub const MAX_CLIPBOARD_LEN: usize = 1024 * 1024;
+
+/// Controls what applications may do with the clipboard via OSC 52.
+/// Reading is off by default because it allows any program that
+/// can write to the terminal to retrieve whatever was last copied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardAccess {
+    /// OSC 52 is ignored
+    Deny,
+    /// Applications may set the clipboard but not read it
+    AllowWrite,
+    /// Applications may both set and read the clipboard
+    AllowRead,
+    /// Applications may set the clipboard, but the user is asked
+    /// before the contents are sent to the application
+    Ask,
+}
+
+impl Default for ClipboardAccess {
+    fn default() -> ClipboardAccess {
+        ClipboardAccess::AllowWrite
+    }
+}
+
+/// A parsed OSC 52 request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClipboardRequest {
+    /// Replace the clipboard contents; None clears it
+    Set(Option<String>),
+    /// Report the clipboard contents.  Holds the selection parameter
+    /// so that it can be echoed in the reply.
+    Query(String),
+}
+
+impl ClipboardRequest {
+    /// Parse the selection and data parameters of `OSC 52 ; Pc ; Pd`.
+    /// Pc may name the clipboard (c), the primary selection (p), the
+    /// terminal's choice (s) or a cut buffer (0-7); an empty Pc means
+    /// `s0`.  The host has a single clipboard, so these all refer to it.
+    pub fn parse(selection: &[u8], data: &[u8]) -> Result<ClipboardRequest, Error> {
+        let selection = str::from_utf8(selection)?;
+        if !selection.chars().all(|c| "cps01234567".contains(c)) {
+            bail!("invalid selection {:?}", selection);
+        }
+
+        if data == b"?" {
+            return Ok(ClipboardRequest::Query(selection.to_owned()));
+        }
+        if data.is_empty() {
+            return Ok(ClipboardRequest::Set(None));
+        }
+
+        let decoded = base64::decode(data)?;
+        if decoded.len() > MAX_CLIPBOARD_LEN {
+            bail!("{} bytes exceeds the limit of {}", decoded.len(), MAX_CLIPBOARD_LEN);
+        }
+        Ok(ClipboardRequest::Set(Some(String::from_utf8(decoded)?)))
+    }
+}
+
+/// Reply to an OSC 52 query with the base64 encoded clipboard contents.
+/// An empty clipboard is reported if the host doesn't have one.
+pub(crate) fn report_clipboard(selection: &str, host: &mut TerminalHost) -> Result<(), Error> {
+    let clip = host.get_clipboard().unwrap_or_default();
+    write!(host.writer(), "\x1b]52;{};{}\x1b\\", selection, base64::encode(clip.as_bytes()))?;
+    Ok(())
+}
@@ -86,6 +86,54 @@ impl RgbColor {
             None
         }
     }
+
+    /// Parse a color specification as used by the OSC sequences that
+    /// set colors; these are the forms understood by XParseColor:
+    /// `rgb:r/g/b` where each component is 1-4 hex digits, `#rgb`,
+    /// `#rrggbb`, `#rrrgggbbb`, `#rrrrggggbbbb`, or a color name.
+    pub fn from_x11_spec(spec: &str) -> Option<RgbColor> {
+        // Scale a component of 1-4 hex digits to 0-255
+        fn component(s: &str) -> Option<u8> {
+            if s.is_empty() || s.len() > 4 || !s.chars().all(|c| c.is_digit(16)) {
+                return None;
+            }
+            let value = u32::from_str_radix(s, 16).ok()?;
+            let max = (1u32 << (4 * s.len())) - 1;
+            Some((value * 255 / max) as u8)
+        }
+
+        if spec.starts_with("rgb:") {
+            let parts: Vec<&str> = spec[4..].split('/').collect();
+            if parts.len() != 3 {
+                return None;
+            }
+            Some(Self::new(component(parts[0])?, component(parts[1])?, component(parts[2])?))
+        } else if spec.starts_with('#') {
+            let hex = &spec[1..];
+            if hex.len() % 3 != 0 || !hex.is_ascii() {
+                return None;
+            }
+            let digits = hex.len() / 3;
+            Some(Self::new(
+                component(&hex[0..digits])?,
+                component(&hex[digits..2 * digits])?,
+                component(&hex[2 * digits..])?,
+            ))
+        } else {
+            Self::from_named(&spec.to_lowercase())
+        }
+    }
+
+    /// Format the color as `rgb:rrrr/gggg/bbbb`, which is how xterm
+    /// reports colors in response to OSC queries.
+    pub fn to_x11_spec(&self) -> String {
+        format!(
+            "rgb:{:04x}/{:04x}/{:04x}",
+            u16::from(self.red) * 0x101,
+            u16::from(self.green) * 0x101,
+            u16::from(self.blue) * 0x101
+        )
+    }
 }
 
 impl<'de> Deserialize<'de> for RgbColor {
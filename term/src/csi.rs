@@ -17,15 +17,41 @@ pub enum DisplayErase {
     SavedLines,
 }
 
+#[derive(Debug)]
+pub enum TabulationClear {
+    /// Clear the tab stop at the cursor column
+    CurrentColumn,
+    /// Clear all tab stops
+    All,
+}
+
+/// The ANSI (non-private) modes set by SM and reset by RM
+#[derive(Debug)]
+pub enum TerminalMode {
+    /// IRM: printed characters shift the rest of the line to the right
+    Insert,
+    /// LNM: LF, VT and FF also return the cursor to the left margin
+    AutomaticNewline,
+}
+
 #[derive(Debug)]
 pub enum DecPrivateMode {
     ApplicationCursorKeys,
     BrackedPaste,
-    SGRMouse,
+    X10Mouse,
+    NormalMouse,
     ButtonEventMouse,
+    AnyEventMouse,
+    Utf8Mouse,
+    SGRMouse,
+    UrxvtMouse,
     ClearAndEnableAlternateScreen,
     StartBlinkingCursor,
     ShowCursor,
+    OriginMode,
+    LeftAndRightMarginMode,
+    FocusTracking,
+    SynchronizedOutput,
 }
 
 #[derive(Debug)]
@@ -40,14 +66,48 @@ pub enum CSIAction {
     SetReverse(bool),
     SetStrikethrough(bool),
     SetInvisible(bool),
-    SetCursorXY { x: Position, y: Position },
+    SetCursorXY {
+        x: Position,
+        y: Position,
+    },
     EraseInLine(LineErase),
     EraseInDisplay(DisplayErase),
     SetDecPrivateMode(DecPrivateMode, bool),
+    /// DECRQM for a DEC private mode, identified by its number
+    RequestDecPrivateMode(i64),
+    SetMode(TerminalMode, bool),
+    /// DECRQM for an ANSI mode, identified by its number
+    RequestMode(i64),
     DeviceStatusReport,
     ReportCursorPosition,
-    SetScrollingRegion { top: i64, bottom: i64 },
-    RequestDeviceAttributes,
+    SetScrollingRegion {
+        top: i64,
+        bottom: i64,
+    },
+    SetLeftAndRightMargins {
+        left: i64,
+        right: i64,
+    },
+    RequestPrimaryDeviceAttributes,
+    RequestSecondaryDeviceAttributes,
+    RequestTertiaryDeviceAttributes,
+    /// XTVERSION
+    RequestTerminalNameAndVersion,
+    /// Push flags onto the stack of kitty keyboard protocol flags
+    PushKeyboardFlags(i64),
+    /// Pop this many entries from the stack of kitty keyboard flags
+    PopKeyboardFlags(i64),
+    /// Change the current kitty keyboard flags.  Mode 1 sets them,
+    /// 2 adds to them and 3 removes them.
+    SetKeyboardFlags {
+        flags: i64,
+        mode: i64,
+    },
+    RequestKeyboardFlags,
+    /// XTMODKEYS: set xterm's modifyOtherKeys level; 0 disables it
+    SetModifyOtherKeys(i64),
+    /// XTQMODKEYS: report the modifyOtherKeys level
+    RequestModifyOtherKeys,
     DeleteLines(i64),
     InsertLines(i64),
     LinePosition(Position),
@@ -56,6 +116,12 @@ pub enum CSIAction {
     ScrollLines(i64),
     SoftReset,
     EraseCharacter(i64),
+    InsertCharacter(i64),
+    DeleteCharacter(i64),
+    RepeatCharacter(i64),
+    TabForward(i64),
+    TabBackward(i64),
+    ClearTabStop(TabulationClear),
 }
 
 /// Constrol Sequence Initiator (CSI) Parser.
@@ -120,12 +186,21 @@ impl<'a> CSIParser<'a> {
     fn parse_dec_mode(&self, mode: i64) -> Option<DecPrivateMode> {
         match mode {
             1 => Some(DecPrivateMode::ApplicationCursorKeys),
+            6 => Some(DecPrivateMode::OriginMode),
             12 => Some(DecPrivateMode::StartBlinkingCursor),
             25 => Some(DecPrivateMode::ShowCursor),
+            9 => Some(DecPrivateMode::X10Mouse),
+            69 => Some(DecPrivateMode::LeftAndRightMarginMode),
+            1000 => Some(DecPrivateMode::NormalMouse),
             1002 => Some(DecPrivateMode::ButtonEventMouse),
+            1003 => Some(DecPrivateMode::AnyEventMouse),
+            1004 => Some(DecPrivateMode::FocusTracking),
+            1005 => Some(DecPrivateMode::Utf8Mouse),
             1006 => Some(DecPrivateMode::SGRMouse),
+            1015 => Some(DecPrivateMode::UrxvtMouse),
             1049 => Some(DecPrivateMode::ClearAndEnableAlternateScreen),
             2004 => Some(DecPrivateMode::BrackedPaste),
+            2026 => Some(DecPrivateMode::SynchronizedOutput),
             _ => {
                 println!("unknown or unhandled DECSET mode: {}", mode);
                 None
@@ -161,6 +236,31 @@ impl<'a> CSIParser<'a> {
         }
     }
 
+    fn parse_mode(&self, mode: i64) -> Option<TerminalMode> {
+        match mode {
+            4 => Some(TerminalMode::Insert),
+            20 => Some(TerminalMode::AutomaticNewline),
+            _ => {
+                println!("unknown or unhandled SM mode: {}", mode);
+                None
+            }
+        }
+    }
+
+    /// Set Mode (SM) when enable is true, otherwise Reset Mode (RM)
+    fn set_mode(&mut self, params: &'a [i64], enable: bool) -> Option<CSIAction> {
+        match params {
+            &[idx, ..] => {
+                self.advance_by(1, params);
+                self.parse_mode(idx).map(|m| CSIAction::SetMode(m, enable))
+            }
+            _ => {
+                println!("set_mode: unhandled sequence {:?}", params);
+                None
+            }
+        }
+    }
+
     /// Set Graphics Rendition (SGR)
     fn sgr(&mut self, params: &'a [i64]) -> Option<CSIAction> {
         match params {
@@ -361,6 +461,28 @@ impl<'a> CSIParser<'a> {
             }
         }
     }
+
+    fn set_left_and_right_margins(&mut self, params: &'a [i64]) -> Option<CSIAction> {
+        match params {
+            &[left, right] => {
+                self.advance_by(2, params);
+                Some(CSIAction::SetLeftAndRightMargins { left: left - 1, right: right - 1 })
+            }
+            &[left] => {
+                self.advance_by(1, params);
+                Some(CSIAction::SetLeftAndRightMargins { left: left - 1, right: i64::max_value() })
+            }
+            &[] => {
+                // As with set_scroll_region, the default is the full
+                // width of the screen.
+                Some(CSIAction::SetLeftAndRightMargins { left: 0, right: i64::max_value() })
+            }
+            _ => {
+                println!("set_left_and_right_margins: invalid sequence: {:?}", params);
+                None
+            }
+        }
+    }
 }
 
 impl<'a> Iterator for CSIParser<'a> {
@@ -402,6 +524,15 @@ impl<'a> Iterator for CSIParser<'a> {
                 Some(CSIAction::SetCursorXY { x: Position::Relative(-x), y: Position::Relative(0) })
             }
 
+            // CHA - Cursor Horizontal Absolute
+            ('G', &[], Some(&[])) => {
+                Some(CSIAction::SetCursorXY { x: Position::Absolute(0), y: Position::Relative(0) })
+            }
+            ('G', &[], Some(&[x])) => Some(CSIAction::SetCursorXY {
+                x: Position::Absolute(x.max(1) - 1),
+                y: Position::Relative(0),
+            }),
+
             // H: Cursor Position (CUP)
             // f: Horizontal and vertical position (HVP)
             ('H', &[], Some(&[])) | ('f', &[], Some(&[])) => {
@@ -415,6 +546,10 @@ impl<'a> Iterator for CSIParser<'a> {
                 })
             }
 
+            // CHT - Cursor Forward Tabulation
+            ('I', &[], Some(&[])) => Some(CSIAction::TabForward(1)),
+            ('I', &[], Some(&[n])) => Some(CSIAction::TabForward(n)),
+
             // Erase in Display (ED)
             ('J', &[], Some(&[])) | ('J', &[], Some(&[0])) => {
                 Some(CSIAction::EraseInDisplay(DisplayErase::Below))
@@ -438,14 +573,40 @@ impl<'a> Iterator for CSIParser<'a> {
             ('M', &[], Some(&[])) => Some(CSIAction::DeleteLines(1)),
             ('M', &[], Some(&[n])) => Some(CSIAction::DeleteLines(n)),
 
+            // DCH: Delete Character
+            ('P', &[], Some(&[])) => Some(CSIAction::DeleteCharacter(1)),
+            ('P', &[], Some(&[n])) => Some(CSIAction::DeleteCharacter(n)),
+
             // SU: Scroll Up Lines
             ('S', &[], Some(&[])) => Some(CSIAction::ScrollLines(-1)),
             ('S', &[], Some(&[n])) => Some(CSIAction::ScrollLines(-n)),
 
+            // SD: Scroll Down Lines.  Note that the xterm mouse highlight
+            // tracking sequence also ends in T but has five parameters.
+            ('T', &[], Some(&[])) => Some(CSIAction::ScrollLines(1)),
+            ('T', &[], Some(&[n])) => Some(CSIAction::ScrollLines(n)),
+
             // ECH: Erase Character
             ('X', &[], Some(&[])) => Some(CSIAction::EraseCharacter(1)),
             ('X', &[], Some(&[n])) => Some(CSIAction::EraseCharacter(n)),
 
+            // CBT - Cursor Backward Tabulation
+            ('Z', &[], Some(&[])) => Some(CSIAction::TabBackward(1)),
+            ('Z', &[], Some(&[n])) => Some(CSIAction::TabBackward(n)),
+
+            // ICH: Insert Character
+            ('@', &[], Some(&[])) => Some(CSIAction::InsertCharacter(1)),
+            ('@', &[], Some(&[n])) => Some(CSIAction::InsertCharacter(n)),
+
+            // HPA - Character Position Absolute
+            ('`', &[], Some(&[])) => {
+                Some(CSIAction::SetCursorXY { x: Position::Absolute(0), y: Position::Relative(0) })
+            }
+            ('`', &[], Some(&[x])) => Some(CSIAction::SetCursorXY {
+                x: Position::Absolute(x.max(1) - 1),
+                y: Position::Relative(0),
+            }),
+
             // HPR - Character position Relative
             ('a', &[], Some(&[])) => {
                 Some(CSIAction::SetCursorXY { x: Position::Relative(1), y: Position::Relative(0) })
@@ -454,10 +615,20 @@ impl<'a> Iterator for CSIParser<'a> {
                 Some(CSIAction::SetCursorXY { x: Position::Relative(x), y: Position::Relative(0) })
             }
 
-            ('c', &[b'>'], Some(&[]))
-            | ('c', &[], Some(&[]))
-            | ('c', &[], Some(&[0]))
-            | ('c', &[b'>'], Some(&[0])) => Some(CSIAction::RequestDeviceAttributes),
+            // REP - Repeat the preceding graphic character
+            ('b', &[], Some(&[])) => Some(CSIAction::RepeatCharacter(1)),
+            ('b', &[], Some(&[n])) => Some(CSIAction::RepeatCharacter(n)),
+
+            // DA1, DA2 and DA3: Primary, Secondary and Tertiary Device Attributes
+            ('c', &[], Some(&[])) | ('c', &[], Some(&[0])) => {
+                Some(CSIAction::RequestPrimaryDeviceAttributes)
+            }
+            ('c', &[b'>'], Some(&[])) | ('c', &[b'>'], Some(&[0])) => {
+                Some(CSIAction::RequestSecondaryDeviceAttributes)
+            }
+            ('c', &[b'='], Some(&[])) | ('c', &[b'='], Some(&[0])) => {
+                Some(CSIAction::RequestTertiaryDeviceAttributes)
+            }
 
             // VPA: Line Position Absolute
             ('d', &[], Some(&[])) => Some(CSIAction::LinePosition(Position::Absolute(0))),
@@ -467,18 +638,52 @@ impl<'a> Iterator for CSIParser<'a> {
             ('e', &[], Some(&[])) => Some(CSIAction::LinePosition(Position::Relative(0))),
             ('e', &[], Some(&[n])) => Some(CSIAction::LinePosition(Position::Relative(n))),
 
+            // TBC - Tabulation Clear
+            ('g', &[], Some(&[])) | ('g', &[], Some(&[0])) => {
+                Some(CSIAction::ClearTabStop(TabulationClear::CurrentColumn))
+            }
+            ('g', &[], Some(&[3])) => Some(CSIAction::ClearTabStop(TabulationClear::All)),
+
             ('h', &[b'?'], Some(params)) => self.dec_set_mode(params),
             ('l', &[b'?'], Some(params)) => self.dec_reset_mode(params),
+            ('h', &[], Some(params)) => self.set_mode(params, true),
+            ('l', &[], Some(params)) => self.set_mode(params, false),
             ('m', &[], Some(params)) => self.sgr(params),
+            // XTMODKEYS; the other key modifier resources aren't supported
+            ('m', &[b'>'], Some(&[4])) => Some(CSIAction::SetModifyOtherKeys(0)),
+            ('m', &[b'>'], Some(&[4, level])) => Some(CSIAction::SetModifyOtherKeys(level)),
+            ('m', &[b'?'], Some(&[4])) => Some(CSIAction::RequestModifyOtherKeys),
             ('n', &[], Some(params)) => self.dsr(params),
+            ('n', &[b'>'], Some(&[4])) => Some(CSIAction::SetModifyOtherKeys(0)),
             ('p', &[b'!'], Some(&[])) => Some(CSIAction::SoftReset),
+            // DECRQM: Request DEC Private Mode
+            ('p', &[b'?', b'$'], Some(&[mode])) => Some(CSIAction::RequestDecPrivateMode(mode)),
+            // DECRQM: Request ANSI Mode
+            ('p', &[b'$'], Some(&[mode])) => Some(CSIAction::RequestMode(mode)),
+            // XTVERSION: Report the terminal name and version
+            ('q', &[b'>'], Some(&[])) | ('q', &[b'>'], Some(&[0])) => {
+                Some(CSIAction::RequestTerminalNameAndVersion)
+            }
             ('r', &[], Some(params)) => self.set_scroll_region(params),
 
-            // SCOSC: Save Cursor
-            ('s', &[], Some(&[])) => Some(CSIAction::SaveCursor),
+            // DECSLRM: Set Left and Right Margins.
+            // This is also SCOSC (Save Cursor) when DECLRMM is not enabled,
+            // which is resolved when the action is performed.
+            ('s', &[], Some(params)) => self.set_left_and_right_margins(params),
             // SCORC: Restore Cursor
             ('u', &[], Some(&[])) => Some(CSIAction::RestoreCursor),
 
+            // The kitty keyboard protocol
+            ('u', &[b'>'], Some(&[])) => Some(CSIAction::PushKeyboardFlags(0)),
+            ('u', &[b'>'], Some(&[flags])) => Some(CSIAction::PushKeyboardFlags(flags)),
+            ('u', &[b'<'], Some(&[])) => Some(CSIAction::PopKeyboardFlags(1)),
+            ('u', &[b'<'], Some(&[n])) => Some(CSIAction::PopKeyboardFlags(n)),
+            ('u', &[b'='], Some(&[flags])) => Some(CSIAction::SetKeyboardFlags { flags, mode: 1 }),
+            ('u', &[b'='], Some(&[flags, mode])) => {
+                Some(CSIAction::SetKeyboardFlags { flags, mode })
+            }
+            ('u', &[b'?'], Some(&[])) => Some(CSIAction::RequestKeyboardFlags),
+
             (b, i, Some(p)) => {
                 println!("cSI unhandled {} {:?} {:?} ignore={}", b, p, i, self.ignore);
                 None
@@ -0,0 +1,138 @@
+//! Images that applications display in the terminal, such as those
+//! sent as sixel graphics.
+
+use failure::Error;
+use image_codec::{self, ImageDecoder, ImageFormat};
+use std::fmt;
+use std::io::{BufReader, Cursor};
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_IMAGE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Images are no more than this many pixels in each direction
+pub(crate) const MAX_IMAGE_DIMENSION: usize = 4096;
+
+/// A decoded image
+pub struct ImageData {
+    /// Uniquely identifies the image, so that the renderer can
+    /// cache its texture
+    id: usize,
+    width: usize,
+    height: usize,
+    /// RGBA pixels, row by row from the top
+    data: Vec<u8>,
+}
+
+impl ImageData {
+    /// Create an image from its RGBA pixels, which are stored row
+    /// by row starting with the top row.
+    pub fn with_rgba(width: usize, height: usize, data: Vec<u8>) -> ImageData {
+        assert_eq!(data.len(), width * height * 4);
+        let id = NEXT_IMAGE_ID.fetch_add(1, Ordering::Relaxed);
+        ImageData { id, width, height, data }
+    }
+
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the RGBA value of a pixel
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let idx = (y * self.width + x) * 4;
+        [self.data[idx], self.data[idx + 1], self.data[idx + 2], self.data[idx + 3]]
+    }
+}
+
+impl PartialEq for ImageData {
+    fn eq(&self, other: &ImageData) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for ImageData {}
+
+impl fmt::Debug for ImageData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ImageData {{ id: {}, width: {}, height: {} }}", self.id, self.width, self.height)
+    }
+}
+
+/// The part of an image that is displayed on a line.  An image that
+/// covers several rows of cells is split into a slice for each row,
+/// so that it scrolls along with the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSlice {
+    pub image: Rc<ImageData>,
+    /// The column of the left edge of the image
+    pub x: usize,
+    /// How many columns the image covers
+    pub cols: usize,
+    /// The first row of pixels displayed on this line
+    pub top: usize,
+    /// How many rows of pixels are displayed on this line
+    pub height: usize,
+    /// Images with a negative z-index are drawn beneath the text and
+    /// the others above it.  Images with a higher z-index are drawn
+    /// over those with a lower one.
+    pub z_index: i32,
+    /// Identifies images placed with the kitty graphics protocol,
+    /// which applications can later delete
+    pub placement: Option<ImagePlacement>,
+}
+
+/// Identifies an image placed with the kitty graphics protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImagePlacement {
+    /// The id that the application gave the image, or 0
+    pub image_id: u32,
+    /// The id that the application gave the placement, or 0
+    pub placement_id: u32,
+    /// Distinguishes placements that have the same ids
+    pub serial: usize,
+}
+
+impl ImageSlice {
+    /// Returns true if the slice covers part of the range of columns
+    pub fn intersects(&self, cols: &Range<usize>) -> bool {
+        self.x < cols.end && self.x + self.cols > cols.start
+    }
+}
+
+/// Read the width and height of an image file, such as a PNG, from its
+/// header.  This allows rejecting images that are too large before
+/// their pixels are decoded.
+pub(crate) fn image_file_size(data: &[u8]) -> Result<(usize, usize), Error> {
+    let reader = Cursor::new(data);
+    let (width, height) = match image_codec::guess_format(data)? {
+        ImageFormat::PNG => image_codec::png::PNGDecoder::new(reader)?.dimensions(),
+        ImageFormat::JPEG => image_codec::jpeg::JPEGDecoder::new(reader)?.dimensions(),
+        ImageFormat::GIF => image_codec::gif::Decoder::new(reader)?.dimensions(),
+        ImageFormat::WEBP => image_codec::webp::WebpDecoder::new(reader)?.dimensions(),
+        ImageFormat::TIFF => image_codec::tiff::TIFFDecoder::new(reader)?.dimensions(),
+        ImageFormat::BMP => image_codec::bmp::BMPDecoder::new(reader)?.dimensions(),
+        ImageFormat::ICO => image_codec::ico::ICODecoder::new(reader)?.dimensions(),
+        ImageFormat::HDR => image_codec::hdr::HDRAdapter::new(BufReader::new(reader))?.dimensions(),
+        ImageFormat::PNM => image_codec::pnm::PNMDecoder::new(BufReader::new(reader))?.dimensions(),
+        format => bail!("unsupported image format {:?}", format),
+    };
+    let clamp = |n: u64| n.min(usize::max_value() as u64) as usize;
+    Ok((clamp(width), clamp(height)))
+}
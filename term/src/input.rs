@@ -15,8 +15,27 @@ bitflags! {
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum KeyCode {
+    /// A key that produces this character.  Control characters are
+    /// sent as they are; prefer the named keys below to them.
     Char(char),
     Unknown,
+    Backspace,
+    Tab,
+    Enter,
+    Escape,
+    Delete,
+    /// F1 through F24
+    Function(u8),
+    /// The digits on the numeric keypad
+    Numpad(u8),
+    NumpadDecimal,
+    NumpadSeparator,
+    NumpadAdd,
+    NumpadSubtract,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadEqual,
+    NumpadEnter,
     Control,
     Alt,
     Meta,
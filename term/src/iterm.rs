@@ -0,0 +1,176 @@
+//! iTerm2's inline image protocol, `OSC 1337 ; File=args : base64 ST`,
+//! which is emitted by `imgcat` and many other tools.
+//! See https://iterm2.com/documentation-images.html
+
+use super::*;
+use base64;
+use image_codec::{self, GenericImageView};
+
+/// The largest image file that applications may display if the
+/// host doesn't say otherwise
+pub const DEFAULT_MAX_IMAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The width or height requested for an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ImageDimension {
+    /// The size of the image itself
+    Auto,
+    /// A number of cells
+    Cells(usize),
+    /// A number of pixels
+    Pixels(usize),
+    /// A percentage of the width or height of the screen
+    Percent(usize),
+}
+
+impl ImageDimension {
+    pub fn parse(s: &str) -> Result<ImageDimension, Error> {
+        let dim = if s == "auto" {
+            ImageDimension::Auto
+        } else if s.ends_with("px") {
+            ImageDimension::Pixels(s[..s.len() - 2].parse()?)
+        } else if s.ends_with('%') {
+            ImageDimension::Percent(s[..s.len() - 1].parse()?)
+        } else {
+            ImageDimension::Cells(s.parse()?)
+        };
+        Ok(dim)
+    }
+
+    /// Resolve the dimension to a number of pixels, or None for Auto.
+    /// `cell` and `screen` are the sizes of a cell and of the screen
+    /// in the same direction.
+    fn to_pixels(self, cell: usize, screen: usize) -> Option<usize> {
+        match self {
+            ImageDimension::Auto => None,
+            ImageDimension::Cells(n) => Some(n.saturating_mul(cell)),
+            ImageDimension::Pixels(n) => Some(n),
+            ImageDimension::Percent(n) => Some(screen * n.min(100) / 100),
+        }
+    }
+}
+
+/// A parsed `OSC 1337 ; File=` request
+#[derive(Debug)]
+pub(crate) struct InlineImage {
+    pub width: ImageDimension,
+    pub height: ImageDimension,
+    pub preserve_aspect_ratio: bool,
+    /// The image is to be displayed rather than downloaded
+    pub inline: bool,
+    /// The contents of the image file
+    pub data: Vec<u8>,
+}
+
+impl InlineImage {
+    /// Parse `File=args:payload`, where the args are `key=value` pairs
+    /// separated by semicolons.  Payloads that decode to more than
+    /// `max_size` bytes are rejected.
+    pub fn parse(osc: &[u8], max_size: usize) -> Result<InlineImage, Error> {
+        if !osc.starts_with(b"File=") {
+            bail!("unsupported request");
+        }
+        let colon = osc.iter().position(|&b| b == b':').ok_or_else(|| format_err!("no payload"))?;
+        let args = str::from_utf8(&osc[5..colon])?;
+        let payload = &osc[colon + 1..];
+
+        let mut image = InlineImage {
+            width: ImageDimension::Auto,
+            height: ImageDimension::Auto,
+            preserve_aspect_ratio: true,
+            inline: false,
+            data: Vec::new(),
+        };
+        for arg in args.split(';').filter(|arg| !arg.is_empty()) {
+            let mut pair = arg.splitn(2, '=');
+            let (key, value) = (pair.next().unwrap_or(""), pair.next().unwrap_or(""));
+            match key {
+                "width" => image.width = ImageDimension::parse(value)?,
+                "height" => image.height = ImageDimension::parse(value)?,
+                "preserveAspectRatio" => image.preserve_aspect_ratio = value != "0",
+                "inline" => image.inline = value == "1",
+                "size" => {
+                    if value.parse::<usize>()? > max_size {
+                        bail!("{} bytes exceeds the limit of {}", value, max_size);
+                    }
+                }
+                // The name is only used for downloads
+                _ => {}
+            }
+        }
+
+        if payload.len() / 4 * 3 > max_size {
+            bail!("the payload exceeds the limit of {} bytes", max_size);
+        }
+        image.data = base64::decode(payload)?;
+        Ok(image)
+    }
+
+    /// Decode the image and scale it to the requested size.
+    /// `cell` and `screen` are the sizes of a cell and of the screen
+    /// in pixels.
+    pub fn decode(&self, cell: (usize, usize), screen: (usize, usize)) -> Result<ImageData, Error> {
+        // Check the size before decoding, so that a small file can't
+        // claim an image large enough to exhaust memory
+        let (width, height) = image_file_size(&self.data)?;
+        if width == 0 || height == 0 {
+            bail!("the image is empty");
+        }
+        if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+            bail!("the image is too large");
+        }
+        let decoded = image_codec::load_from_memory(&self.data)?;
+        let (width, height) = decoded.dimensions();
+
+        let (target_width, target_height) =
+            self.target_size((width as usize, height as usize), cell, screen);
+        let decoded = if (target_width, target_height) == (width as usize, height as usize) {
+            decoded
+        } else {
+            decoded.resize_exact(
+                target_width as u32,
+                target_height as u32,
+                image_codec::FilterType::Triangle,
+            )
+        };
+
+        Ok(ImageData::with_rgba(target_width, target_height, decoded.to_rgba().into_raw()))
+    }
+
+    /// Compute the size at which to display an image whose own size
+    /// is `natural`
+    pub fn target_size(
+        &self,
+        natural: (usize, usize),
+        cell: (usize, usize),
+        screen: (usize, usize),
+    ) -> (usize, usize) {
+        let (natural_width, natural_height) = natural;
+        let width = self.width.to_pixels(cell.0, screen.0).map(|w| w.min(MAX_IMAGE_DIMENSION));
+        let height = self.height.to_pixels(cell.1, screen.1).map(|h| h.min(MAX_IMAGE_DIMENSION));
+        let preserve = self.preserve_aspect_ratio;
+
+        let (width, height) = match (width, height) {
+            // Large images are shrunk to fit the width of the screen
+            (None, None) if natural_width > screen.0 => {
+                (screen.0, natural_height * screen.0 / natural_width)
+            }
+            (None, None) => natural,
+            (Some(width), None) if preserve => (width, natural_height * width / natural_width),
+            (Some(width), None) => (width, natural_height),
+            (None, Some(height)) if preserve => (natural_width * height / natural_height, height),
+            (None, Some(height)) => (natural_width, height),
+            // Fit the image within the box, leaving space at the bottom or right
+            (Some(width), Some(height)) if preserve => {
+                if width * natural_height <= height * natural_width {
+                    (width, natural_height * width / natural_width)
+                } else {
+                    (natural_width * height / natural_height, height)
+                }
+            }
+            (Some(width), Some(height)) => (width, height),
+        };
+
+        (width.max(1).min(MAX_IMAGE_DIMENSION), height.max(1).min(MAX_IMAGE_DIMENSION))
+    }
+}
@@ -0,0 +1,346 @@
+//! Encoding key events.  Besides xterm's encodings of modified keys
+//! this covers the kitty keyboard protocol, which applications enable
+//! with `CSI > flags u`.
+//! See https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+//! and https://invisible-island.net/xterm/modified-keys.html
+
+use super::*;
+
+bitflags! {
+    /// The progressive enhancements that an application has asked for
+    #[derive(Default)]
+    pub struct KeyboardFlags: u8 {
+        const DISAMBIGUATE_ESCAPE_CODES = 1;
+        const REPORT_EVENT_TYPES = 2;
+        const REPORT_ALTERNATE_KEYS = 4;
+        const REPORT_ALL_KEYS_AS_ESCAPE_CODES = 8;
+        const REPORT_ASSOCIATED_TEXT = 16;
+    }
+}
+
+/// The kinds of key event, which are reported when the application
+/// asks for REPORT_EVENT_TYPES
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum KeyEventKind {
+    Press,
+    Repeat,
+    Release,
+}
+
+/// Encode a key event.  Returns None if the key is to be sent as it
+/// is when the protocol isn't enabled, which is the case for text
+/// and for unmodified functional keys, or an empty string if nothing
+/// is to be sent at all.
+pub(crate) fn encode_key(
+    key: KeyCode,
+    mods: KeyModifiers,
+    kind: KeyEventKind,
+    flags: KeyboardFlags,
+) -> Option<String> {
+    use KeyCode::*;
+    let all_keys = flags.contains(KeyboardFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES);
+    let unmodified_press = mods.is_empty() && kind == KeyEventKind::Press;
+
+    let (code, final_byte) = match key {
+        // The host scrolls and pastes with these rather than sending them
+        PageUp | PageDown | Insert if mods == KeyModifiers::SHIFT => {
+            return if kind == KeyEventKind::Release { Some(String::new()) } else { None };
+        }
+        Up | Down | Right | Left | Home | End | PageUp | PageDown | Insert | Delete
+        | Function(1...12) => {
+            if unmodified_press {
+                return None;
+            }
+            match key {
+                Up => (1, 'A'),
+                Down => (1, 'B'),
+                Right => (1, 'C'),
+                Left => (1, 'D'),
+                Home => (1, 'H'),
+                End => (1, 'F'),
+                PageUp => (5, '~'),
+                PageDown => (6, '~'),
+                Insert => (2, '~'),
+                Delete => (3, '~'),
+                // CSI R would be mistaken for a cursor position report
+                Function(3) => (13, '~'),
+                Function(n) => function_key(n),
+                _ => unreachable!(),
+            }
+        }
+        // These have no legacy encoding
+        Function(n) => (57363 + u32::from(n), 'u'),
+        Shift | Control | Alt | Super | Hyper | Meta => {
+            if !all_keys {
+                return Some(String::new());
+            }
+            let code = match key {
+                Shift => 57441,
+                Control => 57442,
+                Alt => 57443,
+                Super => 57444,
+                Hyper => 57445,
+                _ => 57446,
+            };
+            (code, 'u')
+        }
+        Char(c) => return encode_char(c, mods, kind, flags),
+        Backspace => return encode_char('\x7f', mods, kind, flags),
+        Tab => return encode_char('\t', mods, kind, flags),
+        Enter => return encode_char('\r', mods, kind, flags),
+        Escape => return encode_char('\x1b', mods, kind, flags),
+        Numpad(_) | NumpadDecimal | NumpadSeparator | NumpadAdd | NumpadSubtract
+        | NumpadMultiply | NumpadDivide | NumpadEqual | NumpadEnter => {
+            let (text, _, code) = numpad_key(key)?;
+            return encode_text_key(code, text, key != NumpadEnter, mods, kind, flags);
+        }
+        Unknown => return None,
+    };
+    Some(format!("\x1b[{}{}", key_params(code.to_string(), mods, kind, None), final_byte))
+}
+
+/// Encode a key that is reported as a character
+fn encode_char(
+    c: char,
+    mods: KeyModifiers,
+    kind: KeyEventKind,
+    flags: KeyboardFlags,
+) -> Option<String> {
+    let (code, is_text) = match c {
+        '\x08' | '\x7f' => (127, false),
+        '\r' | '\n' => (13, false),
+        '\t' => (9, false),
+        '\x1b' => (27, false),
+        c => (unshifted(c) as u32, c >= ' '),
+    };
+    encode_text_key(code, c, is_text, mods, kind, flags)
+}
+
+/// Encode a key that is identified by `code` and that produces `c`,
+/// which is text unless the key is Enter, Tab, Backspace or Escape
+fn encode_text_key(
+    code: u32,
+    c: char,
+    is_text: bool,
+    mods: KeyModifiers,
+    kind: KeyEventKind,
+    flags: KeyboardFlags,
+) -> Option<String> {
+    let all_keys = flags.contains(KeyboardFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES);
+    let modified = !(mods - KeyModifiers::SHIFT).is_empty();
+
+    let escape_code = if all_keys || code == 27 {
+        true
+    } else if !is_text {
+        // Enter, Tab and Backspace are sent as they always were, so
+        // that the shell remains usable if an application forgets to
+        // turn the protocol off, and their releases aren't reported
+        if kind == KeyEventKind::Release {
+            return Some(String::new());
+        }
+        !mods.is_empty()
+    } else {
+        modified || kind == KeyEventKind::Release
+    };
+    if !escape_code {
+        return None;
+    }
+
+    let mut key = code.to_string();
+    if flags.contains(KeyboardFlags::REPORT_ALTERNATE_KEYS)
+        && mods.contains(KeyModifiers::SHIFT)
+        && is_text
+        && unshifted(c) != c
+    {
+        key.push_str(&format!(":{}", c as u32));
+    }
+    let text = if all_keys
+        && flags.contains(KeyboardFlags::REPORT_ASSOCIATED_TEXT)
+        && is_text
+        && !modified
+        && kind != KeyEventKind::Release
+    {
+        Some(c)
+    } else {
+        None
+    };
+    Some(format!("\x1b[{}u", key_params(key, mods, kind, text)))
+}
+
+/// Format the parameters of an escape code: the key, then the
+/// modifiers and the kind of event, then the text that the key
+/// produces.  Trailing parameters are omitted if they are defaults.
+fn key_params(key: String, mods: KeyModifiers, kind: KeyEventKind, text: Option<char>) -> String {
+    let mut bits = 0;
+    for &(modifier, bit) in &[
+        (KeyModifiers::SHIFT, 1),
+        (KeyModifiers::ALT, 2),
+        (KeyModifiers::CTRL, 4),
+        (KeyModifiers::SUPER, 8),
+        (KeyModifiers::META, 32),
+    ] {
+        if mods.contains(modifier) {
+            bits |= bit;
+        }
+    }
+    let event = match kind {
+        KeyEventKind::Press => "",
+        KeyEventKind::Repeat => ":2",
+        KeyEventKind::Release => ":3",
+    };
+
+    let mut params = key;
+    if bits != 0 || !event.is_empty() || text.is_some() {
+        params.push(';');
+        if bits != 0 || !event.is_empty() {
+            params.push_str(&format!("{}{}", bits + 1, event));
+        }
+    }
+    if let Some(text) = text {
+        params.push_str(&format!(";{}", text as u32));
+    }
+    params
+}
+
+/// The modifier parameter of xterm's encodings of modified keys, or
+/// None if no modifiers are held
+pub(crate) fn xterm_modifiers(mods: KeyModifiers) -> Option<u8> {
+    let mut bits = 0;
+    for &(modifier, bit) in &[
+        (KeyModifiers::SHIFT, 1),
+        (KeyModifiers::ALT, 2),
+        (KeyModifiers::CTRL, 4),
+        (KeyModifiers::META, 8),
+        (KeyModifiers::SUPER, 8),
+    ] {
+        if mods.contains(modifier) {
+            bits |= bit;
+        }
+    }
+    if bits == 0 {
+        None
+    } else {
+        Some(bits + 1)
+    }
+}
+
+/// xterm's encoding of F1 through F12: the number and the final byte
+/// of the escape code.  F1 through F4 are sent as SS3 P to SS3 S when
+/// they aren't modified.
+pub(crate) fn function_key(n: u8) -> (u32, char) {
+    match n {
+        1 => (1, 'P'),
+        2 => (1, 'Q'),
+        3 => (1, 'R'),
+        4 => (1, 'S'),
+        5 => (15, '~'),
+        6 => (17, '~'),
+        7 => (18, '~'),
+        8 => (19, '~'),
+        9 => (20, '~'),
+        10 => (21, '~'),
+        11 => (23, '~'),
+        _ => (24, '~'),
+    }
+}
+
+/// Describes a key on the numeric keypad: the character that it
+/// produces, the final byte of the SS3 sequence that it sends in
+/// application keypad mode and its code in the kitty protocol
+pub(crate) fn numpad_key(key: KeyCode) -> Option<(char, char, u32)> {
+    use KeyCode::*;
+    Some(match key {
+        Numpad(n @ 0...9) => ((b'0' + n) as char, (b'p' + n) as char, 57399 + u32::from(n)),
+        NumpadDecimal => ('.', 'n', 57409),
+        NumpadDivide => ('/', 'o', 57410),
+        NumpadMultiply => ('*', 'j', 57411),
+        NumpadSubtract => ('-', 'm', 57412),
+        NumpadAdd => ('+', 'k', 57413),
+        NumpadEnter => ('\r', 'M', 57414),
+        NumpadEqual => ('=', 'X', 57415),
+        NumpadSeparator => (',', 'l', 57416),
+        _ => return None,
+    })
+}
+
+/// The control character that Ctrl produces with this key, if any
+pub(crate) fn ctrl_code(c: char) -> Option<char> {
+    let code = match c {
+        '@'...'_' => c as u8 - 0x40,
+        'a'...'z' => c as u8 - 0x60,
+        ' ' | '2' => 0,
+        '3'...'7' => c as u8 - b'3' + 0x1b,
+        '8' | '?' => 0x7f,
+        '/' => 0x1f,
+        _ => return None,
+    };
+    Some(code as char)
+}
+
+/// Encode a key as xterm does when modifyOtherKeys is enabled, which
+/// applications do with `CSI > 4 ; level m`.  Level 1 encodes only
+/// the keys that can't otherwise be told apart, such as Ctrl+Enter or
+/// Ctrl+Shift+A, and level 2 encodes every modified key.  Returns None
+/// if the key is to be sent as usual.
+pub(crate) fn encode_modify_other_keys(
+    key: KeyCode,
+    mods: KeyModifiers,
+    level: u8,
+) -> Option<String> {
+    let c = match key {
+        KeyCode::Char(c) => c,
+        KeyCode::Backspace => '\x08',
+        KeyCode::Tab => '\t',
+        KeyCode::Enter => '\r',
+        KeyCode::Escape => '\x1b',
+        _ => return None,
+    };
+    let modifiers = xterm_modifiers(mods)?;
+    let is_text = c >= ' ' && c != '\x7f';
+    let encode = match level {
+        1 => {
+            mods.contains(KeyModifiers::CTRL)
+                && (!is_text
+                    || ctrl_code(c).is_none()
+                    || (mods.contains(KeyModifiers::SHIFT) && c.is_ascii_alphabetic()))
+        }
+        2 => mods != KeyModifiers::SHIFT || !is_text,
+        _ => false,
+    };
+    if encode {
+        Some(format!("\x1b[27;{};{}~", modifiers, c as u32))
+    } else {
+        None
+    }
+}
+
+/// The key is identified by the character that it produces without
+/// shift.  The terminal isn't told the keyboard layout, so this
+/// assumes the US layout for the symbols.
+fn unshifted(c: char) -> char {
+    match c {
+        'A'...'Z' => c.to_ascii_lowercase(),
+        '!' => '1',
+        '@' => '2',
+        '#' => '3',
+        '$' => '4',
+        '%' => '5',
+        '^' => '6',
+        '&' => '7',
+        '*' => '8',
+        '(' => '9',
+        ')' => '0',
+        '_' => '-',
+        '+' => '=',
+        '{' => '[',
+        '}' => ']',
+        '|' => '\\',
+        ':' => ';',
+        '"' => '\'',
+        '<' => ',',
+        '>' => '.',
+        '?' => '/',
+        '~' => '`',
+        c => c,
+    }
+}
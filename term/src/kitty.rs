@@ -0,0 +1,538 @@
+//! The kitty graphics protocol, `APC G control-data ; payload ST`,
+//! which is used by `timg`, `chafa` and the image previews of file
+//! managers such as yazi and ranger.
+//! See https://sw.kovidgoyal.net/kitty/graphics-protocol/
+
+use super::*;
+use base64;
+use image_codec::{self, GenericImageView};
+use inflate::InflateStream;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// How many bytes of pixels are kept for the images that applications
+/// may place again; the oldest images are evicted beyond this
+const MAX_STORED_IMAGE_BYTES: usize = 320 * 1024 * 1024;
+
+/// The most that compressed image data may expand to
+const MAX_DECOMPRESSED_SIZE: usize = MAX_IMAGE_DIMENSION * MAX_IMAGE_DIMENSION * 4;
+
+/// Temporary files must have this in their path, so that the protocol
+/// can't be used to delete arbitrary files
+const TEMP_FILE_MARKER: &str = "tty-graphics-protocol";
+
+/// A parsed graphics command.  The keys of the control data are
+/// single letters, which are given alongside the fields they set.
+#[derive(Debug, Clone)]
+pub(crate) struct GraphicsCommand {
+    /// `a`: t(ransmit), T(ransmit and display), p(ut), d(elete)
+    /// or q(uery)
+    pub action: u8,
+    /// `q`: 1 suppresses OK responses and 2 suppresses errors as well
+    pub quiet: u32,
+    /// `f`: 24 or 32 for raw RGB or RGBA pixels, or 100 for PNG
+    pub format: u32,
+    /// `t`: d(irect), f(ile), t(emporary file) or s(hared memory)
+    pub medium: u8,
+    /// `o`: z for zlib compression
+    pub compression: Option<u8>,
+    /// `s`: the width of raw pixel data
+    pub data_width: u32,
+    /// `v`: the height of raw pixel data
+    pub data_height: u32,
+    /// `S`: how many bytes to read from a file, or 0 for all of it
+    pub data_size: u32,
+    /// `O`: where in a file to start reading
+    pub data_offset: u32,
+    /// `i`: the image id
+    pub image_id: u32,
+    /// `I`: the image number, for which the terminal picks an id
+    pub image_number: u32,
+    /// `p`: the placement id
+    pub placement_id: u32,
+    /// `m`: more chunks of the payload follow
+    pub more: bool,
+    /// `x`: the left edge of the part of the image to display.  It
+    /// also gives the column or the lowest id for some deletions.
+    pub x: u32,
+    /// `y`: the top edge of the part of the image to display.  It
+    /// also gives the row or the highest id for some deletions.
+    pub y: u32,
+    /// `w`: the width of the part of the image to display, or 0
+    pub width: u32,
+    /// `h`: the height of the part of the image to display, or 0
+    pub height: u32,
+    /// `X`: the offset in pixels of the image within its first cell
+    pub cell_x_offset: u32,
+    /// `Y`: the offset in pixels of the image within its first cell
+    pub cell_y_offset: u32,
+    /// `c`: how many columns to scale the image to, or 0
+    pub cols: u32,
+    /// `r`: how many rows to scale the image to, or 0
+    pub rows: u32,
+    /// `C`: 1 leaves the cursor where it is
+    pub cursor_movement: u32,
+    /// `z`: the z-index of the placement
+    pub z_index: i32,
+    /// `d`: which placements to delete
+    pub delete: u8,
+    /// The decoded payload: the image data or the name of a file
+    pub payload: Vec<u8>,
+}
+
+impl GraphicsCommand {
+    /// Parse the APC string that follows `G`: comma separated
+    /// `key=value` pairs, optionally followed by `;` and a base64
+    /// encoded payload
+    pub fn parse(data: &[u8]) -> Result<GraphicsCommand, Error> {
+        let mut parts = data.splitn(2, |&b| b == b';');
+        let control = str::from_utf8(parts.next().unwrap_or(b""))?;
+        let payload = parts.next().unwrap_or(b"");
+
+        let mut cmd = GraphicsCommand {
+            action: b't',
+            quiet: 0,
+            format: 32,
+            medium: b'd',
+            compression: None,
+            data_width: 0,
+            data_height: 0,
+            data_size: 0,
+            data_offset: 0,
+            image_id: 0,
+            image_number: 0,
+            placement_id: 0,
+            more: false,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            cell_x_offset: 0,
+            cell_y_offset: 0,
+            cols: 0,
+            rows: 0,
+            cursor_movement: 0,
+            z_index: 0,
+            delete: b'a',
+            payload: base64::decode(payload)
+                .map_err(|err| format_err!("EINVAL:bad payload: {}", err))?,
+        };
+
+        for pair in control.split(',').filter(|pair| !pair.is_empty()) {
+            let mut pair = pair.splitn(2, '=');
+            let (key, value) = (pair.next().unwrap_or(""), pair.next().unwrap_or(""));
+            let letter =
+                || value.bytes().next().ok_or_else(|| format_err!("EINVAL:no value for {}", key));
+            let number =
+                || value.parse::<u32>().map_err(|_| format_err!("EINVAL:bad value for {}", key));
+            match key {
+                "a" => cmd.action = letter()?,
+                "q" => cmd.quiet = number()?,
+                "f" => cmd.format = number()?,
+                "t" => cmd.medium = letter()?,
+                "o" => cmd.compression = Some(letter()?),
+                "s" => cmd.data_width = number()?,
+                "v" => cmd.data_height = number()?,
+                "S" => cmd.data_size = number()?,
+                "O" => cmd.data_offset = number()?,
+                "i" => cmd.image_id = number()?,
+                "I" => cmd.image_number = number()?,
+                "p" => cmd.placement_id = number()?,
+                "m" => cmd.more = number()? == 1,
+                "x" => cmd.x = number()?,
+                "y" => cmd.y = number()?,
+                "w" => cmd.width = number()?,
+                "h" => cmd.height = number()?,
+                "X" => cmd.cell_x_offset = number()?,
+                "Y" => cmd.cell_y_offset = number()?,
+                "c" => cmd.cols = number()?,
+                "r" => cmd.rows = number()?,
+                "C" => cmd.cursor_movement = number()?,
+                "z" => {
+                    cmd.z_index =
+                        value.parse().map_err(|_| format_err!("EINVAL:bad value for {}", key))?
+                }
+                "d" => cmd.delete = letter()?,
+                // Animation and relative placements aren't supported
+                _ => {}
+            }
+        }
+        Ok(cmd)
+    }
+
+    /// Read the image data from wherever it was transmitted and
+    /// decode it.  Files larger than `max_size` are rejected.
+    pub fn load(&self, max_size: usize) -> Result<ImageData, Error> {
+        let temporary = match self.medium {
+            b'd' => return self.decode(&self.payload),
+            b'f' => false,
+            b't' => true,
+            b's' => bail!("EINVAL:shared memory is not supported"),
+            medium => bail!("EINVAL:unknown transmission medium {}", medium as char),
+        };
+        // The details of the error could tell the application whether
+        // a file exists, how large it is or what it holds, so they
+        // are only logged
+        self.read_file(temporary, max_size).and_then(|data| self.decode(&data)).map_err(|err| {
+            let path = String::from_utf8_lossy(&self.payload);
+            eprintln!("kitty graphics: unable to load {}: {}", path, err);
+            format_err!("EBADF")
+        })
+    }
+
+    /// Decompress and decode the image data
+    fn decode(&self, data: &[u8]) -> Result<ImageData, Error> {
+        let decompressed;
+        let data = match self.compression {
+            None => data,
+            Some(b'z') => {
+                decompressed = inflate(data)?;
+                &decompressed
+            }
+            Some(compression) => bail!("EINVAL:unknown compression {}", compression as char),
+        };
+
+        match self.format {
+            24 | 32 => {
+                let (width, height) = (self.data_width as usize, self.data_height as usize);
+                if width == 0 || height == 0 {
+                    bail!("EINVAL:the size of the image is missing");
+                }
+                if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+                    bail!("EINVAL:the image is too large");
+                }
+                let bytes_per_pixel = self.format as usize / 8;
+                let len = width * height * bytes_per_pixel;
+                if data.len() < len {
+                    bail!("ENODATA:expected {} bytes of image data but got {}", len, data.len());
+                }
+                let rgba = if bytes_per_pixel == 4 {
+                    data[..len].to_vec()
+                } else {
+                    let mut rgba = Vec::with_capacity(width * height * 4);
+                    for pixel in data[..len].chunks(3) {
+                        rgba.extend_from_slice(pixel);
+                        rgba.push(0xff);
+                    }
+                    rgba
+                };
+                Ok(ImageData::with_rgba(width, height, rgba))
+            }
+            100 => {
+                // Check the size before decoding, so that a small file
+                // can't claim an image large enough to exhaust memory
+                let (width, height) =
+                    image_file_size(data).map_err(|err| format_err!("EBADPNG:{}", err))?;
+                if width == 0 || height == 0 {
+                    bail!("EBADPNG:the image is empty");
+                }
+                if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+                    bail!("EINVAL:the image is too large");
+                }
+                let decoded = image_codec::load_from_memory(data)
+                    .map_err(|err| format_err!("EBADPNG:{}", err))?;
+                let (width, height) = decoded.dimensions();
+                Ok(ImageData::with_rgba(
+                    width as usize,
+                    height as usize,
+                    decoded.to_rgba().into_raw(),
+                ))
+            }
+            format => bail!("EINVAL:unknown format {}", format),
+        }
+    }
+
+    /// Read the file named by the payload.  Temporary files are
+    /// deleted once they have been read.
+    fn read_file(&self, temporary: bool, max_size: usize) -> Result<Vec<u8>, Error> {
+        let path = str::from_utf8(&self.payload)
+            .map_err(|_| format_err!("EINVAL:the file name is not valid UTF-8"))?;
+        // Resolve any symlinks and `..` before checking where it is
+        let path = fs::canonicalize(path).map_err(io_error)?;
+        if is_special_file(&path) {
+            bail!("EPERM:{} is not an ordinary file", path.display());
+        }
+        if temporary && !is_temp_file(&path) {
+            bail!("EPERM:{} is not a temporary file", path.display());
+        }
+        // Opening a FIFO would block until something writes to it, so
+        // check before opening, and open without blocking in case the
+        // file is replaced in between
+        if !fs::metadata(&path).map_err(io_error)?.is_file() {
+            bail!("EINVAL:{} is not a regular file", path.display());
+        }
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .map_err(io_error)?;
+        if !file.metadata().map_err(io_error)?.is_file() {
+            bail!("EINVAL:{} is not a regular file", path.display());
+        }
+
+        file.seek(SeekFrom::Start(u64::from(self.data_offset))).map_err(io_error)?;
+        let limit = match self.data_size as usize {
+            0 => max_size + 1,
+            size => size.min(max_size + 1),
+        };
+        let mut data = Vec::new();
+        let res = file.take(limit as u64).read_to_end(&mut data);
+        if temporary {
+            fs::remove_file(&path).ok();
+        }
+        res.map_err(io_error)?;
+
+        if data.len() > max_size {
+            bail!("EFBIG:the file exceeds the limit of {} bytes", max_size);
+        }
+        Ok(data)
+    }
+
+    /// Produce the image as it is to be displayed: cropped to the
+    /// requested part of it, scaled to the requested number of cells
+    /// and offset within its first cell
+    pub fn display_image(
+        &self,
+        image: &Rc<ImageData>,
+        cell: (usize, usize),
+    ) -> Result<Rc<ImageData>, Error> {
+        let (cell_width, cell_height) = cell;
+        let left = (self.x as usize).min(image.width());
+        let top = (self.y as usize).min(image.height());
+        let width = match self.width as usize {
+            0 => image.width() - left,
+            width => width.min(image.width() - left),
+        };
+        let height = match self.height as usize {
+            0 => image.height() - top,
+            height => height.min(image.height() - top),
+        };
+        if width == 0 || height == 0 {
+            bail!("EINVAL:the part of the image to display is empty");
+        }
+
+        let scaled_cols = (self.cols as usize).saturating_mul(cell_width).min(MAX_IMAGE_DIMENSION);
+        let scaled_rows = (self.rows as usize).saturating_mul(cell_height).min(MAX_IMAGE_DIMENSION);
+        let (target_width, target_height) = match (scaled_cols, scaled_rows) {
+            (0, 0) => (width, height),
+            (target_width, 0) => (target_width, height * target_width / width),
+            (0, target_height) => (width * target_height / height, target_height),
+            target => target,
+        };
+        let target_width = target_width.max(1).min(MAX_IMAGE_DIMENSION);
+        let target_height = target_height.max(1).min(MAX_IMAGE_DIMENSION);
+        let x_offset = (self.cell_x_offset as usize).min(cell_width - 1);
+        let y_offset = (self.cell_y_offset as usize).min(cell_height - 1);
+
+        if (left, top, width, height) == (0, 0, image.width(), image.height())
+            && (target_width, target_height) == (width, height)
+            && (x_offset, y_offset) == (0, 0)
+        {
+            return Ok(Rc::clone(image));
+        }
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for row in top..top + height {
+            let start = (row * image.width() + left) * 4;
+            pixels.extend_from_slice(&image.data()[start..start + width * 4]);
+        }
+
+        if (target_width, target_height) != (width, height) {
+            let buffer = image_codec::RgbaImage::from_raw(width as u32, height as u32, pixels)
+                .ok_or_else(|| format_err!("EINVAL:failed to scale the image"))?;
+            pixels = image_codec::imageops::resize(
+                &buffer,
+                target_width as u32,
+                target_height as u32,
+                image_codec::FilterType::Triangle,
+            )
+            .into_raw();
+        }
+
+        if (x_offset, y_offset) == (0, 0) {
+            return Ok(Rc::new(ImageData::with_rgba(target_width, target_height, pixels)));
+        }
+        // The offset is left transparent
+        let padded_width = target_width + x_offset;
+        let padded_height = target_height + y_offset;
+        let mut padded = vec![0; padded_width * padded_height * 4];
+        for (row, src) in pixels.chunks(target_width * 4).enumerate() {
+            let start = ((row + y_offset) * padded_width + x_offset) * 4;
+            padded[start..start + src.len()].copy_from_slice(src);
+        }
+        Ok(Rc::new(ImageData::with_rgba(padded_width, padded_height, padded)))
+    }
+
+    /// The response to send once the command has been carried out,
+    /// or None if the application didn't ask for one.  Only commands
+    /// that identify an image are answered.
+    pub fn response(&self, result: &Result<(), Error>) -> Option<Vec<u8>> {
+        if self.action == b'd' || (self.image_id == 0 && self.image_number == 0) {
+            return None;
+        }
+        let message = match *result {
+            Ok(()) if self.quiet >= 1 => return None,
+            Ok(()) => "OK".to_string(),
+            Err(_) if self.quiet >= 2 => return None,
+            Err(ref err) => err.to_string(),
+        };
+
+        let mut keys = Vec::new();
+        if self.image_id != 0 {
+            keys.push(format!("i={}", self.image_id));
+        }
+        if self.image_number != 0 {
+            keys.push(format!("I={}", self.image_number));
+        }
+        if self.placement_id != 0 {
+            keys.push(format!("p={}", self.placement_id));
+        }
+        Some(format!("\x1b_G{};{}\x1b\\", keys.join(","), message).into_bytes())
+    }
+}
+
+/// Report an I/O error in the form that the protocol uses
+fn io_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => format_err!("ENOENT:{}", err),
+        io::ErrorKind::PermissionDenied => format_err!("EPERM:{}", err),
+        _ => format_err!("EBADF:{}", err),
+    }
+}
+
+/// Returns true if the path is that of a file that the protocol may
+/// delete after reading it
+fn is_temp_file(path: &Path) -> bool {
+    let in_temp_dir = [env::temp_dir(), PathBuf::from("/tmp"), PathBuf::from("/dev/shm")]
+        .iter()
+        .filter_map(|dir| fs::canonicalize(dir).ok())
+        .any(|dir| path.starts_with(dir));
+    in_temp_dir && path.to_string_lossy().contains(TEMP_FILE_MARKER)
+}
+
+/// Returns true if the path is in one of the filesystems that the
+/// kernel provides, such as /proc, whose files may never end or may
+/// expose the state of the system.  Shared memory is allowed.
+fn is_special_file(path: &Path) -> bool {
+    ["/proc", "/sys", "/dev"].iter().any(|dir| path.starts_with(dir))
+        && !path.starts_with("/dev/shm")
+}
+
+/// Decompress zlib data, giving up if it expands too far
+fn inflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut stream = InflateStream::from_zlib();
+    let mut decompressed = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (consumed, output) =
+            stream.update(&data[pos..]).map_err(|err| format_err!("EINVAL:{}", err))?;
+        if consumed == 0 && output.is_empty() {
+            break;
+        }
+        decompressed.extend_from_slice(output);
+        if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+            bail!("EFBIG:the image data is too large");
+        }
+        pos += consumed;
+    }
+    Ok(decompressed)
+}
+
+/// An image that has been transmitted, which can be placed again
+struct StoredImage {
+    /// The image number given when it was transmitted, if any
+    number: u32,
+    /// Orders the images by when they were transmitted, so that the
+    /// oldest can be evicted first
+    serial: usize,
+    image: Rc<ImageData>,
+}
+
+/// The images transmitted with the kitty graphics protocol, by id
+#[derive(Default)]
+pub(crate) struct ImageStore {
+    images: HashMap<u32, StoredImage>,
+    /// How many bytes of pixels the images hold
+    size: usize,
+    next_serial: usize,
+    /// The last id picked for an image that was given a number
+    last_id: u32,
+}
+
+impl ImageStore {
+    /// Store an image, replacing any that has the same id.  If the id
+    /// is 0, an unused id is picked.  Returns the id of the image.
+    pub fn insert(&mut self, id: u32, number: u32, image: Rc<ImageData>) -> u32 {
+        let id = if id == 0 { self.unused_id() } else { id };
+        self.remove(id);
+        self.size += image.data().len();
+        let serial = self.next_serial();
+        self.images.insert(id, StoredImage { number, serial, image });
+
+        while self.size > MAX_STORED_IMAGE_BYTES && self.images.len() > 1 {
+            let oldest =
+                self.images.iter().min_by_key(|&(_, stored)| stored.serial).map(|(&id, _)| id);
+            if let Some(oldest) = oldest {
+                self.remove(oldest);
+            }
+        }
+        id
+    }
+
+    fn unused_id(&mut self) -> u32 {
+        loop {
+            self.last_id = self.last_id.wrapping_add(1);
+            if self.last_id != 0 && !self.images.contains_key(&self.last_id) {
+                return self.last_id;
+            }
+        }
+    }
+
+    /// Returns a number that hasn't been returned before, which
+    /// distinguishes placements that have the same ids
+    pub fn next_serial(&mut self) -> usize {
+        self.next_serial += 1;
+        self.next_serial
+    }
+
+    pub fn get(&self, id: u32) -> Option<Rc<ImageData>> {
+        self.images.get(&id).map(|stored| Rc::clone(&stored.image))
+    }
+
+    /// Returns the id of the most recently transmitted image that was
+    /// given the number
+    pub fn find_number(&self, number: u32) -> Option<u32> {
+        self.images
+            .iter()
+            .filter(|&(_, stored)| stored.number == number)
+            .max_by_key(|&(_, stored)| stored.serial)
+            .map(|(&id, _)| id)
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(stored) = self.images.remove(&id) {
+            self.size -= stored.image.data().len();
+        }
+    }
+
+    /// Remove the images whose ids lie between `min` and `max` inclusive
+    pub fn remove_range(&mut self, min: u32, max: u32) {
+        let remove: Vec<u32> =
+            self.images.keys().cloned().filter(|&id| id >= min && id <= max).collect();
+        for id in remove {
+            self.remove(id);
+        }
+    }
+
+    /// Remove all of the images.  The serials carry on counting so that
+    /// they remain unique.
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.size = 0;
+        self.last_id = 0;
+    }
+}
@@ -3,7 +3,13 @@
 extern crate failure;
 #[macro_use]
 extern crate bitflags;
+extern crate base64;
+// Renamed so as not to clash with our own image module
+extern crate image as image_codec;
+extern crate inflate;
+extern crate libc;
 extern crate palette;
+extern crate regex;
 extern crate serde;
 extern crate unicode_segmentation;
 extern crate unicode_width;
@@ -37,9 +43,18 @@ pub use screen::*;
 pub mod selection;
 use selection::{SelectionCoordinate, SelectionRange};
 
+pub mod search;
+pub use search::*;
+
 pub mod hyperlink;
 use hyperlink::Hyperlink;
 
+pub mod clipboard;
+pub use clipboard::*;
+
+pub mod image;
+pub use image::*;
+
 pub mod terminal;
 pub use terminal::*;
 
@@ -95,18 +110,35 @@ pub struct CursorPosition {
     pub y: VisibleRowIndex,
 }
 
+mod charset;
+use self::charset::*;
 pub mod color;
 mod csi;
 use self::csi::*;
+mod mouse;
+use self::mouse::*;
+mod sixel;
+use self::sixel::*;
+mod iterm;
+pub use self::iterm::DEFAULT_MAX_IMAGE_SIZE;
+use self::iterm::*;
+mod kitty;
+use self::kitty::*;
+mod keyboard;
+use self::keyboard::*;
 
 #[cfg(test)]
 mod test;
 
-/// The response we given when queries for device attributes.
-/// This particular string says "we are a VT102".
-/// TODO: Consider VT220 extended response which can advertise
-/// certain feature sets.
-pub const DEVICE_IDENT: &[u8] = b"\x1b[?6c";
+/// The response we give when queried for primary device attributes.
+/// 62 says that we are a VT220 class terminal, and is followed by the
+/// optional features that we support: 4 for sixel graphics, 22 for
+/// ANSI color and 52 for clipboard access via OSC 52.
+pub const DEVICE_IDENT: &[u8] = b"\x1b[?62;4;22;52c";
+
+/// The name and version reported in response to XTVERSION.
+/// This crate's version is kept the same as that of miro.
+pub const TERMINAL_VERSION: &str = concat!("miro ", env!("CARGO_PKG_VERSION"));
 
 #[allow(dead_code)]
 pub const CSI: &[u8] = b"\x1b[";
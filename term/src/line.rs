@@ -1,13 +1,39 @@
+use std::mem;
 use std::ops::Range;
 use std::str;
 
 use super::*;
 
+bitflags! {
+    /// Marks recorded from the OSC 133 (FinalTerm) semantic prompt
+    /// sequences that shells emit around their prompts and commands
+    #[derive(Default)]
+    pub struct SemanticMarks: u8 {
+        /// A prompt starts on this line (OSC 133 ; A)
+        const PROMPT = 1;
+        /// The command input starts on this line (OSC 133 ; B)
+        const INPUT = 2;
+        /// The command output starts on this line (OSC 133 ; C)
+        const OUTPUT = 4;
+        /// The command finished on this line (OSC 133 ; D)
+        const COMMAND_END = 8;
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Line {
     pub cells: Vec<Cell>,
     dirty: bool,
     has_hyperlink: bool,
+    /// true if the text on this line continues on the next line
+    /// because it was automatically wrapped, rather than ending
+    /// with an explicit newline.
+    wrapped: bool,
+    marks: SemanticMarks,
+    /// The exit status that accompanied the COMMAND_END mark
+    exit_status: Option<i32>,
+    /// The parts of images that are displayed on this line
+    images: Vec<ImageSlice>,
 }
 
 /// A CellCluster is another representation of a Line.
@@ -47,7 +73,30 @@ impl Line {
     pub fn new(cols: usize) -> Line {
         let mut cells = Vec::with_capacity(cols);
         cells.resize(cols, Default::default());
-        Line { cells, dirty: true, has_hyperlink: false }
+        Line {
+            cells,
+            dirty: true,
+            has_hyperlink: false,
+            wrapped: false,
+            marks: SemanticMarks::default(),
+            exit_status: None,
+            images: Vec::new(),
+        }
+    }
+
+    /// Create a line from a set of cells, as part of rewrapping
+    /// the screen contents.
+    pub(crate) fn from_cells(cells: Vec<Cell>, wrapped: bool) -> Line {
+        let has_hyperlink = cells.iter().any(|c| c.attrs.hyperlink.is_some());
+        Line {
+            cells,
+            dirty: true,
+            has_hyperlink,
+            wrapped,
+            marks: SemanticMarks::default(),
+            exit_status: None,
+            images: Vec::new(),
+        }
     }
 
     pub fn reset(&mut self, width: usize) {
@@ -56,6 +105,17 @@ impl Line {
         for cell in self.cells.iter_mut() {
             cell.reset();
         }
+        self.wrapped = false;
+        self.marks = SemanticMarks::default();
+        self.exit_status = None;
+        self.images.clear();
+    }
+
+    /// Returns true if all of the cells are blank with default attributes
+    /// and no image is displayed
+    pub(crate) fn is_blank(&self) -> bool {
+        let blank = Cell::default();
+        self.images.is_empty() && self.cells.iter().all(|c| *c == blank)
     }
 
     /// Recompose line into the corresponding utf8 string.
@@ -124,7 +184,15 @@ impl Line {
             cells.push(Cell::new(sub, attrs))
         }
 
-        Line { cells, dirty: true, has_hyperlink: false }
+        Line {
+            cells,
+            dirty: true,
+            has_hyperlink: false,
+            wrapped: false,
+            marks: SemanticMarks::default(),
+            exit_status: None,
+            images: Vec::new(),
+        }
     }
 
     #[inline]
@@ -151,6 +219,75 @@ impl Line {
     pub fn set_has_hyperlink(&mut self, has: bool) {
         self.has_hyperlink = has;
     }
+
+    /// Returns true if the text on this line was automatically wrapped
+    /// onto the next line, rather than ending with a newline.
+    #[inline]
+    pub fn is_wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    #[inline]
+    pub(crate) fn set_wrapped(&mut self, wrapped: bool) {
+        self.wrapped = wrapped;
+    }
+
+    #[inline]
+    pub fn semantic_marks(&self) -> SemanticMarks {
+        self.marks
+    }
+
+    /// Returns the exit status reported when the command that ended
+    /// on this line finished
+    #[inline]
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    pub fn add_semantic_mark(&mut self, mark: SemanticMarks) {
+        self.marks.insert(mark);
+    }
+
+    pub fn set_exit_status(&mut self, status: Option<i32>) {
+        self.exit_status = status;
+    }
+
+    /// Returns the image slices on this line, in the order in which
+    /// they were added, so that later images are drawn over earlier ones
+    #[inline]
+    pub fn images(&self) -> &[ImageSlice] {
+        &self.images
+    }
+
+    /// Add an image slice.  Any slices that lie entirely beneath
+    /// it are discarded, except for kitty graphics placements, which
+    /// may overlap until the application deletes them.
+    pub fn add_image(&mut self, slice: ImageSlice) {
+        if slice.placement.is_none() {
+            self.images.retain(|s| {
+                s.placement.is_some() || s.x < slice.x || s.x + s.cols > slice.x + slice.cols
+            });
+        }
+        self.images.push(slice);
+    }
+
+    /// Remove the image slices that match a predicate.  Returns true
+    /// if any were removed.
+    pub(crate) fn remove_images<F: Fn(&ImageSlice) -> bool>(&mut self, remove: F) -> bool {
+        let len = self.images.len();
+        self.images.retain(|s| !remove(s));
+        len != self.images.len()
+    }
+
+    /// Remove the images that cover part of the range of columns
+    pub fn clear_images(&mut self, cols: &Range<usize>) {
+        self.images.retain(|s| !s.intersects(cols));
+    }
+
+    /// Remove and return the image slices, as part of rewrapping
+    pub(crate) fn take_images(&mut self) -> Vec<ImageSlice> {
+        mem::replace(&mut self.images, Vec::new())
+    }
 }
 
 impl<'a> From<&'a str> for Line {
@@ -0,0 +1,117 @@
+//! Encoding mouse events for applications that enable mouse reporting.
+//! See https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+
+use super::*;
+
+/// Which mouse events are reported to the application
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MouseTracking {
+    /// Mouse events are handled by the terminal itself
+    Off,
+    /// X10 compatibility (?9): button presses only, without modifiers
+    X10,
+    /// Normal tracking (?1000): button presses and releases
+    Normal,
+    /// Button-event tracking (?1002): also motion while a button is held
+    ButtonEvent,
+    /// Any-event tracking (?1003): also motion without a button held
+    AnyEvent,
+}
+
+impl MouseTracking {
+    /// Returns true if an event of this kind should be reported.
+    /// `button` is the button that is held down during a Move.
+    pub fn reports(self, kind: MouseEventKind, button: MouseButton) -> bool {
+        match (self, kind) {
+            (MouseTracking::Off, _) => false,
+            (_, MouseEventKind::Press) => true,
+            (MouseTracking::X10, _) => false,
+            (_, MouseEventKind::Release) => true,
+            (MouseTracking::Normal, MouseEventKind::Move) => false,
+            (MouseTracking::ButtonEvent, MouseEventKind::Move) => button != MouseButton::None,
+            (MouseTracking::AnyEvent, MouseEventKind::Move) => true,
+        }
+    }
+}
+
+/// How mouse reports are encoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MouseEncoding {
+    /// `CSI M` followed by three bytes; coordinates are limited to 223
+    X10,
+    /// ?1005: like X10, but the values are sent as UTF-8 characters
+    /// which raises the limit to 2015
+    Utf8,
+    /// ?1006: `CSI < b ; x ; y M`, or `m` for a release
+    Sgr,
+    /// ?1015: `CSI b ; x ; y M` with the X10 button value
+    Urxvt,
+}
+
+/// Encode a mouse report.  `button` is the button that was pressed or
+/// released, or that is held down during a Move.
+/// Returns None if the position cannot be represented in this encoding.
+pub fn encode_mouse_report(
+    encoding: MouseEncoding,
+    tracking: MouseTracking,
+    event: &MouseEvent,
+    button: MouseButton,
+) -> Option<Vec<u8>> {
+    let release = event.kind == MouseEventKind::Release;
+
+    let mut code = match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::WheelUp => 64,
+        MouseButton::WheelDown => 65,
+        MouseButton::None => 3,
+    };
+    // Only SGR is able to say which button was released
+    if release && encoding != MouseEncoding::Sgr {
+        code = 3;
+    }
+    if event.kind == MouseEventKind::Move {
+        code += 32;
+    }
+    if tracking != MouseTracking::X10 {
+        if event.modifiers.contains(KeyModifiers::SHIFT) {
+            code += 4;
+        }
+        if event.modifiers.intersects(KeyModifiers::ALT | KeyModifiers::META) {
+            code += 8;
+        }
+        if event.modifiers.contains(KeyModifiers::CTRL) {
+            code += 16;
+        }
+    }
+
+    let x = event.x + 1;
+    let y = event.y.max(0) as usize + 1;
+
+    match encoding {
+        MouseEncoding::X10 => {
+            if x > 223 || y > 223 {
+                return None;
+            }
+            let mut report = b"\x1b[M".to_vec();
+            report.extend_from_slice(&[32 + code as u8, 32 + x as u8, 32 + y as u8]);
+            Some(report)
+        }
+        MouseEncoding::Utf8 => {
+            if x > 2015 || y > 2015 {
+                return None;
+            }
+            let mut report = "\x1b[M".to_string();
+            for &value in &[code, x, y] {
+                report.push(::std::char::from_u32(32 + value as u32)?);
+            }
+            Some(report.into_bytes())
+        }
+        MouseEncoding::Sgr => {
+            let terminator = if release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, terminator).into_bytes())
+        }
+        MouseEncoding::Urxvt => Some(format!("\x1b[{};{};{}M", 32 + code, x, y).into_bytes()),
+    }
+}
@@ -1,5 +1,6 @@
 use super::*;
 use std::collections::VecDeque;
+use std::mem;
 
 /// Holds the model of a screen.  This can either be the primary screen
 /// which includes lines of scrollback text, or the alternate screen
@@ -24,6 +25,11 @@ pub struct Screen {
     pub physical_rows: usize,
     /// Physical, visible width of the screen
     pub physical_cols: usize,
+
+    /// The number of rows that have scrolled off the top of the visible
+    /// screen since it was created.  This allows following a row as the
+    /// screen scrolls.
+    pub rows_scrolled: usize,
 }
 
 impl Screen {
@@ -36,7 +42,7 @@ impl Screen {
             lines.push_back(Line::new(physical_cols));
         }
 
-        Screen { lines, scrollback_size, physical_rows, physical_cols }
+        Screen { lines, scrollback_size, physical_rows, physical_cols, rows_scrolled: 0 }
     }
 
     /// Resize the physical, viewable portion of the screen
@@ -57,6 +63,165 @@ impl Screen {
         self.physical_cols = physical_cols;
     }
 
+    /// Resize the physical, viewable portion of the screen, rewrapping
+    /// the lines that were wrapped by the terminal so that their text
+    /// flows into the new width.  `cursor` is the position of the cursor
+    /// prior to the resize; the returned value is its new position,
+    /// which remains attached to the same character.
+    pub fn resize_and_rewrap(
+        &mut self,
+        physical_rows: usize,
+        physical_cols: usize,
+        cursor: CursorPosition,
+    ) -> CursorPosition {
+        let cursor_y = cursor.y.max(0).min(self.physical_rows as VisibleRowIndex - 1);
+        let mut cursor_phys = self.phys_row(cursor_y);
+        let mut cursor_x = cursor.x;
+
+        if physical_cols != self.physical_cols {
+            let (phys, x) = self.rewrap_lines(physical_cols, cursor_phys, cursor_x);
+            cursor_phys = phys;
+            cursor_x = x;
+        }
+
+        // Blank lines below the cursor are just the unused portion of the
+        // screen; drop them rather than letting them push the text above
+        // the cursor into the scrollback when the screen gets shorter.
+        while self.lines.len() > cursor_phys + 1 && self.lines[self.lines.len() - 1].is_blank() {
+            self.lines.pop_back();
+        }
+        while self.lines.len() < physical_rows {
+            self.lines.push_back(Line::new(physical_cols));
+        }
+        // If the cursor would be above the visible area, sacrifice the
+        // lines at the bottom so that it remains in view.
+        while self.lines.len() > cursor_phys + physical_rows {
+            self.lines.pop_back();
+        }
+
+        let max_allowed = physical_rows + self.scrollback_size;
+        if self.lines.len() > max_allowed {
+            let excess = self.lines.len() - max_allowed;
+            self.lines.drain(0..excess);
+            cursor_phys -= excess;
+        }
+
+        for line in self.lines.iter_mut() {
+            line.set_dirty();
+        }
+        self.physical_rows = physical_rows;
+        self.physical_cols = physical_cols;
+
+        CursorPosition {
+            x: cursor_x,
+            y: (cursor_phys - (self.lines.len() - physical_rows)) as VisibleRowIndex,
+        }
+    }
+
+    /// Join the wrapped lines back into logical lines and split them
+    /// up again at the new width.  Returns the physical row and column
+    /// of the cursor within the new set of lines.
+    fn rewrap_lines(
+        &mut self,
+        physical_cols: usize,
+        cursor_phys: PhysRowIndex,
+        cursor_x: usize,
+    ) -> (PhysRowIndex, usize) {
+        let mut rewrapped = VecDeque::with_capacity(self.lines.capacity());
+        let mut logical: Vec<Cell> = Vec::new();
+        let mut cursor_offset = None;
+        let mut cursor = (0, 0);
+        // The semantic marks and images of the logical line are all
+        // attached to its first line
+        let mut marks = SemanticMarks::default();
+        let mut exit_status = None;
+        let mut images = Vec::new();
+
+        let num_lines = self.lines.len();
+        for (idx, mut line) in self.lines.drain(..).enumerate() {
+            if idx == cursor_phys {
+                cursor_offset = Some(logical.len() + cursor_x);
+            }
+            let wrapped = line.is_wrapped();
+            marks |= line.semantic_marks();
+            exit_status = exit_status.or(line.exit_status());
+            images.append(&mut line.take_images());
+            logical.append(&mut line.cells);
+            if !wrapped || idx == num_lines - 1 {
+                let offset = cursor_offset.take();
+                let cells = logical;
+                logical = Vec::new();
+                let first_idx = rewrapped.len();
+                if let Some(pos) = Self::wrap_cells(&mut rewrapped, cells, physical_cols, offset) {
+                    cursor = pos;
+                }
+                let first = &mut rewrapped[first_idx];
+                first.add_semantic_mark(mem::replace(&mut marks, SemanticMarks::default()));
+                first.set_exit_status(exit_status.take());
+                for slice in images.drain(..) {
+                    first.add_image(slice);
+                }
+            }
+        }
+
+        self.lines = rewrapped;
+        cursor
+    }
+
+    /// Split the cells of a logical line into lines of the specified
+    /// width and append them to `lines`.  Wide characters are not split
+    /// across lines.  If `cursor_offset` is the index of the cursor within
+    /// `cells`, returns its physical row and column.
+    fn wrap_cells(
+        lines: &mut VecDeque<Line>,
+        mut cells: Vec<Cell>,
+        physical_cols: usize,
+        cursor_offset: Option<usize>,
+    ) -> Option<(PhysRowIndex, usize)> {
+        // Trailing blanks are just the unused portion of the final line,
+        // but we need to retain the cells up to the cursor.
+        let blank = Cell::default();
+        let min_len = cursor_offset.map_or(0, |offset| offset + 1);
+        while cells.len() > min_len && cells.last() == Some(&blank) {
+            cells.pop();
+        }
+        if cells.len() < min_len {
+            cells.resize(min_len, Cell::default());
+        }
+
+        let mut cursor = None;
+        let mut current = Vec::with_capacity(physical_cols);
+        for (idx, cell) in cells.into_iter().enumerate() {
+            // The padding cells that follow a wide character have no width
+            // and must stay on the same line as that character.
+            let width = cell.width();
+            if width > 0 && !current.is_empty() && current.len() + width > physical_cols {
+                current.resize(physical_cols.max(current.len()), Cell::default());
+                let cells = mem::replace(&mut current, Vec::with_capacity(physical_cols));
+                lines.push_back(Line::from_cells(cells, true));
+            }
+            if cursor_offset == Some(idx) {
+                cursor = Some((lines.len(), current.len()));
+            }
+            current.push(cell);
+        }
+        current.resize(physical_cols.max(current.len()), Cell::default());
+        lines.push_back(Line::from_cells(current, false));
+
+        cursor
+    }
+
+    /// Change the maximum number of lines of scrollback.
+    /// If the scrollback is shrinking, the oldest lines are discarded.
+    pub fn set_scrollback_size(&mut self, scrollback_size: usize) {
+        self.scrollback_size = scrollback_size;
+        let max_allowed = self.physical_rows + scrollback_size;
+        if self.lines.len() > max_allowed {
+            let excess = self.lines.len() - max_allowed;
+            self.lines.drain(0..excess);
+        }
+    }
+
     /// Get mutable reference to a line, relative to start of scrollback.
     /// Sets the line dirty.
     #[inline]
@@ -131,12 +296,18 @@ impl Screen {
         let line_idx = self.phys_row(y);
         let line = self.line_mut(line_idx);
         let max_col = line.cells.len();
-        for x in cols {
+        for x in cols.clone() {
             if x >= max_col {
                 break;
             }
             line.cells[x].reset();
         }
+        line.clear_images(&cols);
+        // Clearing to the end of the line means that its text no longer
+        // continues on the next line.
+        if cols.end >= max_col {
+            line.set_wrapped(false);
+        }
     }
 
     /// Translate a VisibleRowIndex into a PhysRowIndex.  The resultant index
@@ -210,6 +381,9 @@ impl Screen {
         };
 
         let remove_idx = if scroll_region.start == 0 { 0 } else { phys_scroll.start };
+        if scroll_region.start == 0 {
+            self.rows_scrolled = self.rows_scrolled.wrapping_add(num_rows);
+        }
 
         // To avoid thrashing the heap, prefer to move lines that were
         // scrolled off the top and re-use them at the bottom.
@@ -247,6 +421,109 @@ impl Screen {
         }
     }
 
+    /// Insert num_cells blank cells at column x on the specified row.
+    /// The cells from x up to the right_margin shift to the right and
+    /// those that are pushed beyond the margin are discarded.
+    pub fn insert_cells(
+        &mut self,
+        x: usize,
+        y: VisibleRowIndex,
+        num_cells: usize,
+        right_margin: usize,
+    ) {
+        let line_idx = self.phys_row(y);
+        let line = self.line_mut(line_idx);
+        let right_margin = right_margin.min(line.cells.len());
+        if x >= right_margin {
+            return;
+        }
+        let num_cells = num_cells.min(right_margin - x);
+        line.cells.drain(right_margin - num_cells..right_margin);
+        for _ in 0..num_cells {
+            line.cells.insert(x, Cell::default());
+        }
+    }
+
+    /// Delete num_cells cells starting at column x on the specified row.
+    /// The cells to their right, up to the right_margin, shift left and
+    /// blank cells are introduced at the margin.
+    pub fn delete_cells(
+        &mut self,
+        x: usize,
+        y: VisibleRowIndex,
+        num_cells: usize,
+        right_margin: usize,
+    ) {
+        let line_idx = self.phys_row(y);
+        let line = self.line_mut(line_idx);
+        let right_margin = right_margin.min(line.cells.len());
+        if x >= right_margin {
+            return;
+        }
+        let num_cells = num_cells.min(right_margin - x);
+        line.cells.drain(x..x + num_cells);
+        for _ in 0..num_cells {
+            line.cells.insert(right_margin - num_cells, Cell::default());
+        }
+    }
+
+    /// Copy the cells in the `cols` range from the `src` line to the
+    /// `dest` line, or blank them in `dest` if `src` is None.
+    fn copy_cells(&mut self, src: Option<PhysRowIndex>, dest: PhysRowIndex, cols: &Range<usize>) {
+        let cells: Vec<Cell> = match src {
+            Some(src) => {
+                let line = &self.lines[src];
+                cols.clone().map(|x| line.cells.get(x).cloned().unwrap_or_default()).collect()
+            }
+            None => vec![Cell::default(); cols.end - cols.start],
+        };
+        let line = self.line_mut(dest);
+        if line.cells.len() < cols.end {
+            line.cells.resize(cols.end, Cell::default());
+        }
+        line.cells[cols.clone()].clone_from_slice(&cells);
+    }
+
+    /// Like scroll_up, but only moves the cells that lie within the left
+    /// and right margins.  The cells outside of the margins are left in
+    /// place and nothing is moved into the scrollback.
+    pub fn scroll_up_within_margins(
+        &mut self,
+        scroll_region: &Range<VisibleRowIndex>,
+        left_and_right_margins: &Range<usize>,
+        num_rows: usize,
+    ) {
+        debug!(
+            "scroll_up_within_margins {:?} {:?} {}",
+            scroll_region, left_and_right_margins, num_rows
+        );
+        let phys_scroll = self.phys_range(scroll_region);
+        for y in phys_scroll.clone() {
+            let src = y + num_rows;
+            let src = if src < phys_scroll.end { Some(src) } else { None };
+            self.copy_cells(src, y, left_and_right_margins);
+        }
+    }
+
+    /// Like scroll_down, but only moves the cells that lie within the
+    /// left and right margins.
+    pub fn scroll_down_within_margins(
+        &mut self,
+        scroll_region: &Range<VisibleRowIndex>,
+        left_and_right_margins: &Range<usize>,
+        num_rows: usize,
+    ) {
+        debug!(
+            "scroll_down_within_margins {:?} {:?} {}",
+            scroll_region, left_and_right_margins, num_rows
+        );
+        let phys_scroll = self.phys_range(scroll_region);
+        for y in phys_scroll.clone().rev() {
+            let src = if y >= phys_scroll.start + num_rows { Some(y - num_rows) } else { None };
+            self.copy_cells(src, y, left_and_right_margins);
+        }
+    }
+
     /// ---------
     /// |
     /// |--- top
@@ -0,0 +1,104 @@
+//! Searching the screen and scrollback for text
+use super::*;
+use regex::Regex;
+use selection::{SelectionCoordinate, SelectionRange};
+
+/// What to look for when searching
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Match the text exactly
+    Literal(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Compile a regular expression pattern
+    pub fn regex(pattern: &str) -> Result<Pattern, Error> {
+        Ok(Pattern::Regex(Regex::new(pattern)?))
+    }
+
+    /// Returns the byte ranges of the non-empty matches in `haystack`
+    fn find_all(&self, haystack: &str) -> Vec<Range<usize>> {
+        match self {
+            &Pattern::Literal(ref literal) => {
+                if literal.is_empty() {
+                    return Vec::new();
+                }
+                haystack
+                    .match_indices(literal.as_str())
+                    .map(|(start, text)| start..start + text.len())
+                    .collect()
+            }
+            &Pattern::Regex(ref regex) => regex
+                .find_iter(haystack)
+                .filter(|m| m.start() != m.end())
+                .map(|m| m.start()..m.end())
+                .collect(),
+        }
+    }
+}
+
+/// The location of a match.  The coordinates are relative to the
+/// top of the visible screen in the same way as the selection,
+/// with negative rows referring to the scrollback.
+/// The end coordinate is inclusive.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SearchMatch {
+    pub start: SelectionCoordinate,
+    pub end: SelectionCoordinate,
+}
+
+impl SearchMatch {
+    /// Yields the range of columns covered by this match on the
+    /// specified row.
+    pub fn cols_for_row(&self, row: ScrollbackOrVisibleRowIndex) -> Range<usize> {
+        SelectionRange { start: self.start, end: self.end }.cols_for_row(row)
+    }
+}
+
+/// Describes the columns of a line that are part of a search match
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SearchHighlight {
+    pub cols: Range<usize>,
+    /// true if this is part of the current match
+    pub current: bool,
+}
+
+/// Search the lines of a screen, including its scrollback.
+/// Lines that were wrapped by the terminal are joined together
+/// so that a match can span the wrap point.
+pub fn search_screen(screen: &Screen, pattern: &Pattern) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+    let first_visible = screen.lines.len() - screen.physical_rows;
+
+    let mut text = String::new();
+    // For each byte of `text`, the position and width of the cell
+    // that it came from
+    let mut cells: Vec<(SelectionCoordinate, usize)> = Vec::new();
+
+    for (idx, line) in screen.lines.iter().enumerate() {
+        let y = idx as ScrollbackOrVisibleRowIndex - first_visible as ScrollbackOrVisibleRowIndex;
+        for (x, cell) in line.cells.iter().enumerate() {
+            text.push_str(cell.str());
+            for _ in 0..cell.str().len() {
+                cells.push((SelectionCoordinate { x, y }, cell.width()));
+            }
+        }
+
+        if line.is_wrapped() && idx + 1 < screen.lines.len() {
+            continue;
+        }
+
+        for range in pattern.find_all(&text) {
+            let (start, _) = cells[range.start];
+            // Extend the match over the padding of a double width character
+            let (end, width) = cells[range.end - 1];
+            let end = SelectionCoordinate { x: end.x + width.max(1) - 1, y: end.y };
+            matches.push(SearchMatch { start, end });
+        }
+        text.clear();
+        cells.clear();
+    }
+
+    matches
+}
@@ -0,0 +1,242 @@
+//! Decoding sixel graphics, which arrive as the payload of
+//! `DCS P1 ; P2 ; P3 q`.
+//! See https://vt100.net/docs/vt3xx-gp/chapter14.html
+
+use super::*;
+
+/// Images are clipped to this many pixels in each direction
+const MAX_SIXEL_SIZE: usize = 4096;
+
+/// How many color registers are available
+const NUM_COLOR_REGISTERS: usize = 1024;
+
+/// The default color registers of the VT340, as RGB percentages
+const VT340_COLORS: [(u32, u32, u32); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+/// Pixels that have been drawn are opaque, so a zero alpha means
+/// that the pixel hasn't been drawn.
+const UNDRAWN: [u8; 4] = [0, 0, 0, 0];
+
+/// Accumulates the pixels of a sixel image as its data is received
+pub(crate) struct SixelBuilder {
+    /// P2 == 1 leaves the pixels that aren't drawn transparent;
+    /// otherwise they take the color of register 0
+    transparent_background: bool,
+    registers: Vec<[u8; 4]>,
+    color: [u8; 4],
+    /// The pixels drawn so far, row by row; the rows are only as
+    /// long as their rightmost drawn pixel
+    rows: Vec<Vec<[u8; 4]>>,
+    /// The width of the image, which grows as sixels are added
+    width: usize,
+    /// The size given by the raster attributes, if any
+    raster_size: (usize, usize),
+    /// How many rows of pixels each sixel bit covers
+    aspect: usize,
+    x: usize,
+    y: usize,
+    /// The `!`, `#` or `"` command whose parameters are being
+    /// received, if any
+    command: Option<u8>,
+    params: Vec<u32>,
+}
+
+impl SixelBuilder {
+    /// Begin an image.  `params` are the parameters of the DCS sequence.
+    /// The aspect ratio in P1 is ignored in favor of the raster
+    /// attributes, as it is by other modern terminals.
+    pub fn new(params: &[i64]) -> SixelBuilder {
+        let registers = (0..NUM_COLOR_REGISTERS)
+            .map(|idx| match VT340_COLORS.get(idx) {
+                Some(&(r, g, b)) => [percent(r), percent(g), percent(b), 0xff],
+                None => [0, 0, 0, 0xff],
+            })
+            .collect();
+        SixelBuilder {
+            transparent_background: params.get(1) == Some(&1),
+            registers,
+            color: [0, 0, 0, 0xff],
+            rows: Vec::new(),
+            width: 0,
+            raster_size: (0, 0),
+            aspect: 1,
+            x: 0,
+            y: 0,
+            command: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// Process a byte of the sixel data
+    pub fn put(&mut self, b: u8) {
+        if self.command.is_some() {
+            match b {
+                b'0'...b'9' => {
+                    if let Some(param) = self.params.last_mut() {
+                        *param = param.saturating_mul(10).saturating_add((b - b'0') as u32);
+                    }
+                    return;
+                }
+                b';' => {
+                    self.params.push(0);
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        let repeat = match self.command.take() {
+            Some(b'!') => self.params[0].max(1) as usize,
+            Some(b'#') => {
+                self.color_introducer();
+                1
+            }
+            Some(b'"') => {
+                self.raster_attributes();
+                1
+            }
+            _ => 1,
+        };
+
+        match b {
+            b'?'...b'~' => self.sixel(b - b'?', repeat),
+            // Graphics carriage return
+            b'$' => self.x = 0,
+            // Graphics new line
+            b'-' => {
+                self.x = 0;
+                self.y = (self.y + 6 * self.aspect).min(MAX_SIXEL_SIZE);
+            }
+            b'!' | b'#' | b'"' => {
+                self.command = Some(b);
+                self.params = vec![0];
+            }
+            _ => {}
+        }
+    }
+
+    /// Draw a column of six pixels `repeat` times.  Bit 0 of `bits` is
+    /// the top pixel.
+    fn sixel(&mut self, bits: u8, repeat: usize) {
+        let start = self.x.min(MAX_SIXEL_SIZE);
+        let end = self.x.saturating_add(repeat).min(MAX_SIXEL_SIZE);
+        for bit in 0..6 {
+            if bits & (1 << bit) == 0 {
+                continue;
+            }
+            let top = self.y + bit * self.aspect;
+            for y in top..(top + self.aspect).min(MAX_SIXEL_SIZE) {
+                if self.rows.len() <= y {
+                    self.rows.resize(y + 1, Vec::new());
+                }
+                let row = &mut self.rows[y];
+                if row.len() < end {
+                    row.resize(end, UNDRAWN);
+                }
+                for pixel in &mut row[start..end] {
+                    *pixel = self.color;
+                }
+            }
+        }
+        self.x = end;
+        self.width = self.width.max(end);
+    }
+
+    /// `# Pc` selects a color register, and `# Pc ; Pu ; Px ; Py ; Pz`
+    /// also defines it.  Pu is 1 for HLS and 2 for RGB.
+    fn color_introducer(&mut self) {
+        let register = self.params[0] as usize % NUM_COLOR_REGISTERS;
+        if let &[_, space, x, y, z] = &self.params[..] {
+            let rgb = match space {
+                1 => Some(hls_to_rgb(x, y, z)),
+                2 => Some([percent(x), percent(y), percent(z), 0xff]),
+                _ => None,
+            };
+            if let Some(rgb) = rgb {
+                self.registers[register] = rgb;
+            }
+        }
+        self.color = self.registers[register];
+    }
+
+    /// `" Pan ; Pad ; Ph ; Pv` gives the pixel aspect ratio and the
+    /// size of the image
+    fn raster_attributes(&mut self) {
+        let params = &self.params;
+        if let (Some(&pan), Some(&pad)) = (params.get(0), params.get(1)) {
+            if pad > 0 {
+                self.aspect = ((pan / pad) as usize).max(1).min(MAX_SIXEL_SIZE);
+            }
+        }
+        if let (Some(&width), Some(&height)) = (params.get(2), params.get(3)) {
+            self.raster_size =
+                ((width as usize).min(MAX_SIXEL_SIZE), (height as usize).min(MAX_SIXEL_SIZE));
+        }
+    }
+
+    /// Produce the image, or None if nothing was drawn
+    pub fn finish(self) -> Option<ImageData> {
+        let width = self.width.max(self.raster_size.0);
+        let height = self.rows.len().max(self.raster_size.1);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let background = if self.transparent_background { UNDRAWN } else { self.registers[0] };
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = self.rows.get(y).and_then(|row| row.get(x)).cloned();
+                match pixel {
+                    Some(pixel) if pixel != UNDRAWN => data.extend_from_slice(&pixel),
+                    _ => data.extend_from_slice(&background),
+                }
+            }
+        }
+        Some(ImageData::with_rgba(width, height, data))
+    }
+}
+
+/// Convert a percentage to a color component
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Convert a sixel HLS color to RGB.  Unlike the usual HLS model,
+/// a hue of 0 degrees is blue rather than red.
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> [u8; 4] {
+    let hue = ((hue % 360 + 240) % 360) as f32 / 60.0;
+    let lightness = lightness.min(100) as f32 / 100.0;
+    let saturation = saturation.min(100) as f32 / 100.0;
+
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let component = |v: f32| ((v + m) * 255.0).round() as u8;
+    [component(r), component(g), component(b), 0xff]
+}
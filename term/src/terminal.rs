@@ -1,4 +1,6 @@
 use super::*;
+use std::mem;
+use vte::Perform;
 
 /// Represents the host of the terminal.
 /// Provides a means for sending data to the connected pty,
@@ -14,18 +16,77 @@ pub trait TerminalHost {
     /// Adjust the contents of the clipboard
     fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error>;
 
-    /// Change the title of the window
+    /// Change the title of the window.  An empty title means that
+    /// the window should revert to its default title.
     fn set_title(&mut self, title: &str);
 
+    /// Called when the application reports its working directory
+    /// via OSC 7
+    fn set_current_dir(&mut self, cwd: &str);
+
     /// Called when a URL is clicked
     fn click_link(&mut self, link: &Rc<Hyperlink>);
 }
 
+/// The longest OSC or APC string that we will collect.  Longer
+/// strings are discarded.
+const MAX_STRING_LEN: usize = 4 * 1024 * 1024;
+
+/// The longest DCS header that we will collect; any further
+/// parameters are ignored
+const MAX_DCS_HEADER_LEN: usize = 64;
+
+/// The kinds of control string that the Terminal collects itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringKind {
+    /// An Operating System Command, following `ESC ]`
+    Osc,
+    /// An Application Program Command, following `ESC _`
+    Apc,
+}
+
+/// Returns the longest that the string beginning with `data` may be.
+/// Images may exceed MAX_STRING_LEN, up to the configured size.
+fn string_limit(kind: StringKind, data: &[u8], max_image_size: usize) -> usize {
+    let image = match kind {
+        StringKind::Osc => data.starts_with(b"1337;File="),
+        StringKind::Apc => data.starts_with(b"G"),
+    };
+    if image {
+        // Leave room for the base64 encoding and the arguments
+        MAX_STRING_LEN.max(max_image_size / 3 * 4 + 4096)
+    } else {
+        MAX_STRING_LEN
+    }
+}
+
+/// vte limits OSC strings to 1024 bytes, which is too small for
+/// payloads such as OSC 52, and ignores APC strings altogether, so
+/// the Terminal collects them itself and uses vte for everything else.
+enum ControlString {
+    /// Not in an OSC or APC string
+    Inactive,
+    /// Collecting the bytes following `ESC ]` or `ESC _`
+    Collecting(StringKind, Vec<u8>),
+    /// The string exceeded its limit and is being ignored
+    Discarding,
+}
+
 pub struct Terminal {
     /// The terminal model/state
     state: TerminalState,
     /// Baseline terminal escape sequence parser
     parser: vte::Parser,
+    /// The OSC or APC string being collected, if any
+    string: ControlString,
+    /// true if the last byte fed to the parser was ESC
+    last_byte_was_esc: bool,
+    /// The parameter bytes of the DCS sequence whose header is being
+    /// received, if any
+    dcs_header: Option<Vec<u8>>,
+    /// true between `ESC P` and the end of the DCS string, where
+    /// `ESC ]` and `ESC _` don't begin strings for us to collect
+    in_dcs: bool,
 }
 
 impl Deref for Terminal {
@@ -52,6 +113,10 @@ pub(crate) enum AnswerBack {
     /// The application has requested that we change
     /// the terminal title, and here it is.
     TitleChanged(String),
+    /// The application wants to set or read the clipboard
+    Clipboard(ClipboardRequest),
+    /// The application has reported its working directory
+    CurrentDirChanged(String),
 }
 
 impl Terminal {
@@ -59,6 +124,10 @@ impl Terminal {
         Terminal {
             state: TerminalState::new(physical_rows, physical_cols, scrollback_size),
             parser: vte::Parser::new(),
+            string: ControlString::Inactive,
+            last_byte_was_esc: false,
+            dcs_header: None,
+            in_dcs: false,
         }
     }
 
@@ -66,8 +135,9 @@ impl Terminal {
     pub fn advance_bytes<B: AsRef<[u8]>>(&mut self, bytes: B, host: &mut TerminalHost) {
         let bytes = bytes.as_ref();
         for b in bytes.iter() {
-            self.parser.advance(&mut self.state, *b);
+            self.advance_byte(*b);
         }
+        self.state.invalidate_search();
         if let Some(answerback) = self.state.drain_answerback() {
             for answer in answerback {
                 match answer {
@@ -77,8 +147,106 @@ impl Terminal {
                     AnswerBack::TitleChanged(title) => {
                         host.set_title(&title);
                     }
+                    AnswerBack::CurrentDirChanged(cwd) => {
+                        host.set_current_dir(&cwd);
+                    }
+                    AnswerBack::Clipboard(ClipboardRequest::Set(clip)) => {
+                        host.set_clipboard(clip).ok(); // discard error
+                    }
+                    AnswerBack::Clipboard(ClipboardRequest::Query(selection)) => {
+                        report_clipboard(&selection, host).ok(); // discard error
+                    }
                 }
             }
         }
     }
+
+    fn advance_byte(&mut self, b: u8) {
+        match self.string {
+            ControlString::Inactive => {
+                let kind = match b {
+                    b']' => Some(StringKind::Osc),
+                    b'_' => Some(StringKind::Apc),
+                    _ => None,
+                };
+                if let (Some(kind), true, false) = (kind, self.last_byte_was_esc, self.in_dcs) {
+                    // The parser has only seen the ESC.  Start it
+                    // afresh in the ground state, where it will be
+                    // when the string is terminated.
+                    self.parser = vte::Parser::new();
+                    self.string = ControlString::Collecting(kind, Vec::new());
+                    self.last_byte_was_esc = false;
+                    return;
+                }
+            }
+            _ => match b {
+                // BEL and ESC terminate the string; CAN and SUB cancel it
+                0x07 | 0x1b | 0x18 | 0x1a => {
+                    let string = mem::replace(&mut self.string, ControlString::Inactive);
+                    if let ControlString::Collecting(kind, data) = string {
+                        if b == 0x07 || b == 0x1b {
+                            match kind {
+                                StringKind::Osc => self.dispatch_osc(&data),
+                                StringKind::Apc => self.state.apc_dispatch(&data),
+                            }
+                        }
+                    }
+                    if b == 0x07 {
+                        // BEL only terminates the string
+                        return;
+                    }
+                }
+                _ => {
+                    let max_image_size = self.state.max_image_size();
+                    let overflowed = match self.string {
+                        ControlString::Collecting(kind, ref mut data) => {
+                            data.push(b);
+                            // Only long strings need to have their
+                            // limit worked out
+                            data.len() > MAX_STRING_LEN
+                                && data.len() > string_limit(kind, data, max_image_size)
+                        }
+                        _ => false,
+                    };
+                    if overflowed {
+                        eprintln!("control string is too long; ignoring it");
+                        self.string = ControlString::Discarding;
+                    }
+                    return;
+                }
+            },
+        }
+        if self.in_dcs && (self.last_byte_was_esc || b == 0x18 || b == 0x1a) {
+            // The parser ends the DCS string at ESC, CAN or SUB
+            self.in_dcs = false;
+        }
+        match self.dcs_header.take() {
+            Some(mut header) => match b {
+                // The final byte identifies the function.  vte doesn't
+                // pass it to hook(), so record it along with the
+                // parameters before the parser calls that.
+                0x40...0x7e => self.state.set_dcs_header(&header, b),
+                0x18 | 0x1a | 0x1b => {}
+                _ => {
+                    if header.len() < MAX_DCS_HEADER_LEN {
+                        header.push(b);
+                    }
+                    self.dcs_header = Some(header);
+                }
+            },
+            None => {
+                if b == b'P' && self.last_byte_was_esc {
+                    self.dcs_header = Some(Vec::new());
+                    self.in_dcs = true;
+                }
+            }
+        }
+        self.last_byte_was_esc = b == 0x1b;
+        self.parser.advance(&mut self.state, b);
+    }
+
+    fn dispatch_osc(&mut self, data: &[u8]) {
+        let params: Vec<&[u8]> = data.split(|&b| b == b';').collect();
+        self.state.osc_dispatch(&params);
+    }
 }
@@ -1,4 +1,44 @@
 use super::*;
+use color::{ColorAttribute, ColorPalette, RgbColor};
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// How long synchronized output may defer painting before we give up
+/// on the application finishing its update and paint anyway.
+const SYNCHRONIZED_OUTPUT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How many entries each stack of kitty keyboard flags may hold.
+/// The oldest entries are discarded beyond this.
+const MAX_KEYBOARD_FLAGS_DEPTH: usize = 16;
+
+/// The size of a cell in pixels until the host tells us otherwise
+const DEFAULT_CELL_SIZE: (usize, usize) = (8, 16);
+
+/// How many bytes of pixels the images displayed on the lines of both
+/// screens, including the scrollback, may hold.  The oldest images are
+/// removed from the lines beyond this.
+const MAX_LINE_IMAGE_BYTES: usize = 320 * 1024 * 1024;
+
+/// The output of a command, as delimited by the OSC 133 marks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// The rows that hold the output
+    pub rows: Range<ScrollbackOrVisibleRowIndex>,
+    /// The exit status reported by the shell, if any
+    pub exit_status: Option<i32>,
+}
+
+/// The longest DECRQSS request that we will collect.  The settings
+/// that we can report are named by at most two bytes.
+const MAX_STATUS_STRING_LEN: usize = 8;
+
+/// A DCS string that is being collected
+enum DeviceControl {
+    /// DECRQSS: the bytes that name the requested setting
+    RequestStatusString(Vec<u8>),
+    /// A sixel image
+    Sixel(SixelBuilder),
+}
 
 struct TabStop {
     tabs: Vec<bool>,
@@ -14,10 +54,36 @@ impl TabStop {
         Self { tabs }
     }
 
+    /// Adjust for a change in the screen width.  Existing tab stops
+    /// are retained and new columns get the default tab stops.
+    fn resize(&mut self, screen_width: usize, tab_width: usize) {
+        let current = self.tabs.len();
+        self.tabs.resize(screen_width, false);
+        for i in current..screen_width {
+            self.tabs[i] = (i % tab_width) == 0;
+        }
+    }
+
     fn set_tab_stop(&mut self, col: usize) {
         self.tabs[col] = true;
     }
 
+    fn clear_tab_stop(&mut self, col: usize) {
+        if let Some(tab) = self.tabs.get_mut(col) {
+            *tab = false;
+        }
+    }
+
+    fn clear_all_tab_stops(&mut self) {
+        for tab in self.tabs.iter_mut() {
+            *tab = false;
+        }
+    }
+
+    fn find_prev_tab_stop(&self, col: usize) -> Option<usize> {
+        (0..col.min(self.tabs.len())).rev().find(|&i| self.tabs[i])
+    }
+
     fn find_next_tab_stop(&self, col: usize) -> Option<usize> {
         for i in col + 1..self.tabs.len() {
             if self.tabs[i] {
@@ -42,6 +108,12 @@ pub struct TerminalState {
     /// of the screen.  0-based index.
     cursor: CursorPosition,
     saved_cursor: CursorPosition,
+    /// The character sets saved along with the cursor by DECSC
+    saved_charsets: CharSets,
+    /// The designated G0-G3 character sets and the shift state
+    charsets: CharSets,
+    /// The most recently printed character, for REP
+    last_printed_char: Option<char>,
 
     /// if true, implicitly move to the next line on the next
     /// printed character
@@ -53,9 +125,37 @@ pub struct TerminalState {
     /// method.
     answerback: Vec<AnswerBack>,
 
+    /// The parameters and final byte of the most recent DCS sequence.
+    /// vte doesn't pass the final byte to hook() and drops the last
+    /// parameter, so the Terminal records these for us.
+    dcs_params: Vec<i64>,
+    dcs_final: u8,
+    /// The DCS string that is being collected, if any
+    dcs: Option<DeviceControl>,
+
     /// The scroll region
     scroll_region: Range<VisibleRowIndex>,
 
+    /// The left and right margins.  These span the width of the
+    /// screen unless they were changed while left_and_right_margin_mode
+    /// was enabled.
+    left_and_right_margins: Range<usize>,
+
+    /// DECLRMM: when set, `CSI s` sets the left and right margins
+    /// rather than saving the cursor position.
+    left_and_right_margin_mode: bool,
+
+    /// IRM: when set, printing shifts the rest of the line to the
+    /// right rather than replacing the cell at the cursor.
+    insert_mode: bool,
+
+    /// LNM: when set, LF also returns the cursor to the left margin.
+    newline_mode: bool,
+
+    /// DECOM: when set, cursor positions are relative to the top left
+    /// margin and the cursor is confined to the margins.
+    origin_mode: bool,
+
     /// When set, modifies the sequence of bytes sent for keys
     /// designated as cursor keys.  This includes various navigation
     /// keys.  The code in key_down() is responsible for interpreting this.
@@ -69,8 +169,54 @@ pub struct TerminalState {
     /// designated marker characters.
     bracketed_paste: bool,
 
-    sgr_mouse: bool,
-    button_event_mouse: bool,
+    /// The stacks of kitty keyboard protocol flags for the primary
+    /// and alternate screens.  The flags at the top of the stack for
+    /// the active screen determine how key_down() and key_up() encode
+    /// keys.
+    keyboard_flags: Vec<KeyboardFlags>,
+    alt_keyboard_flags: Vec<KeyboardFlags>,
+    /// The key that is held down, so that repeats can be told apart
+    /// from presses
+    held_key: Option<KeyCode>,
+    /// xterm's modifyOtherKeys level: 0 leaves modified keys alone,
+    /// 1 and 2 encode progressively more of them
+    modify_other_keys: u8,
+
+    /// Which mouse events are reported to the application
+    mouse_tracking: MouseTracking,
+    /// How the mouse reports are encoded
+    mouse_encoding: MouseEncoding,
+    /// When set, focus changes are reported to the application
+    focus_tracking: bool,
+    /// Whether the window that displays the terminal has the focus
+    focused: bool,
+    /// When synchronized output was enabled, if it is in effect
+    synchronized_output: Option<Instant>,
+    /// What applications may do with the clipboard via OSC 52
+    clipboard_access: ClipboardAccess,
+    /// The selection parameter of an OSC 52 query that is waiting
+    /// for the user to allow or deny it
+    pending_clipboard_read: Option<String>,
+
+    /// The colors used to render the terminal.  Applications may
+    /// change these with OSC 4, 10, 11 and 12.
+    palette: ColorPalette,
+    /// The configured colors, which OSC 104 and 110-112 restore
+    default_palette: ColorPalette,
+
+    /// The working directory most recently reported via OSC 7
+    current_dir: Option<String>,
+    /// The size of a cell in pixels, which determines how many
+    /// cells an image covers
+    cell_size: (usize, usize),
+    /// The largest image file that applications may display
+    max_image_size: usize,
+    /// How many bytes of pixels the images on the lines may hold
+    line_image_budget: usize,
+    /// The images transmitted with the kitty graphics protocol
+    kitty_images: ImageStore,
+    /// A kitty graphics command whose payload is arriving in chunks
+    kitty_upload: Option<GraphicsCommand>,
     current_mouse_button: MouseButton,
     mouse_position: CursorPosition,
     cursor_visible: bool,
@@ -98,6 +244,21 @@ pub struct TerminalState {
     /// Holds the not-normalized selection range.
     selection_range: Option<SelectionRange>,
 
+    /// The pattern for the active search, if any
+    search_pattern: Option<Pattern>,
+    /// The matches for search_pattern, ordered from the top of
+    /// the scrollback
+    search_matches: Vec<SearchMatch>,
+    /// Index into search_matches of the current match
+    current_search_match: Option<usize>,
+    /// true if the screen has changed since search_matches was computed
+    search_stale: bool,
+    /// The rows_scrolled of the screen when search_matches was computed
+    search_rows_scrolled: usize,
+    /// true if the lines have moved in a way that search_matches can't
+    /// follow, so that the search must start over
+    search_lines_moved: bool,
+
     tabs: TabStop,
 }
 
@@ -117,14 +278,42 @@ impl TerminalState {
             pen: CellAttributes::default(),
             cursor: CursorPosition::default(),
             saved_cursor: CursorPosition::default(),
+            saved_charsets: CharSets::default(),
+            charsets: CharSets::default(),
+            last_printed_char: None,
             answerback: Vec::new(),
+            dcs_params: Vec::new(),
+            dcs_final: 0,
+            dcs: None,
             scroll_region: 0..physical_rows as VisibleRowIndex,
+            left_and_right_margins: 0..physical_cols,
+            left_and_right_margin_mode: false,
+            insert_mode: false,
+            newline_mode: false,
+            origin_mode: false,
             wrap_next: false,
             application_cursor_keys: false,
             application_keypad: false,
             bracketed_paste: false,
-            sgr_mouse: false,
-            button_event_mouse: false,
+            keyboard_flags: Vec::new(),
+            alt_keyboard_flags: Vec::new(),
+            held_key: None,
+            modify_other_keys: 0,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::X10,
+            focus_tracking: false,
+            focused: true,
+            synchronized_output: None,
+            clipboard_access: ClipboardAccess::default(),
+            pending_clipboard_read: None,
+            palette: ColorPalette::default(),
+            default_palette: ColorPalette::default(),
+            current_dir: None,
+            cell_size: DEFAULT_CELL_SIZE,
+            max_image_size: DEFAULT_MAX_IMAGE_SIZE,
+            line_image_budget: MAX_LINE_IMAGE_BYTES,
+            kitty_images: ImageStore::default(),
+            kitty_upload: None,
             cursor_visible: true,
             current_mouse_button: MouseButton::None,
             mouse_position: CursorPosition::default(),
@@ -133,6 +322,12 @@ impl TerminalState {
             viewport_offset: 0,
             selection_range: None,
             selection_start: None,
+            search_pattern: None,
+            search_matches: Vec::new(),
+            current_search_match: None,
+            search_stale: false,
+            search_rows_scrolled: 0,
+            search_lines_moved: false,
             tabs: TabStop::new(physical_cols, 8),
         }
     }
@@ -158,13 +353,23 @@ impl TerminalState {
 
         if let Some(sel) = self.selection_range.as_ref().map(|r| r.normalize()) {
             let screen = self.screen();
+            let mut last_was_wrapped = false;
             for y in sel.rows() {
                 let idx = screen.scrollback_or_visible_row(y);
                 let cols = sel.cols_for_row(y);
-                if s.len() > 0 {
+                // Lines that were wrapped by the terminal are joined back
+                // together so that long commands and urls copy as one line.
+                if s.len() > 0 && !last_was_wrapped {
                     s.push('\n');
                 }
-                s.push_str(&screen.lines[idx].columns_as_str(cols).trim_right());
+                let line = &screen.lines[idx];
+                let text = line.columns_as_str(cols);
+                if line.is_wrapped() {
+                    s.push_str(&text);
+                } else {
+                    s.push_str(text.trim_right());
+                }
+                last_was_wrapped = line.is_wrapped();
             }
         }
 
@@ -187,6 +392,282 @@ impl TerminalState {
         self.selection_start = None;
     }
 
+    /// Search the screen and its scrollback for `pattern` and highlight
+    /// the matches.  The match closest to the bottom of the screen becomes
+    /// the current match and is scrolled into view.  The search is kept up
+    /// to date as output arrives, by `refresh_search`, until `clear_search`
+    /// is called.
+    /// Returns the matches, ordered from the top of the scrollback.
+    pub fn search(&mut self, pattern: &Pattern) -> Vec<SearchMatch> {
+        self.search_pattern = Some(pattern.clone());
+        self.search_matches = search_screen(self.screen(), pattern);
+        self.current_search_match = self.search_matches.len().checked_sub(1);
+        self.search_stale = false;
+        self.search_rows_scrolled = self.screen().rows_scrolled;
+        self.search_lines_moved = false;
+        self.make_all_lines_dirty();
+        if let Some(m) = self.current_search_match() {
+            self.scroll_to_row(m.start.y);
+        }
+        self.search_matches.clone()
+    }
+
+    /// Note that the screen has changed, so that the next call to
+    /// `refresh_search` re-runs the active search.
+    pub(crate) fn invalidate_search(&mut self) {
+        if self.search_pattern.is_some() {
+            self.search_stale = true;
+        }
+    }
+
+    /// Note that the lines have been reflowed or that the other screen
+    /// has become active, so that the next call to `refresh_search`
+    /// searches again from scratch.
+    fn reset_search(&mut self) {
+        if self.search_pattern.is_some() {
+            self.search_stale = true;
+            self.search_lines_moved = true;
+        }
+    }
+
+    /// Re-run the active search if the screen has changed since the
+    /// matches were last computed.  The renderer calls this before it
+    /// paints, so that the scrollback is searched at most once per frame
+    /// rather than for every read from the pty.
+    /// The current match follows its position as the screen scrolls,
+    /// and only the lines whose highlights changed are marked dirty.
+    pub fn refresh_search(&mut self) {
+        if !self.search_stale {
+            return;
+        }
+        self.search_stale = false;
+        let matches = match self.search_pattern {
+            Some(ref pattern) => search_screen(self.screen(), pattern),
+            None => return,
+        };
+        let rows_scrolled = self.screen().rows_scrolled;
+
+        if self.search_lines_moved {
+            // The old positions mean nothing now.  Reflowing doesn't
+            // change the text, so if there are as many matches as before
+            // then the current match is still the same one.
+            self.search_lines_moved = false;
+            self.search_rows_scrolled = rows_scrolled;
+            self.current_search_match = match self.current_search_match {
+                Some(idx) if matches.len() == self.search_matches.len() => Some(idx),
+                _ => matches.len().checked_sub(1),
+            };
+            self.search_matches = matches;
+            self.make_all_lines_dirty();
+            return;
+        }
+
+        // Move the old matches to where their rows are now
+        let shift =
+            rows_scrolled.wrapping_sub(self.search_rows_scrolled) as ScrollbackOrVisibleRowIndex;
+        self.search_rows_scrolled = rows_scrolled;
+        let old_matches: Vec<SearchMatch> = self
+            .search_matches
+            .iter()
+            .map(|m| {
+                let mut m = *m;
+                m.start.y -= shift;
+                m.end.y -= shift;
+                m
+            })
+            .collect();
+        let old_current = self.current_search_match;
+
+        // Keep the current match at the same position, or move to the
+        // next match if its text has gone
+        let current = match old_current {
+            Some(idx) => {
+                let start = old_matches[idx].start;
+                matches
+                    .iter()
+                    .position(|m| (m.start.y, m.start.x) >= (start.y, start.x))
+                    .or_else(|| matches.len().checked_sub(1))
+            }
+            None => matches.len().checked_sub(1),
+        };
+
+        let mut rows: Vec<ScrollbackOrVisibleRowIndex> =
+            old_matches.iter().chain(matches.iter()).flat_map(|m| m.start.y..=m.end.y).collect();
+        rows.sort();
+        rows.dedup();
+
+        let first_row = -((self.screen().lines.len() - self.screen().physical_rows)
+            as ScrollbackOrVisibleRowIndex);
+        let last_row = self.screen().physical_rows as ScrollbackOrVisibleRowIndex;
+        for row in rows {
+            if row < first_row || row >= last_row {
+                continue;
+            }
+            if highlights_for_row(&old_matches, old_current, row)
+                != highlights_for_row(&matches, current, row)
+            {
+                let idx = self.screen().scrollback_or_visible_row(row);
+                self.screen_mut().lines[idx].set_dirty();
+            }
+        }
+
+        self.search_matches = matches;
+        self.current_search_match = current;
+    }
+
+    /// Stop searching and remove the highlights
+    pub fn clear_search(&mut self) {
+        self.search_pattern = None;
+        self.search_matches.clear();
+        self.current_search_match = None;
+        self.search_stale = false;
+        self.search_lines_moved = false;
+        self.make_all_lines_dirty();
+    }
+
+    /// Returns the matches as of the last search or `refresh_search`
+    pub fn search_matches(&self) -> &[SearchMatch] {
+        &self.search_matches
+    }
+
+    pub fn current_search_match(&self) -> Option<SearchMatch> {
+        self.current_search_match.map(|idx| self.search_matches[idx])
+    }
+
+    /// Make the next match below the current match the current match,
+    /// wrapping around to the top, and scroll it into view.
+    pub fn next_search_match(&mut self) -> Option<SearchMatch> {
+        self.refresh_search();
+        let len = self.search_matches.len();
+        self.select_search_match(|idx| (idx + 1) % len)
+    }
+
+    /// Make the next match above the current match the current match,
+    /// wrapping around to the bottom, and scroll it into view.
+    pub fn prev_search_match(&mut self) -> Option<SearchMatch> {
+        self.refresh_search();
+        let len = self.search_matches.len();
+        self.select_search_match(|idx| (idx + len - 1) % len)
+    }
+
+    fn select_search_match<F: Fn(usize) -> usize>(&mut self, advance: F) -> Option<SearchMatch> {
+        let idx = advance(self.current_search_match?);
+        self.current_search_match = Some(idx);
+        let m = self.search_matches[idx];
+        self.make_all_lines_dirty();
+        self.scroll_to_row(m.start.y);
+        Some(m)
+    }
+
+    /// Adjust the viewport so that the specified row is visible,
+    /// centering it if it isn't already in view
+    fn scroll_to_row(&mut self, row: ScrollbackOrVisibleRowIndex) {
+        let rows = self.screen().physical_rows as ScrollbackOrVisibleRowIndex;
+        let top = -self.viewport_offset as ScrollbackOrVisibleRowIndex;
+        if row < top || row >= top + rows {
+            self.set_scroll_viewport((rows / 2 - row) as VisibleRowIndex);
+        }
+    }
+
+    /// Returns the rows that have the specified semantic mark,
+    /// ordered from the top of the scrollback
+    fn rows_with_mark(&self, mark: SemanticMarks) -> Vec<ScrollbackOrVisibleRowIndex> {
+        let screen = self.screen();
+        let first_visible = screen.lines.len() - screen.physical_rows;
+        screen
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|&(_, line)| line.semantic_marks().contains(mark))
+            .map(|(idx, _)| {
+                idx as ScrollbackOrVisibleRowIndex - first_visible as ScrollbackOrVisibleRowIndex
+            })
+            .collect()
+    }
+
+    /// Scroll the viewport so that the closest prompt above the top of
+    /// the viewport is at the top.  Returns false if there is no such prompt.
+    pub fn scroll_to_prev_prompt(&mut self) -> bool {
+        let top = -self.viewport_offset as ScrollbackOrVisibleRowIndex;
+        match self.rows_with_mark(SemanticMarks::PROMPT).into_iter().rev().find(|&row| row < top) {
+            Some(row) => {
+                self.set_scroll_viewport(-row as VisibleRowIndex);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Scroll the viewport so that the closest prompt below the top of
+    /// the viewport is at the top, or as close to it as the scrollback
+    /// allows.  Returns false if the viewport is already at the bottom.
+    pub fn scroll_to_next_prompt(&mut self) -> bool {
+        if self.viewport_offset == 0 {
+            return false;
+        }
+        let top = -self.viewport_offset as ScrollbackOrVisibleRowIndex;
+        let row = self
+            .rows_with_mark(SemanticMarks::PROMPT)
+            .into_iter()
+            .find(|&row| row > top)
+            .unwrap_or(0);
+        self.set_scroll_viewport(-row as VisibleRowIndex);
+        true
+    }
+
+    /// Select the output of the most recent command that finished,
+    /// as delimited by the OSC 133 C and D marks, and scroll it into
+    /// view.  Returns None if there is no marked command output.
+    pub fn select_last_command_output(&mut self) -> Option<CommandOutput> {
+        let (rows, exit_status) = {
+            let screen = self.screen();
+            // The line where the command finished, along with its status
+            let mut end = None;
+            let mut output = None;
+            // The marks on a line appear in the order C, D, A, so they
+            // are considered in reverse as we walk up the screen
+            for (idx, line) in screen.lines.iter().enumerate().rev() {
+                let marks = line.semantic_marks();
+                if marks.contains(SemanticMarks::PROMPT) {
+                    // The command that ended below this prompt had no
+                    // output; consider the one before it
+                    end = None;
+                }
+                if marks.contains(SemanticMarks::COMMAND_END) {
+                    end = Some((idx, line.exit_status()));
+                }
+                if marks.contains(SemanticMarks::OUTPUT) {
+                    if let Some((end_idx, status)) = end {
+                        output = Some((idx..end_idx, status));
+                        break;
+                    }
+                }
+            }
+            let (rows, exit_status) = output?;
+            let first_visible = (screen.lines.len() - screen.physical_rows) as isize;
+            let start = (rows.start as isize - first_visible) as ScrollbackOrVisibleRowIndex;
+            let end = (rows.end as isize - first_visible) as ScrollbackOrVisibleRowIndex;
+            (start..end, exit_status)
+        };
+
+        if rows.start < rows.end {
+            self.scroll_to_row(rows.start);
+            let cols = self.screen().physical_cols;
+            self.selection_start = None;
+            self.selection_range = Some(SelectionRange {
+                start: SelectionCoordinate { x: 0, y: rows.start },
+                end: SelectionCoordinate { x: cols.saturating_sub(1), y: rows.end - 1 },
+            });
+            self.dirty_selection_lines();
+        }
+        Some(CommandOutput { rows, exit_status })
+    }
+
+    /// Returns the search matches that intersect the specified row
+    fn search_highlights_for_row(&self, row: ScrollbackOrVisibleRowIndex) -> Vec<SearchHighlight> {
+        highlights_for_row(&self.search_matches, self.current_search_match, row)
+    }
+
     fn hyperlink_for_cell(
         &self,
         x: usize,
@@ -243,13 +724,17 @@ impl TerminalState {
         // for highlighting clickable things elsewhere.
         let new_position = CursorPosition { x: event.x, y: event.y as VisibleRowIndex };
 
-        if new_position != self.mouse_position {
+        let moved = new_position != self.mouse_position;
+        if moved {
             self.mouse_position = new_position;
             self.recompute_highlight();
         }
 
-        // First pass to figure out if we're messing with the selection
-        let send_event = self.sgr_mouse && !event.modifiers.contains(KeyModifiers::SHIFT);
+        // First pass to figure out if we're messing with the selection.
+        // Holding shift allows selecting text while the application
+        // has enabled mouse reporting.
+        let send_event = self.mouse_tracking != MouseTracking::Off
+            && !event.modifiers.contains(KeyModifiers::SHIFT);
 
         // Perform click counting
         if event.kind == MouseEventKind::Press {
@@ -395,20 +880,14 @@ impl TerminalState {
         match event {
             MouseEvent { kind: MouseEventKind::Press, button: MouseButton::WheelUp, .. }
             | MouseEvent { kind: MouseEventKind::Press, button: MouseButton::WheelDown, .. } => {
-                let (report_button, scroll_delta, key) = if event.button == MouseButton::WheelUp {
-                    (64, -1, KeyCode::Up)
+                let (scroll_delta, key) = if event.button == MouseButton::WheelUp {
+                    (-1, KeyCode::Up)
                 } else {
-                    (65, 1, KeyCode::Down)
+                    (1, KeyCode::Down)
                 };
 
-                if self.sgr_mouse {
-                    write!(
-                        host.writer(),
-                        "\x1b[<{};{};{}M",
-                        report_button,
-                        event.x + 1,
-                        event.y + 1
-                    )?;
+                if send_event {
+                    self.report_mouse_event(&event, event.button, host)?;
                 } else if self.alt_screen_is_active {
                     // Send cursor keys instead (equivalent to xterm's alternateScroll mode)
                     self.key_down(key, KeyModifiers::default(), host)?;
@@ -416,49 +895,332 @@ impl TerminalState {
                     self.scroll_viewport(scroll_delta)
                 }
             }
+            MouseEvent { kind: MouseEventKind::Release, button: MouseButton::WheelUp, .. }
+            | MouseEvent {
+                kind: MouseEventKind::Release, button: MouseButton::WheelDown, ..
+            } => {
+                // The wheel has no release to report
+            }
             MouseEvent { kind: MouseEventKind::Press, .. } => {
                 self.current_mouse_button = event.button;
-                if let Some(button) = match event.button {
-                    MouseButton::Left => Some(0),
-                    MouseButton::Middle => Some(1),
-                    MouseButton::Right => Some(2),
-                    _ => None,
-                } {
-                    if self.sgr_mouse {
-                        write!(host.writer(), "\x1b[<{};{};{}M", button, event.x + 1, event.y + 1)?;
-                    } else if event.button == MouseButton::Middle {
-                        let clip = host.get_clipboard()?;
-                        if self.bracketed_paste {
-                            write!(host.writer(), "\x1b[200~{}\x1b[201~", clip)?;
-                        } else {
-                            write!(host.writer(), "{}", clip)?;
-                        }
+                if send_event {
+                    self.report_mouse_event(&event, event.button, host)?;
+                } else if event.button == MouseButton::Middle {
+                    let clip = host.get_clipboard()?;
+                    if self.bracketed_paste {
+                        write!(host.writer(), "\x1b[200~{}\x1b[201~", clip)?;
+                    } else {
+                        write!(host.writer(), "{}", clip)?;
                     }
                 }
             }
             MouseEvent { kind: MouseEventKind::Release, .. } => {
                 self.current_mouse_button = MouseButton::None;
-                if self.sgr_mouse {
-                    write!(host.writer(), "\x1b[<3;{};{}m", event.x + 1, event.y + 1)?;
+                if send_event {
+                    self.report_mouse_event(&event, event.button, host)?;
                 }
             }
             MouseEvent { kind: MouseEventKind::Move, .. } => {
-                if let Some(button) = match (self.current_mouse_button, self.button_event_mouse) {
-                    (_, false) => None,
-                    (MouseButton::Left, true) => Some(32),
-                    (MouseButton::Middle, true) => Some(33),
-                    (MouseButton::Right, true) => Some(34),
-                    (..) => None,
-                } {
-                    if self.sgr_mouse {
-                        write!(host.writer(), "\x1b[<{};{};{}M", button, event.x + 1, event.y + 1)?;
-                    }
+                // Only report motion from one cell to another
+                if send_event && moved {
+                    let button = self.current_mouse_button;
+                    self.report_mouse_event(&event, button, host)?;
                 }
             }
         }
         Ok(())
     }
 
+    /// Called by the gui layer when the window gains or loses the
+    /// keyboard focus.  The change is reported to the application
+    /// if it has enabled focus tracking.
+    pub fn focus_changed(&mut self, focused: bool, host: &mut TerminalHost) -> Result<(), Error> {
+        if focused == self.focused {
+            return Ok(());
+        }
+        self.focused = focused;
+
+        // The cursor is rendered differently while unfocused
+        let y = self.cursor.y;
+        self.screen_mut().dirty_line(y);
+
+        if self.focus_tracking {
+            host.writer().write_all(if focused { b"\x1b[I" } else { b"\x1b[O" })?;
+        }
+        Ok(())
+    }
+
+    /// While the application has enabled synchronized output, returns
+    /// the time until which painting should be deferred so that a
+    /// partially drawn frame is not displayed.
+    /// Returns None once the mode is reset or the timeout has passed.
+    pub fn synchronized_output_deadline(&self) -> Option<Instant> {
+        self.synchronized_output
+            .map(|start| start + SYNCHRONIZED_OUTPUT_TIMEOUT)
+            .filter(|deadline| *deadline > Instant::now())
+    }
+
+    /// Returns true if the window that displays the terminal has the focus
+    pub fn has_focus(&self) -> bool {
+        self.focused
+    }
+
+    /// Change what applications are permitted to do with the
+    /// clipboard via OSC 52
+    pub fn set_clipboard_access(&mut self, access: ClipboardAccess) {
+        self.clipboard_access = access;
+        if access != ClipboardAccess::Ask {
+            self.pending_clipboard_read = None;
+        }
+    }
+
+    /// Returns the colors that should be used to render the terminal
+    pub fn palette(&self) -> &ColorPalette {
+        &self.palette
+    }
+
+    /// Change the configured colors.  This replaces any colors that
+    /// were set by the application.
+    pub fn set_default_palette(&mut self, palette: ColorPalette) {
+        self.palette = palette.clone();
+        self.default_palette = palette;
+        self.make_all_lines_dirty();
+    }
+
+    /// Returns the working directory that the application reported
+    /// via OSC 7, if any
+    pub fn current_dir(&self) -> Option<&str> {
+        self.current_dir.as_ref().map(String::as_str)
+    }
+
+    /// Tell the terminal how large the cells are when rendered, so
+    /// that it knows how many cells an image covers
+    pub fn set_cell_size(&mut self, width: usize, height: usize) {
+        self.cell_size = (width.max(1), height.max(1));
+    }
+
+    /// Limit the size of the image files that applications may
+    /// display via OSC 1337 or the kitty graphics protocol, so that
+    /// they can't exhaust memory
+    pub fn set_max_image_size(&mut self, bytes: usize) {
+        self.max_image_size = bytes;
+    }
+
+    pub(crate) fn max_image_size(&self) -> usize {
+        self.max_image_size
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_line_image_budget(&mut self, bytes: usize) {
+        self.line_image_budget = bytes;
+    }
+
+    /// Returns the image slices on the lines in the viewport, along
+    /// with the row on which each is displayed
+    pub fn visible_images(&self) -> Vec<(usize, &ImageSlice)> {
+        let screen = self.screen();
+        let height = screen.physical_rows;
+        let len = screen.lines.len() - self.viewport_offset as usize;
+
+        let mut res = Vec::new();
+        for (i, line) in screen.lines.iter().skip(len - height).take(height).enumerate() {
+            for slice in line.images() {
+                res.push((i, slice));
+            }
+        }
+        res
+    }
+
+    /// Returns true if an application has asked to read the clipboard
+    /// and the gui layer should ask the user whether to allow it
+    pub fn has_pending_clipboard_read(&self) -> bool {
+        self.pending_clipboard_read.is_some()
+    }
+
+    /// Called by the gui layer with the user's answer to the pending
+    /// clipboard read.  If allowed, the clipboard contents are sent
+    /// to the application.
+    pub fn answer_clipboard_read(
+        &mut self,
+        allow: bool,
+        host: &mut TerminalHost,
+    ) -> Result<(), Error> {
+        match self.pending_clipboard_read.take() {
+            Some(ref selection) if allow => report_clipboard(selection, host),
+            _ => Ok(()),
+        }
+    }
+
+    /// `OSC 4 ; c ; spec ; ...` sets entry `c` of the 256 color palette,
+    /// or reports it if spec is `?`.
+    fn set_palette_colors(&mut self, params: &[&[u8]]) {
+        let mut changed = false;
+        for pair in params.chunks(2) {
+            let (idx, spec) = match pair {
+                &[idx, spec] => (idx, spec),
+                _ => break,
+            };
+            let idx = match str::from_utf8(idx).ok().and_then(|idx| idx.parse::<u8>().ok()) {
+                Some(idx) => idx,
+                None => {
+                    eprintln!("OSC 4: invalid color index {:?}", idx);
+                    continue;
+                }
+            };
+            if spec == b"?" {
+                let color = self.palette.colors[idx as usize];
+                let report = format!("\x1b]4;{};{}\x1b\\", idx, color.to_x11_spec());
+                self.push_answerback(report.as_bytes());
+            } else if let Some(color) = str::from_utf8(spec).ok().and_then(RgbColor::from_x11_spec)
+            {
+                self.palette.colors[idx as usize] = color;
+                changed = true;
+            } else {
+                eprintln!("OSC 4: invalid color {:?}", spec);
+            }
+        }
+        if changed {
+            self.make_all_lines_dirty();
+        }
+    }
+
+    /// `OSC 104 ; c ; ...` restores the listed palette entries, or
+    /// all of them if none are listed.
+    fn reset_palette_colors(&mut self, params: &[&[u8]]) {
+        let indices: Vec<u8> = params
+            .iter()
+            .filter_map(|idx| str::from_utf8(idx).ok().and_then(|idx| idx.parse::<u8>().ok()))
+            .collect();
+        if indices.is_empty() {
+            self.palette.colors = self.default_palette.colors;
+        } else {
+            for idx in indices {
+                self.palette.colors[idx as usize] = self.default_palette.colors[idx as usize];
+            }
+        }
+        self.make_all_lines_dirty();
+    }
+
+    /// The color that is set by `OSC code` where code is 10 (the
+    /// default foreground), 11 (the default background) or 12 (the cursor)
+    fn dynamic_color(palette: &mut ColorPalette, code: u8) -> &mut RgbColor {
+        match code {
+            10 => &mut palette.foreground,
+            11 => &mut palette.background,
+            _ => &mut palette.cursor,
+        }
+    }
+
+    /// `OSC 10 ; spec` sets the default foreground color, or reports
+    /// it if spec is `?`, and similarly for OSC 11 and OSC 12.
+    /// Each additional parameter applies to the next color in that
+    /// sequence, so `OSC 10 ; fg ; bg` sets both.
+    fn set_dynamic_colors(&mut self, first: u8, params: &[&[u8]]) {
+        for (code, spec) in (first..13).zip(params.iter()) {
+            if *spec == b"?" {
+                let color = *Self::dynamic_color(&mut self.palette, code);
+                let report = format!("\x1b]{};{}\x1b\\", code, color.to_x11_spec());
+                self.push_answerback(report.as_bytes());
+            } else if let Some(color) = str::from_utf8(spec).ok().and_then(RgbColor::from_x11_spec)
+            {
+                *Self::dynamic_color(&mut self.palette, code) = color;
+                self.make_all_lines_dirty();
+            } else {
+                eprintln!("OSC {}: invalid color {:?}", code, spec);
+            }
+        }
+    }
+
+    /// `OSC 110`, `OSC 111` and `OSC 112` restore the colors set
+    /// by OSC 10, 11 and 12 respectively
+    fn reset_dynamic_color(&mut self, code: u8) {
+        let color = *Self::dynamic_color(&mut self.default_palette, code);
+        *Self::dynamic_color(&mut self.palette, code) = color;
+        self.make_all_lines_dirty();
+    }
+
+    /// `OSC 7 ; file://host/path` reports the working directory.
+    /// The path may have been split on `;` by the parser, so the
+    /// parameters are joined back together.  Directories on other
+    /// hosts, such as those reported over ssh, are ignored.
+    fn set_current_dir(&mut self, params: &[&[u8]]) {
+        let url = params.join(&b';');
+        match str::from_utf8(&url).ok().and_then(parse_file_url) {
+            Some((ref host, ref path)) if !is_local_host(host) => {
+                eprintln!("OSC 7: ignoring {} on another host {}", path, host)
+            }
+            Some((_, path)) => {
+                self.answerback.push(AnswerBack::CurrentDirChanged(path.clone()));
+                self.current_dir = Some(path);
+            }
+            None => eprintln!("OSC 7: invalid working directory {:?}", url),
+        }
+    }
+
+    /// `OSC 1337 ; File=`: display an image at the cursor position.
+    /// Files that are sent to be downloaded rather than displayed
+    /// are ignored.
+    fn inline_image(&mut self, params: &[&[u8]]) {
+        let data = params.join(&b';');
+        let (cell_width, cell_height) = self.cell_size;
+        let screen =
+            (self.screen().physical_cols * cell_width, self.screen().physical_rows * cell_height);
+        let image = InlineImage::parse(&data, self.max_image_size).and_then(|image| {
+            if !image.inline {
+                bail!("downloading files is not supported");
+            }
+            image.decode(self.cell_size, screen)
+        });
+        match image {
+            Ok(image) => self.place_image(image),
+            Err(err) => eprintln!("OSC 1337: {}", err),
+        }
+    }
+
+    /// `OSC 133 ; A`, `B`, `C` and `D ; status` mark the start of the
+    /// prompt, the command input, the command output and the end of
+    /// the command on the line that holds the cursor
+    fn semantic_prompt(&mut self, params: &[&[u8]]) {
+        let mark = match params.first() {
+            Some(&b"A") => SemanticMarks::PROMPT,
+            Some(&b"B") => SemanticMarks::INPUT,
+            Some(&b"C") => SemanticMarks::OUTPUT,
+            Some(&b"D") => SemanticMarks::COMMAND_END,
+            _ => {
+                eprintln!("OSC 133: unhandled {:?}", params);
+                return;
+            }
+        };
+        let idx = self.screen().phys_row(self.cursor.y);
+        let line = self.screen_mut().line_mut(idx);
+        line.add_semantic_mark(mark);
+        if mark == SemanticMarks::COMMAND_END {
+            let status = params
+                .get(1)
+                .and_then(|status| str::from_utf8(status).ok())
+                .and_then(|status| status.parse().ok());
+            line.set_exit_status(status);
+        }
+    }
+
+    /// Handle `OSC 52 ; Pc ; Pd` according to the clipboard_access policy
+    fn clipboard_osc(&mut self, selection: &[u8], data: &[u8]) {
+        if self.clipboard_access == ClipboardAccess::Deny {
+            return;
+        }
+        match ClipboardRequest::parse(selection, data) {
+            Ok(ClipboardRequest::Query(selection)) => match self.clipboard_access {
+                ClipboardAccess::AllowRead => {
+                    self.answerback.push(AnswerBack::Clipboard(ClipboardRequest::Query(selection)))
+                }
+                ClipboardAccess::Ask => self.pending_clipboard_read = Some(selection),
+                _ => eprintln!("OSC 52: reading the clipboard is not permitted"),
+            },
+            Ok(request) => self.answerback.push(AnswerBack::Clipboard(request)),
+            Err(err) => eprintln!("OSC 52: {}", err),
+        }
+    }
+
     /// Processes a key_down event generated by the gui/render layer
     /// that is embedding the Terminal.  This method translates the
     /// keycode into a sequence of bytes to send to the slave end
@@ -469,81 +1231,144 @@ impl TerminalState {
         mods: KeyModifiers,
         host: &mut TerminalHost,
     ) -> Result<(), Error> {
-        const CTRL: KeyModifiers = KeyModifiers::CTRL;
+        use std::fmt::Write;
         const SHIFT: KeyModifiers = KeyModifiers::SHIFT;
         const ALT: KeyModifiers = KeyModifiers::ALT;
-        const APPCURSOR: bool = true;
         use KeyCode::*;
 
-        let ctrl = mods & CTRL;
+        let kind =
+            if self.held_key == Some(key) { KeyEventKind::Repeat } else { KeyEventKind::Press };
+        self.held_key = Some(key);
+
+        let flags = self.current_keyboard_flags();
+        if !flags.is_empty() {
+            let kind = if flags.contains(KeyboardFlags::REPORT_EVENT_TYPES) {
+                kind
+            } else {
+                KeyEventKind::Press
+            };
+            if let Some(encoded) = encode_key(key, mods, kind, flags) {
+                return self.send_key(encoded.as_bytes(), host);
+            }
+        }
+
+        if let Some(encoded) = encode_modify_other_keys(key, mods, self.modify_other_keys) {
+            return self.send_key(encoded.as_bytes(), host);
+        }
+
+        // As in xterm, F13 to F24 send what shifted F1 to F12 do
+        let (key, mods) = match key {
+            Function(n) if n > 12 => (Function(n - 12), mods | SHIFT),
+            key => (key, mods),
+        };
         let shift = mods & SHIFT;
         let alt = mods & ALT;
+        let modifiers = xterm_modifiers(mods);
 
         let mut buf = String::new();
 
-        // TODO: also respect self.application_keypad
-
-        let to_send = match (key, ctrl, alt, shift, self.application_cursor_keys) {
-            (Char(c), CTRL, _, SHIFT, _) if c <= 0xff as char => {
-                // If shift is held we have C == 0x43 and want to translate
-                // that into 0x03
-                buf.push((c as u8 - 0x40) as char);
-                buf.as_str()
-            }
-            (Char(c), CTRL, ..) if c <= 0xff as char => {
-                // If shift is not held we have C == 0x63 and want to translate
-                // that into 0x03
-                buf.push((c as u8 - 0x60) as char);
-                buf.as_str()
-            }
-            (Char(c), _, ALT, ..) if c <= 0xff as char => {
+        let to_send = match (key, alt, shift) {
+            (Char(c), ..) => {
                 // TODO: add config option to select 8-bit vs. escape behavior?
-                //buf.push((c as u8 | 0x80) as char);
-                buf.push(0x1b as char);
-                buf.push(c);
+                if alt == ALT {
+                    buf.push(0x1b as char);
+                }
+                match ctrl_code(c) {
+                    Some(code) if mods.contains(KeyModifiers::CTRL) => buf.push(code),
+                    _ => buf.push(c),
+                }
                 buf.as_str()
             }
-            (Char(c), ..) => {
-                buf.push(c);
+            (Tab, _, SHIFT) => "\x1b[Z",
+            (Backspace, ..) | (Tab, ..) | (Enter, ..) | (Escape, ..) => {
+                if alt == ALT {
+                    buf.push(0x1b as char);
+                }
+                buf.push(match key {
+                    Backspace => '\x08',
+                    Tab => '\t',
+                    Enter => '\r',
+                    _ => '\x1b',
+                });
                 buf.as_str()
             }
-            (Insert, _, _, SHIFT, _) => {
+            (Insert, _, SHIFT) => {
                 let clip = host.get_clipboard()?;
                 if self.bracketed_paste {
-                    use std::fmt::Write;
                     write!(buf, "\x1b[200~{}\x1b[201~", clip)?;
                 } else {
                     buf = clip;
                 }
                 buf.as_str()
             }
-
-            (Up, _, _, _, APPCURSOR) => "\x1bOA",
-            (Down, _, _, _, APPCURSOR) => "\x1bOB",
-            (Right, _, _, _, APPCURSOR) => "\x1bOC",
-            (Left, _, _, _, APPCURSOR) => "\x1bOD",
-            (Home, _, _, _, APPCURSOR) => "\x1bOH",
-            (End, _, _, _, APPCURSOR) => "\x1bOF",
-
-            (Up, ..) => "\x1b[A",
-            (Down, ..) => "\x1b[B",
-            (Right, ..) => "\x1b[C",
-            (Left, ..) => "\x1b[D",
-            (PageUp, _, _, SHIFT, _) => {
+            (PageUp, _, SHIFT) => {
                 let rows = self.screen().physical_rows as i64;
                 self.scroll_viewport(-rows);
                 ""
             }
-            (PageDown, _, _, SHIFT, _) => {
+            (PageDown, _, SHIFT) => {
                 let rows = self.screen().physical_rows as i64;
                 self.scroll_viewport(rows);
                 ""
             }
-            (PageUp, ..) => "\x1b[5~",
-            (PageDown, ..) => "\x1b[6~",
-            (Home, ..) => "\x1b[H",
-            (End, ..) => "\x1b[F",
-            (Insert, ..) => "\x1b[2~",
+
+            (Up, ..) | (Down, ..) | (Right, ..) | (Left, ..) | (Home, ..) | (End, ..) => {
+                let final_byte = match key {
+                    Up => 'A',
+                    Down => 'B',
+                    Right => 'C',
+                    Left => 'D',
+                    Home => 'H',
+                    _ => 'F',
+                };
+                match modifiers {
+                    Some(m) => write!(buf, "\x1b[1;{}{}", m, final_byte)?,
+                    None if self.application_cursor_keys => write!(buf, "\x1bO{}", final_byte)?,
+                    None => write!(buf, "\x1b[{}", final_byte)?,
+                }
+                buf.as_str()
+            }
+            (Function(n), ..) => {
+                let (code, final_byte) = function_key(n);
+                match modifiers {
+                    Some(m) => write!(buf, "\x1b[{};{}{}", code, m, final_byte)?,
+                    None if final_byte == '~' => write!(buf, "\x1b[{}~", code)?,
+                    None => write!(buf, "\x1bO{}", final_byte)?,
+                }
+                buf.as_str()
+            }
+            (PageUp, ..) | (PageDown, ..) | (Insert, ..) | (Delete, ..) => {
+                let code = match key {
+                    PageUp => 5,
+                    PageDown => 6,
+                    Insert => 2,
+                    _ => 3,
+                };
+                match modifiers {
+                    Some(m) => write!(buf, "\x1b[{};{}~", code, m)?,
+                    None => write!(buf, "\x1b[{}~", code)?,
+                }
+                buf.as_str()
+            }
+
+            (Numpad(_), ..)
+            | (NumpadDecimal, ..)
+            | (NumpadSeparator, ..)
+            | (NumpadAdd, ..)
+            | (NumpadSubtract, ..)
+            | (NumpadMultiply, ..)
+            | (NumpadDivide, ..)
+            | (NumpadEqual, ..)
+            | (NumpadEnter, ..) => {
+                if let Some((text, final_byte, _)) = numpad_key(key) {
+                    if self.application_keypad {
+                        write!(buf, "\x1bO{}", final_byte)?;
+                    } else {
+                        buf.push(text);
+                    }
+                }
+                buf.as_str()
+            }
 
             // Modifier keys pressed on their own and unmappable keys don't expand to anything
             (Control, ..)
@@ -555,7 +1380,34 @@ impl TerminalState {
             | (Unknown, ..) => "",
         };
 
-        host.writer().write(&to_send.as_bytes())?;
+        self.send_key(to_send.as_bytes(), host)
+    }
+
+    /// Processes a key_up event generated by the gui/render layer.
+    /// Releases are only reported to applications that asked for
+    /// them via the kitty keyboard protocol.
+    pub fn key_up(
+        &mut self,
+        key: KeyCode,
+        mods: KeyModifiers,
+        host: &mut TerminalHost,
+    ) -> Result<(), Error> {
+        if self.held_key == Some(key) {
+            self.held_key = None;
+        }
+        let flags = self.current_keyboard_flags();
+        if !flags.contains(KeyboardFlags::REPORT_EVENT_TYPES) {
+            return Ok(());
+        }
+        match encode_key(key, mods, KeyEventKind::Release, flags) {
+            Some(encoded) => self.send_key(encoded.as_bytes(), host),
+            None => Ok(()),
+        }
+    }
+
+    /// Send the encoding of a key to the application
+    fn send_key(&mut self, to_send: &[u8], host: &mut TerminalHost) -> Result<(), Error> {
+        host.writer().write_all(to_send)?;
 
         // Reset the viewport if we sent data to the parser
         if to_send.len() > 0 && self.viewport_offset != 0 {
@@ -566,20 +1418,75 @@ impl TerminalState {
         Ok(())
     }
 
-    pub fn key_up(
-        &mut self,
-        _: KeyCode,
-        _: KeyModifiers,
-        _: &mut TerminalHost,
-    ) -> Result<(), Error> {
-        Ok(())
+    /// The kitty keyboard protocol flags in effect on the active screen
+    fn current_keyboard_flags(&self) -> KeyboardFlags {
+        let stack =
+            if self.alt_screen_is_active { &self.alt_keyboard_flags } else { &self.keyboard_flags };
+        stack.last().cloned().unwrap_or_default()
+    }
+
+    fn keyboard_flags_stack(&mut self) -> &mut Vec<KeyboardFlags> {
+        if self.alt_screen_is_active {
+            &mut self.alt_keyboard_flags
+        } else {
+            &mut self.keyboard_flags
+        }
     }
 
+    /// Resize the terminal.  The text on the primary screen is rewrapped
+    /// to fit the new width; the alternate screen is left for the
+    /// application to redraw.
     pub fn resize(&mut self, physical_rows: usize, physical_cols: usize) {
-        self.screen.resize(physical_rows, physical_cols);
+        // The window may be made smaller than a single cell
+        let physical_rows = physical_rows.max(1);
+        let physical_cols = physical_cols.max(1);
+        let primary_cursor =
+            if self.alt_screen_is_active { self.saved_cursor } else { self.cursor };
+        let mut primary_cursor =
+            self.screen.resize_and_rewrap(physical_rows, physical_cols, primary_cursor);
         self.alt_screen.resize(physical_rows, physical_cols);
+
+        let clamp = |pos: CursorPosition| CursorPosition {
+            x: pos.x.min(physical_cols - 1),
+            y: pos.y.max(0).min(physical_rows as VisibleRowIndex - 1),
+        };
+
+        if self.alt_screen_is_active {
+            self.saved_cursor = primary_cursor;
+            self.cursor = clamp(self.cursor);
+        } else {
+            // A pending wrap applies to the character under the cursor;
+            // if it no longer ends up in the last column then the cursor
+            // belongs in the cell following it.
+            if self.wrap_next {
+                let width = {
+                    let screen = &self.screen;
+                    let line = &screen.lines[screen.phys_row(primary_cursor.y)];
+                    line.cells.get(primary_cursor.x).map_or(1, |cell| cell.width().max(1))
+                };
+                if primary_cursor.x + width < physical_cols {
+                    primary_cursor.x += width;
+                    self.wrap_next = false;
+                }
+            }
+            self.cursor = clamp(primary_cursor);
+            self.saved_cursor = clamp(self.saved_cursor);
+        }
+
+        self.tabs.resize(physical_cols, 8);
         self.scroll_region = 0..physical_rows as i64;
+        self.left_and_right_margins = 0..physical_cols;
         self.set_scroll_viewport(0);
+        self.reset_search();
+    }
+
+    /// Change the number of lines of scrollback retained by the
+    /// primary screen, discarding the oldest lines if it shrinks.
+    pub fn set_scrollback_size(&mut self, scrollback_size: usize) {
+        self.screen.set_scrollback_size(scrollback_size);
+        // Clamp the viewport in case it was looking at lines that are gone
+        let offset = self.viewport_offset;
+        self.set_scroll_viewport(offset);
     }
 
     /// Returns true if any of the visible lines are marked dirty
@@ -602,7 +1509,9 @@ impl TerminalState {
     /// line_idx is relative to the top of the viewport.
     /// The selrange value is the column range representing the selected
     /// columns on this line.
-    pub fn get_dirty_lines(&self) -> Vec<(usize, &Line, Range<usize>)> {
+    /// Returns the dirty lines in the viewport, along with the range of
+    /// selected columns and the search matches for each line
+    pub fn get_dirty_lines(&self) -> Vec<(usize, &Line, Range<usize>, Vec<SearchHighlight>)> {
         let mut res = Vec::new();
 
         let screen = self.screen();
@@ -618,17 +1527,16 @@ impl TerminalState {
                 break;
             }
             if line.is_dirty() {
+                // i is relative to the viewport, convert it back to
+                // something we can relate to the selection
+                let row = (i as ScrollbackOrVisibleRowIndex)
+                    - self.viewport_offset as ScrollbackOrVisibleRowIndex;
                 let selrange = match selection {
                     None => 0..0,
-                    Some(sel) => {
-                        // i is relative to the viewport, convert it back to
-                        // something we can relate to the selection
-                        let row = (i as ScrollbackOrVisibleRowIndex)
-                            - self.viewport_offset as ScrollbackOrVisibleRowIndex;
-                        sel.cols_for_row(row)
-                    }
+                    Some(sel) => sel.cols_for_row(row),
                 };
-                res.push((i, &*line, selrange));
+                let highlights = self.search_highlights_for_row(row);
+                res.push((i, &*line, selrange, highlights));
             }
         }
 
@@ -669,15 +1577,15 @@ impl TerminalState {
 
     /// Sets the cursor position. x and y are 0-based and relative to the
     /// top left of the visible screen.
-    /// TODO: DEC origin mode impacts the interpreation of these
+    /// The margins are not taken into account; see move_cursor.
     fn set_cursor_pos(&mut self, x: &Position, y: &Position) {
         let x = match x {
             &Position::Relative(x) => (self.cursor.x as i64 + x).max(0),
-            &Position::Absolute(x) => x,
+            &Position::Absolute(x) => x.max(0),
         };
         let y = match y {
             &Position::Relative(y) => (self.cursor.y + y).max(0),
-            &Position::Absolute(y) => y,
+            &Position::Absolute(y) => y.max(0),
         };
 
         let rows = self.screen().physical_rows;
@@ -694,6 +1602,145 @@ impl TerminalState {
         screen.dirty_line(new_y);
     }
 
+    /// Move the cursor in response to a cursor positioning sequence.
+    /// When DEC origin mode is enabled, absolute positions are relative
+    /// to the top left margin and the cursor cannot leave the margins.
+    /// Otherwise relative movement stops at a margin if the cursor
+    /// started out within the margins.
+    fn move_cursor(&mut self, x: &Position, y: &Position) {
+        let cols = self.left_and_right_margins.start as i64..self.left_and_right_margins.end as i64;
+        let rows = self.scroll_region.clone();
+        let x = Self::position_within_margins(x, self.cursor.x as i64, &cols, self.origin_mode);
+        let y = Self::position_within_margins(y, self.cursor.y, &rows, self.origin_mode);
+        self.set_cursor_pos(&Position::Absolute(x), &Position::Absolute(y));
+    }
+
+    /// Resolve a position along one axis on behalf of move_cursor
+    fn position_within_margins(
+        pos: &Position,
+        current: i64,
+        margins: &Range<i64>,
+        origin_mode: bool,
+    ) -> i64 {
+        let clamp = |value: i64| value.max(margins.start).min(margins.end - 1);
+        match pos {
+            &Position::Absolute(value) if origin_mode => clamp(value + margins.start),
+            &Position::Absolute(value) => value,
+            &Position::Relative(delta) if origin_mode || in_range(current, margins) => {
+                clamp(current + delta)
+            }
+            &Position::Relative(delta) => current + delta,
+        }
+    }
+
+    /// The column that a carriage return moves to; this is the left
+    /// margin unless the cursor is already to the left of it.
+    fn carriage_return_column(&self) -> usize {
+        let left = self.left_and_right_margins.start;
+        if self.cursor.x >= left {
+            left
+        } else {
+            0
+        }
+    }
+
+    /// Enabling a tracking mode replaces the current one; disabling
+    /// any of them turns mouse reporting off.
+    fn set_mouse_tracking(&mut self, tracking: MouseTracking, on: bool) {
+        self.mouse_tracking = if on { tracking } else { MouseTracking::Off };
+    }
+
+    /// Disabling an encoding only has an effect if it is the current one
+    fn set_mouse_encoding(&mut self, encoding: MouseEncoding, on: bool) {
+        if on {
+            self.mouse_encoding = encoding;
+        } else if self.mouse_encoding == encoding {
+            self.mouse_encoding = MouseEncoding::X10;
+        }
+    }
+
+    /// Send a mouse report to the application if the tracking mode
+    /// calls for it.  `button` is the button that was pressed or
+    /// released, or that is held down during a Move.
+    fn report_mouse_event(
+        &mut self,
+        event: &MouseEvent,
+        button: MouseButton,
+        host: &mut TerminalHost,
+    ) -> Result<(), Error> {
+        if self.mouse_tracking.reports(event.kind, button) {
+            if let Some(report) =
+                encode_mouse_report(self.mouse_encoding, self.mouse_tracking, event, button)
+            {
+                host.writer().write_all(&report)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft Terminal Reset (DECSTR).
+    /// Restores the modes and state that an application may have changed
+    /// to their defaults, without touching the screen contents or moving
+    /// the cursor.
+    /// See https://vt100.net/docs/vt510-rm/DECSTR.html
+    fn soft_reset(&mut self) {
+        self.pen = CellAttributes::default();
+        self.cursor_visible = true;
+        self.insert_mode = false;
+        self.origin_mode = false;
+        self.application_cursor_keys = false;
+        self.application_keypad = false;
+        self.scroll_region = 0..self.screen().physical_rows as VisibleRowIndex;
+        self.left_and_right_margins = 0..self.screen().physical_cols;
+        self.charsets = CharSets::default();
+        self.saved_cursor = CursorPosition::default();
+        self.saved_charsets = CharSets::default();
+        self.wrap_next = false;
+    }
+
+    /// Reset to Initial State (RIS).
+    /// In addition to the soft reset, this leaves the alternate screen,
+    /// clears the screen and scrollback, homes the cursor and resets
+    /// the remaining modes, the tab stops and the title.
+    fn full_reset(&mut self) {
+        self.soft_reset();
+
+        let rows = self.screen.physical_rows;
+        let cols = self.screen.physical_cols;
+        let scrollback_size = self.screen.scrollback_size;
+        self.screen = Screen::new(rows, cols, scrollback_size);
+        self.alt_screen = Screen::new(rows, cols, 0);
+        self.alt_screen_is_active = false;
+        self.cursor = CursorPosition::default();
+        self.last_printed_char = None;
+        self.set_scroll_viewport(0);
+        self.clear_selection();
+
+        self.left_and_right_margin_mode = false;
+        self.newline_mode = false;
+        self.bracketed_paste = false;
+        self.keyboard_flags.clear();
+        self.alt_keyboard_flags.clear();
+        self.modify_other_keys = 0;
+        self.mouse_tracking = MouseTracking::Off;
+        self.mouse_encoding = MouseEncoding::X10;
+        self.focus_tracking = false;
+        self.synchronized_output = None;
+        self.current_mouse_button = MouseButton::None;
+        self.tabs = TabStop::new(cols, 8);
+        self.palette = self.default_palette.clone();
+        self.held_key = None;
+        self.dcs = None;
+        self.kitty_images.clear();
+        self.kitty_upload = None;
+        self.clear_search();
+        self.pending_clipboard_read = None;
+        self.current_dir = None;
+
+        // An empty title asks the host to restore its default title
+        self.answerback.push(AnswerBack::TitleChanged(String::new()));
+    }
+
     fn set_scroll_viewport(&mut self, position: VisibleRowIndex) {
         self.clear_selection();
         let position = position.max(0);
@@ -722,19 +1769,47 @@ impl TerminalState {
     }
 
     fn scroll_up(&mut self, num_rows: usize) {
-        self.clear_selection();
         let scroll_region = self.scroll_region.clone();
-        self.screen_mut().scroll_up(&scroll_region, num_rows)
+        self.scroll_rows_up(&scroll_region, num_rows)
     }
 
     fn scroll_down(&mut self, num_rows: usize) {
-        self.clear_selection();
         let scroll_region = self.scroll_region.clone();
-        self.screen_mut().scroll_down(&scroll_region, num_rows)
+        self.scroll_rows_down(&scroll_region, num_rows)
+    }
+
+    /// Scroll the portion of the specified rows that lies within the
+    /// left and right margins up by num_rows
+    fn scroll_rows_up(&mut self, rows: &Range<VisibleRowIndex>, num_rows: usize) {
+        self.clear_selection();
+        let num_rows = num_rows.min((rows.end - rows.start) as usize);
+        let margins = self.left_and_right_margins.clone();
+        let full_width = margins.start == 0 && margins.end >= self.screen().physical_cols;
+        let screen = self.screen_mut();
+        if full_width {
+            screen.scroll_up(rows, num_rows)
+        } else {
+            screen.scroll_up_within_margins(rows, &margins, num_rows)
+        }
+    }
+
+    /// Scroll the portion of the specified rows that lies within the
+    /// left and right margins down by num_rows
+    fn scroll_rows_down(&mut self, rows: &Range<VisibleRowIndex>, num_rows: usize) {
+        self.clear_selection();
+        let num_rows = num_rows.min((rows.end - rows.start) as usize);
+        let margins = self.left_and_right_margins.clone();
+        let full_width = margins.start == 0 && margins.end >= self.screen().physical_cols;
+        let screen = self.screen_mut();
+        if full_width {
+            screen.scroll_down(rows, num_rows)
+        } else {
+            screen.scroll_down_within_margins(rows, &margins, num_rows)
+        }
     }
 
     fn new_line(&mut self, move_to_first_column: bool) {
-        let x = if move_to_first_column { 0 } else { self.cursor.x };
+        let x = if move_to_first_column { self.carriage_return_column() } else { self.cursor.x };
         let y = self.cursor.y;
         let y = if y == self.scroll_region.end - 1 {
             self.scroll_up(1);
@@ -749,6 +1824,367 @@ impl TerminalState {
         self.answerback.push(AnswerBack::WriteToPty(buf.to_vec()));
     }
 
+    /// Record the header of a DCS sequence: the parameter bytes and
+    /// the final byte, which identifies its function.  This is called
+    /// before the parser calls hook().
+    pub(crate) fn set_dcs_header(&mut self, header: &[u8], final_byte: u8) {
+        // The parameters are followed by any intermediates
+        let len = header.iter().position(|&b| !(b.is_ascii_digit() || b == b';'));
+        let params = &header[..len.unwrap_or(header.len())];
+        self.dcs_params = params
+            .split(|&b| b == b';')
+            .map(|p| str::from_utf8(p).ok().and_then(|p| p.parse().ok()).unwrap_or(0))
+            .collect();
+        self.dcs_final = final_byte;
+    }
+
+    /// Display an image with its top left corner at the cursor
+    /// position.  Each row of cells that it covers holds a slice of
+    /// the image, so that it scrolls along with the text.  The cursor
+    /// moves to the line below the image, in the same column.
+    fn place_image(&mut self, image: ImageData) {
+        self.add_image_slices(Rc::new(image), 0, None, true);
+        self.c1_index();
+        self.wrap_next = false;
+    }
+
+    /// Add the slices of an image to the rows of cells that it covers,
+    /// starting at the cursor position, and return how many columns
+    /// it covers.  If `scroll` is true the cursor moves down to the
+    /// last of those rows, scrolling the screen as needed.  Otherwise
+    /// the cursor stays put and the image is cut off at the bottom of
+    /// the screen.
+    fn add_image_slices(
+        &mut self,
+        image: Rc<ImageData>,
+        z_index: i32,
+        placement: Option<ImagePlacement>,
+        scroll: bool,
+    ) -> usize {
+        let (cell_width, cell_height) = self.cell_size;
+        let cols = (image.width() + cell_width - 1) / cell_width;
+        let x = self.cursor.x;
+        let mut y = self.cursor.y;
+        let mut top = 0;
+        while top < image.height() && y < self.screen().physical_rows as i64 {
+            let height = cell_height.min(image.height() - top);
+            let line_idx = self.screen().phys_row(y);
+            self.screen_mut().line_mut(line_idx).add_image(ImageSlice {
+                image: Rc::clone(&image),
+                x,
+                cols,
+                top,
+                height,
+                z_index,
+                placement,
+            });
+            top += height;
+            if top < image.height() {
+                if scroll {
+                    self.c1_index();
+                    y = self.cursor.y;
+                } else {
+                    y += 1;
+                }
+            }
+        }
+        self.limit_line_images();
+        cols
+    }
+
+    /// Remove the oldest images from the lines of both screens until
+    /// their pixels fit in the budget.  The newest image is always kept.
+    fn limit_line_images(&mut self) {
+        // Images are numbered in the order in which they were created
+        let mut sizes = BTreeMap::new();
+        for line in self.screen.lines.iter().chain(self.alt_screen.lines.iter()) {
+            for slice in line.images() {
+                sizes.insert(slice.image.id(), slice.image.data().len());
+            }
+        }
+        let mut total: usize = sizes.values().sum();
+        let mut evicted = HashSet::new();
+        for (&id, &size) in sizes.iter().take(sizes.len().saturating_sub(1)) {
+            if total <= self.line_image_budget {
+                break;
+            }
+            total -= size;
+            evicted.insert(id);
+        }
+        if evicted.is_empty() {
+            return;
+        }
+        for line in self.screen.lines.iter_mut().chain(self.alt_screen.lines.iter_mut()) {
+            if line.remove_images(|slice| evicted.contains(&slice.image.id())) {
+                line.set_dirty();
+            }
+        }
+    }
+
+    /// Handle an APC string.  The parser ignores these, so they are
+    /// collected by the Terminal.
+    pub(crate) fn apc_dispatch(&mut self, data: &[u8]) {
+        match data.split_first() {
+            Some((&b'G', control)) => self.kitty_graphics(control),
+            _ => eprintln!("APC unhandled: {:?}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Carry out a kitty graphics command, and respond to it if the
+    /// application asked
+    fn kitty_graphics(&mut self, data: &[u8]) {
+        let cmd = match GraphicsCommand::parse(data) {
+            Ok(cmd) => cmd,
+            Err(err) => {
+                eprintln!("kitty graphics: {}", err);
+                self.kitty_upload = None;
+                return;
+            }
+        };
+        // The chunks that follow the first give only the m key
+        let mut cmd = match self.kitty_upload.take() {
+            Some(mut upload) => {
+                upload.payload.extend_from_slice(&cmd.payload);
+                upload.more = cmd.more;
+                upload
+            }
+            None => cmd,
+        };
+
+        let result = if cmd.payload.len() > self.max_image_size {
+            Err(format_err!("EFBIG:the image exceeds the limit of {} bytes", self.max_image_size))
+        } else if cmd.more {
+            self.kitty_upload = Some(cmd);
+            return;
+        } else {
+            self.kitty_action(&mut cmd)
+        };
+        if let Some(response) = cmd.response(&result) {
+            self.push_answerback(&response);
+        }
+    }
+
+    fn kitty_action(&mut self, cmd: &mut GraphicsCommand) -> Result<(), Error> {
+        match cmd.action {
+            b't' | b'T' | b'q' => {
+                // Otherwise a query could probe for arbitrary files
+                // without anything being displayed
+                if cmd.action == b'q' && (cmd.medium == b'f' || cmd.medium == b't') {
+                    bail!("EINVAL:queries can't read files");
+                }
+                let image = cmd.load(self.max_image_size)?;
+                // A query only checks that the image could be displayed
+                if cmd.action == b'q' {
+                    return Ok(());
+                }
+                let image = Rc::new(image);
+                if cmd.image_id != 0 || cmd.image_number != 0 {
+                    cmd.image_id =
+                        self.kitty_images.insert(cmd.image_id, cmd.image_number, Rc::clone(&image));
+                }
+                if cmd.action == b'T' {
+                    self.put_kitty_image(cmd, &image)?;
+                }
+                Ok(())
+            }
+            b'p' => {
+                if cmd.image_id == 0 {
+                    cmd.image_id = self.kitty_images.find_number(cmd.image_number).unwrap_or(0);
+                }
+                let image = self
+                    .kitty_images
+                    .get(cmd.image_id)
+                    .ok_or_else(|| format_err!("ENOENT:the image was not found"))?;
+                self.put_kitty_image(cmd, &image)
+            }
+            b'd' => {
+                self.delete_kitty_images(cmd);
+                Ok(())
+            }
+            action => bail!("EINVAL:unsupported action {}", action as char),
+        }
+    }
+
+    /// Display a kitty graphics image at the cursor position.  Unless
+    /// the application asks otherwise, the cursor moves to the cell
+    /// after the bottom right corner of the image.
+    fn put_kitty_image(
+        &mut self,
+        cmd: &GraphicsCommand,
+        image: &Rc<ImageData>,
+    ) -> Result<(), Error> {
+        let image = cmd.display_image(image, self.cell_size)?;
+        if cmd.placement_id != 0 {
+            // Placing it again moves it
+            let (image_id, placement_id) = (cmd.image_id, cmd.placement_id);
+            self.remove_kitty_placements(|p| {
+                p.image_id == image_id && p.placement_id == placement_id
+            });
+        }
+        let placement = ImagePlacement {
+            image_id: cmd.image_id,
+            placement_id: cmd.placement_id,
+            serial: self.kitty_images.next_serial(),
+        };
+
+        let x = self.cursor.x;
+        let move_cursor = cmd.cursor_movement != 1;
+        let cols = self.add_image_slices(image, cmd.z_index, Some(placement), move_cursor);
+        if move_cursor {
+            let y = self.cursor.y;
+            self.set_cursor_pos(&Position::Absolute((x + cols) as i64), &Position::Absolute(y));
+        }
+        Ok(())
+    }
+
+    /// Delete the kitty graphics placements selected by the d key.
+    /// The uppercase forms also free the images that they display.
+    fn delete_kitty_images(&mut self, cmd: &GraphicsCommand) {
+        let which = cmd.delete.to_ascii_lowercase();
+        let image_id = if which == b'n' {
+            match self.kitty_images.find_number(cmd.image_number) {
+                Some(id) => id,
+                None => return,
+            }
+        } else {
+            cmd.image_id
+        };
+        let cursor = (self.cursor.x, self.cursor.y as usize);
+        // Cells are given as 1-based coordinates
+        let cell = ((cmd.x as usize).saturating_sub(1), (cmd.y as usize).saturating_sub(1));
+
+        // Whether to delete the placement that a slice belongs to,
+        // given the row of the screen on which it is displayed, if any
+        let selected = |slice: &ImageSlice, placement: &ImagePlacement, row: Option<usize>| {
+            let at = |(x, y): (usize, usize)| row == Some(y) && slice.intersects(&(x..x + 1));
+            match which {
+                b'a' => row.is_some(),
+                b'i' | b'n' => {
+                    image_id != 0
+                        && placement.image_id == image_id
+                        && (cmd.placement_id == 0 || placement.placement_id == cmd.placement_id)
+                }
+                b'c' => at(cursor),
+                b'p' => at(cell),
+                b'q' => at(cell) && slice.z_index == cmd.z_index,
+                b'x' => row.is_some() && slice.intersects(&(cell.0..cell.0 + 1)),
+                b'y' => row == Some(cell.1),
+                b'z' => row.is_some() && slice.z_index == cmd.z_index,
+                b'r' => placement.image_id >= cmd.x && placement.image_id <= cmd.y,
+                _ => false,
+            }
+        };
+
+        let mut placements = HashSet::new();
+        {
+            let screen = self.screen();
+            let first_visible = screen.lines.len() - screen.physical_rows;
+            for (idx, line) in screen.lines.iter().enumerate() {
+                let row = idx.checked_sub(first_visible);
+                for slice in line.images() {
+                    if let Some(ref placement) = slice.placement {
+                        if selected(slice, placement, row) {
+                            placements.insert(*placement);
+                        }
+                    }
+                }
+            }
+        }
+        self.remove_kitty_placements(|p| placements.contains(p));
+
+        if cmd.delete.is_ascii_uppercase() {
+            match which {
+                b'i' | b'n' => self.kitty_images.remove(image_id),
+                b'r' => self.kitty_images.remove_range(cmd.x, cmd.y),
+                _ => {
+                    for placement in &placements {
+                        self.kitty_images.remove(placement.image_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the kitty graphics placements that match a predicate
+    /// from the screen and its scrollback
+    fn remove_kitty_placements<F: Fn(&ImagePlacement) -> bool>(&mut self, remove: F) {
+        for line in self.screen_mut().lines.iter_mut() {
+            if line.remove_images(|slice| slice.placement.as_ref().map_or(false, |p| remove(p))) {
+                line.set_dirty();
+            }
+        }
+    }
+
+    /// The DECRQM state of a DEC private mode: 0 if the mode is not
+    /// recognized, 1 if it is set, 2 if it is reset, 3 if it is
+    /// permanently set and 4 if it is permanently reset.
+    fn dec_private_mode_state(&self, mode: i64) -> u8 {
+        let set = match mode {
+            1 => self.application_cursor_keys,
+            6 => self.origin_mode,
+            // Auto wrap is always enabled
+            7 => return 3,
+            // The cursor doesn't blink
+            12 => return 4,
+            25 => self.cursor_visible,
+            69 => self.left_and_right_margin_mode,
+            9 => self.mouse_tracking == MouseTracking::X10,
+            1000 => self.mouse_tracking == MouseTracking::Normal,
+            1002 => self.mouse_tracking == MouseTracking::ButtonEvent,
+            1003 => self.mouse_tracking == MouseTracking::AnyEvent,
+            1004 => self.focus_tracking,
+            1005 => self.mouse_encoding == MouseEncoding::Utf8,
+            1006 => self.mouse_encoding == MouseEncoding::Sgr,
+            1015 => self.mouse_encoding == MouseEncoding::Urxvt,
+            1049 => self.alt_screen_is_active,
+            2004 => self.bracketed_paste,
+            2026 => self.synchronized_output.is_some(),
+            _ => return 0,
+        };
+        if set {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// The DECRQM state of an ANSI mode, using the same values as
+    /// dec_private_mode_state().
+    fn mode_state(&self, mode: i64) -> u8 {
+        let set = match mode {
+            4 => self.insert_mode,
+            20 => self.newline_mode,
+            _ => return 0,
+        };
+        if set {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// DECRQSS: report a setting, which is named by the final bytes of
+    /// the control function that changes it.
+    fn report_status_string(&mut self, setting: &[u8]) {
+        let report = match setting {
+            b"m" => Some(format!("{}m", sgr_params(&self.pen))),
+            b"r" => Some(format!("{};{}r", self.scroll_region.start + 1, self.scroll_region.end)),
+            b"s" => Some(format!(
+                "{};{}s",
+                self.left_and_right_margins.start + 1,
+                self.left_and_right_margins.end
+            )),
+            // DECSCL: the conformance level, which matches DEVICE_IDENT,
+            // with 7-bit controls
+            b"\"p" => Some("62;1\"p".to_string()),
+            _ => None,
+        };
+        match report {
+            Some(report) => self.push_answerback(format!("\x1bP1$r{}\x1b\\", report).as_bytes()),
+            None => self.push_answerback(b"\x1bP0$r\x1b\\"),
+        }
+    }
+
     pub(crate) fn drain_answerback(&mut self) -> Option<Vec<AnswerBack>> {
         if self.answerback.len() == 0 {
             None
@@ -784,9 +2220,29 @@ impl TerminalState {
     /// Moves the cursor to the next tab stop. If there are no more tab stops,
     /// the cursor moves to the right margin. HT does not cause text to auto wrap.
     fn c0_horizontal_tab(&mut self) {
+        let right = if self.cursor.x < self.left_and_right_margins.end {
+            self.left_and_right_margins.end - 1
+        } else {
+            self.screen().physical_cols - 1
+        };
         let x = match self.tabs.find_next_tab_stop(self.cursor.x) {
-            Some(x) => x,
-            None => self.screen().physical_cols - 1,
+            Some(x) => x.min(right),
+            None => right,
+        };
+        self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Relative(0));
+    }
+
+    /// Moves the cursor to the previous tab stop, stopping at the left
+    /// margin if the cursor is within the margins.
+    fn tab_backward(&mut self) {
+        let left = if self.cursor.x >= self.left_and_right_margins.start {
+            self.left_and_right_margins.start
+        } else {
+            0
+        };
+        let x = match self.tabs.find_prev_tab_stop(self.cursor.x) {
+            Some(x) => x.max(left),
+            None => left,
         };
         self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Relative(0));
     }
@@ -824,10 +2280,52 @@ impl TerminalState {
                     screen.set_cell(x, y, ' ', &blank);
                 }
             }
-            CSIAction::SoftReset => {
-                self.pen = CellAttributes::default();
-                // TODO: see https://vt100.net/docs/vt510-rm/DECSTR.html
+            CSIAction::InsertCharacter(n) => {
+                if in_range(self.cursor.x, &self.left_and_right_margins) {
+                    let x = self.cursor.x;
+                    let y = self.cursor.y;
+                    let right_margin = self.left_and_right_margins.end;
+                    self.screen_mut().insert_cells(x, y, n.max(0) as usize, right_margin);
+                }
+            }
+            CSIAction::DeleteCharacter(n) => {
+                if in_range(self.cursor.x, &self.left_and_right_margins) {
+                    let x = self.cursor.x;
+                    let y = self.cursor.y;
+                    let right_margin = self.left_and_right_margins.end;
+                    self.screen_mut().delete_cells(x, y, n.max(0) as usize, right_margin);
+                }
+            }
+            CSIAction::RepeatCharacter(n) => {
+                if let Some(c) = self.last_printed_char {
+                    // Repeating more than a screenful has no further
+                    // visible effect, so don't let a huge count stall us
+                    let limit = self.screen().physical_rows * self.screen().physical_cols;
+                    for _ in 0..(n.max(0) as usize).min(limit) {
+                        self.print_char(c);
+                    }
+                }
+            }
+            CSIAction::TabForward(n) => {
+                // There can't be more tab stops than columns
+                let limit = self.screen().physical_cols;
+                for _ in 0..(n.max(0) as usize).min(limit) {
+                    self.c0_horizontal_tab();
+                }
+            }
+            CSIAction::TabBackward(n) => {
+                let limit = self.screen().physical_cols;
+                for _ in 0..(n.max(0) as usize).min(limit) {
+                    self.tab_backward();
+                }
             }
+            CSIAction::ClearTabStop(TabulationClear::CurrentColumn) => {
+                self.tabs.clear_tab_stop(self.cursor.x);
+            }
+            CSIAction::ClearTabStop(TabulationClear::All) => {
+                self.tabs.clear_all_tab_stops();
+            }
+            CSIAction::SoftReset => self.soft_reset(),
             CSIAction::SetPenNoLink(pen) => {
                 let link = self.pen.hyperlink.take();
                 self.pen = pen;
@@ -861,7 +2359,7 @@ impl TerminalState {
                 self.pen.set_invisible(on);
             }
             CSIAction::SetCursorXY { x, y } => {
-                self.set_cursor_pos(&x, &y);
+                self.move_cursor(&x, &y);
             }
             CSIAction::EraseInLine(erase) => {
                 let cx = self.cursor.x;
@@ -912,11 +2410,40 @@ impl TerminalState {
             CSIAction::SetDecPrivateMode(DecPrivateMode::ShowCursor, on) => {
                 self.cursor_visible = on;
             }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::X10Mouse, on) => {
+                self.set_mouse_tracking(MouseTracking::X10, on);
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::NormalMouse, on) => {
+                self.set_mouse_tracking(MouseTracking::Normal, on);
+            }
             CSIAction::SetDecPrivateMode(DecPrivateMode::ButtonEventMouse, on) => {
-                self.button_event_mouse = on;
+                self.set_mouse_tracking(MouseTracking::ButtonEvent, on);
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::AnyEventMouse, on) => {
+                self.set_mouse_tracking(MouseTracking::AnyEvent, on);
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::FocusTracking, on) => {
+                self.focus_tracking = on;
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::SynchronizedOutput, on) => {
+                self.synchronized_output = if on { Some(Instant::now()) } else { None };
+            }
+            CSIAction::RequestDecPrivateMode(mode) => {
+                let state = self.dec_private_mode_state(mode);
+                self.push_answerback(format!("\x1b[?{};{}$y", mode, state).as_bytes());
+            }
+            CSIAction::RequestMode(mode) => {
+                let state = self.mode_state(mode);
+                self.push_answerback(format!("\x1b[{};{}$y", mode, state).as_bytes());
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::Utf8Mouse, on) => {
+                self.set_mouse_encoding(MouseEncoding::Utf8, on);
             }
             CSIAction::SetDecPrivateMode(DecPrivateMode::SGRMouse, on) => {
-                self.sgr_mouse = on;
+                self.set_mouse_encoding(MouseEncoding::Sgr, on);
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::UrxvtMouse, on) => {
+                self.set_mouse_encoding(MouseEncoding::Urxvt, on);
             }
             CSIAction::SetDecPrivateMode(DecPrivateMode::ClearAndEnableAlternateScreen, on) => {
                 // TODO: some folks like to disable alt screen
@@ -927,11 +2454,13 @@ impl TerminalState {
                         self.set_cursor_pos(&Position::Absolute(0), &Position::Absolute(0));
                         self.perform_csi(CSIAction::EraseInDisplay(DisplayErase::All));
                         self.set_scroll_viewport(0);
+                        self.reset_search();
                     }
                     (false, true) => {
                         self.alt_screen_is_active = false;
                         self.perform_csi(CSIAction::RestoreCursor);
                         self.set_scroll_viewport(0);
+                        self.reset_search();
                     }
                     _ => {}
                 }
@@ -942,13 +2471,36 @@ impl TerminalState {
             CSIAction::SetDecPrivateMode(DecPrivateMode::BrackedPaste, on) => {
                 self.bracketed_paste = on;
             }
+            CSIAction::SetMode(TerminalMode::Insert, on) => {
+                self.insert_mode = on;
+            }
+            CSIAction::SetMode(TerminalMode::AutomaticNewline, on) => {
+                self.newline_mode = on;
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::OriginMode, on) => {
+                self.origin_mode = on;
+                // Changing the mode moves the cursor to the new home position
+                self.move_cursor(&Position::Absolute(0), &Position::Absolute(0));
+            }
+            CSIAction::SetDecPrivateMode(DecPrivateMode::LeftAndRightMarginMode, on) => {
+                self.left_and_right_margin_mode = on;
+                if !on {
+                    self.left_and_right_margins = 0..self.screen().physical_cols;
+                }
+            }
             CSIAction::DeviceStatusReport => {
                 // "OK"
                 self.push_answerback(b"\x1b[0n");
             }
             CSIAction::ReportCursorPosition => {
-                let row = self.cursor.y + 1;
-                let col = self.cursor.x + 1;
+                let (row, col) = if self.origin_mode {
+                    (
+                        self.cursor.y - self.scroll_region.start + 1,
+                        self.cursor.x - self.left_and_right_margins.start + 1,
+                    )
+                } else {
+                    (self.cursor.y + 1, self.cursor.x + 1)
+                };
                 self.push_answerback(format!("\x1b[{};{}R", row, col).as_bytes());
             }
             CSIAction::SetScrollingRegion { top, bottom } => {
@@ -959,32 +2511,105 @@ impl TerminalState {
                     std::mem::swap(&mut top, &mut bottom);
                 }
                 self.scroll_region = top..bottom + 1;
+                // Like DECSLRM, this homes the cursor, which depends on DECOM
+                self.move_cursor(&Position::Absolute(0), &Position::Absolute(0));
+            }
+            CSIAction::SetLeftAndRightMargins { left, right } => {
+                if self.left_and_right_margin_mode {
+                    let cols = self.screen().physical_cols as i64;
+                    let left = left.max(0).min(cols - 1);
+                    let right = right.max(0).min(cols - 1);
+                    // The margins must be at least two columns apart
+                    if left < right {
+                        self.left_and_right_margins = left as usize..right as usize + 1;
+                    }
+                    // As in xterm, setting the margins moves the cursor to the
+                    // home position, which depends on DECOM
+                    self.move_cursor(&Position::Absolute(0), &Position::Absolute(0));
+                } else {
+                    self.perform_csi(CSIAction::SaveCursor);
+                }
             }
-            CSIAction::RequestDeviceAttributes => {
+            CSIAction::RequestPrimaryDeviceAttributes => {
                 self.push_answerback(DEVICE_IDENT);
             }
+            CSIAction::RequestSecondaryDeviceAttributes => {
+                // We identify as a VT220 (1), followed by our version
+                // and the ROM cartridge registration number (0)
+                self.push_answerback(format!("\x1b[>1;{};0c", version_number()).as_bytes());
+            }
+            CSIAction::RequestTertiaryDeviceAttributes => {
+                // DECRPTUI: the unit id, which we don't have
+                self.push_answerback(b"\x1bP!|00000000\x1b\\");
+            }
+            CSIAction::RequestTerminalNameAndVersion => {
+                self.push_answerback(format!("\x1bP>|{}\x1b\\", TERMINAL_VERSION).as_bytes());
+            }
+            CSIAction::PushKeyboardFlags(flags) => {
+                let stack = self.keyboard_flags_stack();
+                if stack.len() == MAX_KEYBOARD_FLAGS_DEPTH {
+                    stack.remove(0);
+                }
+                stack.push(KeyboardFlags::from_bits_truncate(flags as u8));
+            }
+            CSIAction::PopKeyboardFlags(n) => {
+                let stack = self.keyboard_flags_stack();
+                let len = stack.len().saturating_sub(n.max(0) as usize);
+                stack.truncate(len);
+            }
+            CSIAction::SetKeyboardFlags { flags, mode } => {
+                let flags = KeyboardFlags::from_bits_truncate(flags as u8);
+                let stack = self.keyboard_flags_stack();
+                if stack.is_empty() {
+                    stack.push(KeyboardFlags::default());
+                }
+                if let Some(current) = stack.last_mut() {
+                    match mode {
+                        1 => *current = flags,
+                        2 => current.insert(flags),
+                        3 => current.remove(flags),
+                        _ => {}
+                    }
+                }
+            }
+            CSIAction::RequestKeyboardFlags => {
+                let flags = self.current_keyboard_flags();
+                self.push_answerback(format!("\x1b[?{}u", flags.bits()).as_bytes());
+            }
+            CSIAction::SetModifyOtherKeys(level) => {
+                self.modify_other_keys = level.max(0).min(2) as u8;
+            }
+            CSIAction::RequestModifyOtherKeys => {
+                self.push_answerback(format!("\x1b[>4;{}m", self.modify_other_keys).as_bytes());
+            }
             CSIAction::DeleteLines(n) => {
-                if in_range(self.cursor.y, &self.scroll_region) {
+                if in_range(self.cursor.y, &self.scroll_region)
+                    && in_range(self.cursor.x, &self.left_and_right_margins)
+                {
                     let scroll_region = self.cursor.y..self.scroll_region.end;
-                    self.screen_mut().scroll_up(&scroll_region, n as usize);
+                    self.scroll_rows_up(&scroll_region, n.max(0) as usize);
                 }
             }
             CSIAction::InsertLines(n) => {
-                if in_range(self.cursor.y, &self.scroll_region) {
+                if in_range(self.cursor.y, &self.scroll_region)
+                    && in_range(self.cursor.x, &self.left_and_right_margins)
+                {
                     let scroll_region = self.cursor.y..self.scroll_region.end;
-                    self.screen_mut().scroll_down(&scroll_region, n as usize);
+                    self.scroll_rows_down(&scroll_region, n.max(0) as usize);
                 }
             }
             CSIAction::SaveCursor => {
                 self.saved_cursor = self.cursor;
+                self.saved_charsets = self.charsets;
             }
             CSIAction::RestoreCursor => {
+                self.charsets = self.saved_charsets;
                 let x = self.saved_cursor.x;
                 let y = self.saved_cursor.y;
                 self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Absolute(y));
             }
             CSIAction::LinePosition(row) => {
-                self.set_cursor_pos(&Position::Relative(0), &row);
+                self.move_cursor(&Position::Relative(0), &row);
             }
             CSIAction::ScrollLines(amount) => {
                 if amount > 0 {
@@ -995,21 +2620,36 @@ impl TerminalState {
             }
         }
     }
-}
 
-impl vte::Perform for TerminalState {
-    /// Draw a character to the screen
-    fn print(&mut self, c: char) {
+    /// Draw a character to the screen at the cursor position, after
+    /// any character set translation has been applied.
+    fn print_char(&mut self, c: char) {
         if self.wrap_next {
+            // Remember that the text continues on the next line so
+            // that it can be rewrapped if the terminal is resized.
+            let y = self.cursor.y;
+            let line_idx = self.screen().phys_row(y);
+            self.screen_mut().line_mut(line_idx).set_wrapped(true);
             self.new_line(true);
         }
 
         let x = self.cursor.x;
         let y = self.cursor.y;
-        let width = self.screen().physical_cols;
+        // Text wraps at the right margin, unless the cursor is
+        // already beyond it
+        let width = if x < self.left_and_right_margins.end {
+            self.left_and_right_margins.end
+        } else {
+            self.screen().physical_cols
+        };
 
         let pen = self.pen.clone();
 
+        if self.insert_mode {
+            let cell_width = Cell::from_char(c, &pen).width();
+            self.screen_mut().insert_cells(x, y, cell_width, width);
+        }
+
         // Assign the cell and extract its printable width
         let print_width = {
             let cell = self.screen_mut().set_cell(x, y, c, &pen);
@@ -1037,26 +2677,173 @@ impl vte::Perform for TerminalState {
             self.wrap_next = true;
         }
     }
+}
+
+/// Our version, as reported in the secondary device attributes.
+/// This must be a number, so 1.2.3 is reported as 10203.
+fn version_number() -> u32 {
+    let part = |s: &str| s.parse::<u32>().unwrap_or(0);
+    part(env!("CARGO_PKG_VERSION_MAJOR")) * 10000
+        + part(env!("CARGO_PKG_VERSION_MINOR")) * 100
+        + part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+/// Express the attributes as SGR parameters, for DECRQSS.
+/// The parameters begin with a reset so that they can be replayed.
+fn sgr_params(attrs: &CellAttributes) -> String {
+    let mut params = vec!["0".to_string()];
+    match attrs.intensity() {
+        Intensity::Normal => {}
+        Intensity::Bold => params.push("1".to_string()),
+        Intensity::Half => params.push("2".to_string()),
+    }
+    if attrs.italic() {
+        params.push("3".to_string());
+    }
+    match attrs.underline() {
+        Underline::None => {}
+        Underline::Single => params.push("4".to_string()),
+        Underline::Double => params.push("21".to_string()),
+    }
+    if attrs.blink() {
+        params.push("5".to_string());
+    }
+    if attrs.reverse() {
+        params.push("7".to_string());
+    }
+    if attrs.invisible() {
+        params.push("8".to_string());
+    }
+    if attrs.strikethrough() {
+        params.push("9".to_string());
+    }
+    for &(color, base) in &[(&attrs.foreground, 30), (&attrs.background, 40)] {
+        match *color {
+            ColorAttribute::Foreground | ColorAttribute::Background => {}
+            ColorAttribute::PaletteIndex(idx) if idx < 8 => {
+                params.push((base + idx as u32).to_string());
+            }
+            ColorAttribute::PaletteIndex(idx) if idx < 16 => {
+                params.push((base + 60 + idx as u32 - 8).to_string());
+            }
+            ColorAttribute::PaletteIndex(idx) => {
+                params.push(format!("{};5;{}", base + 8, idx));
+            }
+            ColorAttribute::Rgb(rgb) => {
+                params.push(format!("{};2;{};{};{}", base + 8, rgb.red, rgb.green, rgb.blue));
+            }
+        }
+    }
+    params.join(";")
+}
+
+/// Split a `file://host/path` URL into its host and its path,
+/// decoding any percent-encoded bytes in the path
+fn parse_file_url(url: &str) -> Option<(&str, String)> {
+    if !url.starts_with("file://") {
+        return None;
+    }
+    let rest = &url[7..];
+    let slash = rest.find('/')?;
+    let (host, path) = (&rest[..slash], &rest[slash..]);
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            decoded.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(b);
+        }
+    }
+    String::from_utf8(decoded).ok().map(|path| (host, path))
+}
+
+/// Returns true if the host of a `file://` URL refers to this machine
+fn is_local_host(host: &str) -> bool {
+    host.is_empty()
+        || host.eq_ignore_ascii_case("localhost")
+        || local_hostname().map_or(false, |name| host.eq_ignore_ascii_case(&name))
+}
+
+/// Returns the name of this machine
+pub(crate) fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+impl vte::Perform for TerminalState {
+    /// Draw a character to the screen
+    fn print(&mut self, c: char) {
+        let c = self.charsets.translate(c);
+        self.last_printed_char = Some(c);
+        self.print_char(c);
+    }
 
     fn execute(&mut self, byte: u8) {
         debug!("execute {:02x}", byte);
         match byte {
             b'\n' | 0x0b /* VT */ | 0x0c /* FF */ => {
-                self.new_line(true /* TODO: depend on terminal mode */)
+                let newline_mode = self.newline_mode;
+                self.new_line(newline_mode)
             }
             b'\r' => /* CR */ {
-                self.set_cursor_pos(&Position::Absolute(0), &Position::Relative(0));
+                let x = self.carriage_return_column();
+                self.set_cursor_pos(&Position::Absolute(x as i64), &Position::Relative(0));
             }
             0x08 /* BS */ => {
-                self.set_cursor_pos(&Position::Relative(-1), &Position::Relative(0));
+                self.move_cursor(&Position::Relative(-1), &Position::Relative(0));
             }
             b'\t' => self.c0_horizontal_tab(),
+            // Shift Out (SO): invoke G1
+            0x0e => self.charsets.locking_shift(1),
+            // Shift In (SI): invoke G0
+            0x0f => self.charsets.locking_shift(0),
             _ => println!("unhandled vte execute {}", byte),
         }
     }
-    fn hook(&mut self, _: &[i64], _: &[u8], _: bool) {}
-    fn put(&mut self, _: u8) {}
-    fn unhook(&mut self) {}
+    fn hook(&mut self, _: &[i64], intermediates: &[u8], ignore: bool) {
+        let params = &self.dcs_params;
+        self.dcs = match (self.dcs_final, intermediates) {
+            _ if ignore => None,
+            (b'q', &[]) => Some(DeviceControl::Sixel(SixelBuilder::new(params))),
+            (b'q', &[b'$']) => Some(DeviceControl::RequestStatusString(Vec::new())),
+            (b, i) => {
+                println!("DCS unhandled {} {:?} {:?}", b as char, params, i);
+                None
+            }
+        };
+    }
+    fn put(&mut self, byte: u8) {
+        match self.dcs {
+            Some(DeviceControl::RequestStatusString(ref mut setting)) => {
+                if setting.len() < MAX_STATUS_STRING_LEN {
+                    setting.push(byte);
+                }
+            }
+            Some(DeviceControl::Sixel(ref mut sixel)) => sixel.put(byte),
+            None => {}
+        }
+    }
+    fn unhook(&mut self) {
+        match self.dcs.take() {
+            Some(DeviceControl::RequestStatusString(setting)) => {
+                self.report_status_string(&setting);
+            }
+            Some(DeviceControl::Sixel(sixel)) => {
+                if let Some(image) = sixel.finish() {
+                    self.place_image(image);
+                }
+            }
+            None => {}
+        }
+    }
     fn osc_dispatch(&mut self, osc: &[&[u8]]) {
         match osc {
             &[b"0", title] => {
@@ -1066,6 +2853,18 @@ impl vte::Perform for TerminalState {
                     eprintln!("OSC: failed to decode utf title for {:?}", title);
                 }
             }
+            &[b"4", ..] => self.set_palette_colors(&osc[1..]),
+            &[b"7", ..] => self.set_current_dir(&osc[1..]),
+            &[b"133", ..] => self.semantic_prompt(&osc[1..]),
+            &[b"1337", ..] => self.inline_image(&osc[1..]),
+            &[b"10", ..] => self.set_dynamic_colors(10, &osc[1..]),
+            &[b"11", ..] => self.set_dynamic_colors(11, &osc[1..]),
+            &[b"12", ..] => self.set_dynamic_colors(12, &osc[1..]),
+            &[b"104", ..] => self.reset_palette_colors(&osc[1..]),
+            &[b"110", ..] => self.reset_dynamic_color(10),
+            &[b"111", ..] => self.reset_dynamic_color(11),
+            &[b"112", ..] => self.reset_dynamic_color(12),
+            &[b"52", selection, data] => self.clipboard_osc(selection, data),
             &[b"8", params, url] => {
                 // Hyperlinks per:
                 // https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
@@ -1139,14 +2938,23 @@ impl vte::Perform for TerminalState {
             // Horizontal Tab Set (HTS)
             (b'H', &[], &[]) => self.c1_hts(),
 
-            // Enable alternate character set mode (smacs)
-            (b'0', &[b'('], &[]) => {
-                debug!("ESC: smacs");
-            }
-            // Exit alternate character set mode (rmacs)
-            (b'B', &[b'('], &[]) => {
-                debug!("ESC: rmacs");
-            }
+            // Designate G0-G3 Character Set, eg: `ESC ( 0` is used
+            // by curses to enter the line drawing set (smacs)
+            (designator, &[idx @ b'('...b'+'], &[]) => match CharSet::from_designator(designator) {
+                Some(charset) => self.charsets.designate((idx - b'(') as usize, charset),
+                None => println!("ESC: unhandled character set {}", designator as char),
+            },
+            // Locking Shift 2 (LS2)
+            (b'n', &[], &[]) => self.charsets.locking_shift(2),
+            // Locking Shift 3 (LS3)
+            (b'o', &[], &[]) => self.charsets.locking_shift(3),
+            // Single Shift 2 (SS2)
+            (b'N', &[], &[]) => self.charsets.single_shift(2),
+            // Single Shift 3 (SS3)
+            (b'O', &[], &[]) => self.charsets.single_shift(3),
+
+            // Reset to Initial State (RIS)
+            (b'c', &[], &[]) => self.full_reset(),
 
             // DECSC - Save Cursor
             (b'7', &[], &[]) => self.perform_csi(CSIAction::SaveCursor),
@@ -1162,3 +2970,21 @@ impl vte::Perform for TerminalState {
         }
     }
 }
+
+/// Returns the highlights for the matches that intersect the specified
+/// row, where `current` is the index of the current match
+fn highlights_for_row(
+    matches: &[SearchMatch],
+    current: Option<usize>,
+    row: ScrollbackOrVisibleRowIndex,
+) -> Vec<SearchHighlight> {
+    matches
+        .iter()
+        .enumerate()
+        .filter(|&(_, m)| row >= m.start.y && row <= m.end.y)
+        .map(|(idx, m)| SearchHighlight {
+            cols: m.cols_for_row(row),
+            current: Some(idx) == current,
+        })
+        .collect()
+}
@@ -10,14 +10,28 @@ fn test_bs() {
     term.assert_cursor_pos(0, 0, Some("cannot move left of the margin"));
     term.print("ab\x08");
     term.assert_cursor_pos(1, 0, None);
-    // TODO: when we can set the left margin, we should test that here
+
+    term.set_mode("?69", true);
+    term.set_left_and_right_margins(1, 3);
+    term.cup(1, 0);
+    term.print("\x08");
+    term.assert_cursor_pos(1, 0, Some("cannot move left of the left margin"));
+    term.cup(0, 0);
+    term.print("\x08");
+    term.assert_cursor_pos(0, 0, Some("cannot move left of the screen"));
 }
 
 #[test]
 fn test_lf() {
     let mut term = TestTerm::new(3, 10, 0);
     term.print("hello\n");
-    term.assert_cursor_pos(0, 1, Some("LF moves to new line"));
+    term.assert_cursor_pos(5, 1, Some("LF moves to new line"));
+    term.print("\x1b[20h\n");
+    term.assert_cursor_pos(0, 2, Some("LNM: LF also moves to the left margin"));
+    term.print("hi\x0b");
+    term.assert_cursor_pos(0, 2, Some("VT scrolls at the bottom and obeys LNM"));
+    term.print("\x1b[20lhi\x0c");
+    term.assert_cursor_pos(2, 2, Some("FF acts as LF when LNM is reset"));
 }
 
 #[test]
@@ -25,7 +39,15 @@ fn test_cr() {
     let mut term = TestTerm::new(3, 10, 0);
     term.print("hello\r");
     term.assert_cursor_pos(0, 0, Some("CR moves to left margin on current line"));
-    // TODO: when we can set the left margin, we should test that here
+
+    term.set_mode("?69", true);
+    term.set_left_and_right_margins(2, 8);
+    term.cup(5, 1);
+    term.print("\r");
+    term.assert_cursor_pos(2, 1, Some("CR moves to the left margin"));
+    term.cup(1, 1);
+    term.print("\r");
+    term.assert_cursor_pos(0, 1, Some("CR moves to column 0 when left of the margin"));
 }
 
 #[test]
@@ -0,0 +1,36 @@
+//! Testing character set designation and shifts
+
+use super::*;
+
+#[test]
+fn test_dec_special_graphics() {
+    let mut term = TestTerm::new(2, 5, 0);
+    term.print("\x1b(0lqqk\x1b(B");
+    term.print("\r\nlqqk");
+    assert_visible_contents(&term, &["┌──┐ ", "lqqk "]);
+}
+
+#[test]
+fn test_shift_out_and_in() {
+    let mut term = TestTerm::new(1, 6, 0);
+    term.print("\x1b)0");
+    term.print("x\x0ex\x0fx");
+    assert_visible_contents(&term, &["x│x   "]);
+}
+
+#[test]
+fn test_single_shift() {
+    let mut term = TestTerm::new(1, 6, 0);
+    term.print("\x1b*0\x1b+A");
+    term.print("\x1bNqq\x1bO##");
+    assert_visible_contents(&term, &["─q£#  "]);
+    term.print("\x1bo#\x1bnq");
+    assert_visible_contents(&term, &["─q£#£─"]);
+}
+
+#[test]
+fn test_save_charsets_with_cursor() {
+    let mut term = TestTerm::new(1, 4, 0);
+    term.print("\x1b(0\x1b7\x1b(Bq\x1b8q");
+    assert_visible_contents(&term, &["─   "]);
+}
@@ -0,0 +1,90 @@
+//! Testing OSC 52 clipboard access
+
+use super::*;
+
+#[test]
+fn test_set_clipboard() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.print("\x1b]52;c;aGVsbG8=\x07");
+    assert_eq!(term.host.clip, Some("hello".to_owned()));
+
+    term.print("\x1b]52;p;d29ybGQ=\x1b\\");
+    assert_eq!(term.host.clip, Some("world".to_owned()));
+
+    term.print("\x1b]52;c;\x07");
+    assert_eq!(term.host.clip, None);
+
+    // Invalid base64 and selections leave the clipboard alone
+    term.host.clip = Some("keep".to_owned());
+    term.print("\x1b]52;c;!!!!\x07");
+    term.print("\x1b]52;x;aGVsbG8=\x07");
+    assert_eq!(term.host.clip, Some("keep".to_owned()));
+
+    // The parser returns to the ground state afterwards
+    term.print("ab");
+    assert_visible_contents(&term, &["ab  ", "    "]);
+}
+
+#[test]
+fn test_set_clipboard_after_dcs() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.print("\x1bP$qm\x1b\\\x1b]52;c;aGVsbG8=\x07ab");
+    assert_eq!(term.take_written(), "\x1bP1$r0m\x1b\\");
+    assert_eq!(term.host.clip, Some("hello".to_owned()));
+    assert_visible_contents(&term, &["ab  ", "    "]);
+}
+
+#[test]
+fn test_set_clipboard_beyond_vte_limit() {
+    let mut term = TestTerm::new(2, 4, 0);
+    let text = "x".repeat(4096);
+    term.print(format!("\x1b]52;c;{}\x1b\\", base64::encode(text.as_bytes())));
+    assert_eq!(term.host.clip, Some(text));
+
+    let text = "y".repeat(MAX_CLIPBOARD_LEN + 1);
+    term.print(format!("\x1b]52;c;{}\x1b\\", base64::encode(text.as_bytes())));
+    assert_eq!(term.host.clip.as_ref().map(|c| c.len()), Some(4096), "too large");
+}
+
+#[test]
+fn test_query_clipboard() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.host.clip = Some("hello".to_owned());
+
+    term.print("\x1b]52;c;?\x07");
+    assert_eq!(term.take_written(), "", "reads are denied by default");
+
+    term.set_clipboard_access(ClipboardAccess::AllowRead);
+    term.print("\x1b]52;c;?\x07");
+    assert_eq!(term.take_written(), "\x1b]52;c;aGVsbG8=\x1b\\");
+}
+
+#[test]
+fn test_clipboard_access_deny() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.set_clipboard_access(ClipboardAccess::Deny);
+    term.print("\x1b]52;c;aGVsbG8=\x07");
+    term.print("\x1b]52;c;?\x07");
+    assert_eq!(term.host.clip, None);
+    assert_eq!(term.take_written(), "");
+}
+
+#[test]
+fn test_clipboard_access_ask() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.set_clipboard_access(ClipboardAccess::Ask);
+
+    term.print("\x1b]52;c;aGVsbG8=\x07");
+    assert_eq!(term.host.clip, Some("hello".to_owned()), "writes don't ask");
+
+    term.print("\x1b]52;c;?\x07");
+    assert!(term.has_pending_clipboard_read());
+    assert_eq!(term.take_written(), "");
+    term.answer_clipboard_read(false);
+    assert!(!term.has_pending_clipboard_read());
+    assert_eq!(term.take_written(), "");
+
+    term.print("\x1b]52;p;?\x07");
+    term.answer_clipboard_read(true);
+    assert_eq!(term.take_written(), "\x1b]52;p;aGVsbG8=\x1b\\");
+}
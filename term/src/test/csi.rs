@@ -4,11 +4,11 @@ use super::*;
 fn test_vpa() {
     let mut term = TestTerm::new(3, 4, 0);
     term.assert_cursor_pos(0, 0, None);
-    term.print("a\nb\nc");
+    term.print("a\r\nb\r\nc");
     term.assert_cursor_pos(1, 2, None);
     term.print("\x1b[d");
     term.assert_cursor_pos(1, 0, None);
-    term.print("\n\n");
+    term.print("\r\n\r\n");
     term.assert_cursor_pos(0, 2, None);
 
     // escapes are 1-based, so check that we're handling that
@@ -66,7 +66,7 @@ fn test_hvp() {
 #[test]
 fn test_dl() {
     let mut term = TestTerm::new(3, 1, 0);
-    term.print("a\nb\nc");
+    term.print("a\r\nb\r\nc");
     term.cup(0, 1);
     term.delete_lines(1);
     assert_visible_contents(&term, &["a", "c", " "]);
@@ -74,8 +74,303 @@ fn test_dl() {
     term.cup(0, 0);
     term.delete_lines(2);
     assert_visible_contents(&term, &[" ", " ", " "]);
-    term.print("1\n2\n3");
+    term.print("1\r\n2\r\n3");
     term.cup(0, 1);
     term.delete_lines(-2);
     assert_visible_contents(&term, &["1", "2", "3"]);
 }
+
+#[test]
+fn test_origin_mode() {
+    let mut term = TestTerm::new(5, 5, 0);
+    term.set_scroll_region(1, 3);
+    term.set_mode("?6", true);
+    term.assert_cursor_pos(0, 1, Some("DECOM homes the cursor to the top margin"));
+    term.cup(1, 1);
+    term.assert_cursor_pos(1, 2, Some("CUP is relative to the top margin"));
+    term.cup(500, 500);
+    term.assert_cursor_pos(4, 3, Some("CUP cannot leave the scroll region"));
+    term.print("\x1b[10A");
+    term.assert_cursor_pos(4, 1, Some("CUU stops at the top margin"));
+    term.set_mode("?6", false);
+    term.assert_cursor_pos(0, 0, Some("resetting DECOM homes the cursor"));
+    term.cup(4, 4);
+    term.assert_cursor_pos(4, 4, None);
+}
+
+#[test]
+fn test_scrolling_region_homes_cursor() {
+    let mut term = TestTerm::new(12, 5, 0);
+    term.cup(2, 7);
+    term.print("\x1b[5;10r");
+    term.assert_cursor_pos(0, 0, Some("DECSTBM homes the cursor"));
+    term.set_mode("?6", true);
+    term.cup(2, 2);
+    term.print("\x1b[5;10r");
+    term.assert_cursor_pos(0, 4, Some("DECSTBM homes the cursor to the top margin with DECOM"));
+}
+
+#[test]
+fn test_left_and_right_margins() {
+    let mut term = TestTerm::new(3, 6, 0);
+    term.cup(2, 1);
+    term.set_left_and_right_margins(1, 4);
+    term.cup(0, 0);
+    term.print("\x1b[u");
+    term.assert_cursor_pos(2, 1, Some("CSI s saves the cursor without DECLRMM"));
+
+    term.set_mode("?69", true);
+    term.cup(2, 1);
+    term.set_left_and_right_margins(1, 4);
+    term.assert_cursor_pos(0, 0, Some("DECSLRM homes the cursor"));
+    term.set_mode("?6", true);
+    term.assert_cursor_pos(1, 0, Some("DECOM homes the cursor to the left margin"));
+    term.set_left_and_right_margins(2, 4);
+    term.assert_cursor_pos(2, 0, Some("DECSLRM homes the cursor within the margins"));
+    term.set_left_and_right_margins(1, 4);
+    term.print("abcdefg");
+    assert_visible_contents(&term, &[" abcd ", " efg  ", "      "]);
+
+    term.set_mode("?6", false);
+    term.set_mode("?69", false);
+    term.cup(0, 2);
+    term.print("abcdefg");
+    assert_visible_contents(&term, &[" efg  ", "abcdef", "g     "]);
+}
+
+#[test]
+fn test_scroll_within_margins() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.print("abcd\r\nefgh\r\nijkl");
+    term.set_mode("?69", true);
+    term.set_left_and_right_margins(1, 2);
+
+    term.cup(1, 0);
+    term.delete_lines(1);
+    assert_visible_contents(&term, &["afgd", "ejkh", "i  l"]);
+
+    term.cup(0, 0);
+    term.delete_lines(1);
+    assert_visible_contents(&term, &["afgd", "ejkh", "i  l"]);
+
+    term.cup(2, 1);
+    term.print("\x1b[L");
+    assert_visible_contents(&term, &["afgd", "e  h", "ijkl"]);
+
+    term.cup(2, 2);
+    term.print("\n");
+    assert_visible_contents(&term, &["a  d", "ejkh", "i  l"]);
+    term.assert_cursor_pos(2, 2, None);
+}
+
+#[test]
+fn test_ich() {
+    let mut term = TestTerm::new(2, 5, 0);
+    term.print("abcde");
+    term.cup(1, 0);
+    term.print("\x1b[@");
+    assert_visible_contents(&term, &["a bcd", "     "]);
+    term.print("\x1b[2@");
+    assert_visible_contents(&term, &["a   b", "     "]);
+    term.print("\x1b[10@");
+    assert_visible_contents(&term, &["a    ", "     "]);
+    term.assert_cursor_pos(1, 0, Some("ICH does not move the cursor"));
+}
+
+#[test]
+fn test_dch() {
+    let mut term = TestTerm::new(2, 5, 0);
+    term.print("abcde");
+    term.cup(1, 0);
+    term.print("\x1b[P");
+    assert_visible_contents(&term, &["acde ", "     "]);
+    term.print("\x1b[2P");
+    assert_visible_contents(&term, &["ae   ", "     "]);
+    term.print("\x1b[10P");
+    assert_visible_contents(&term, &["a    ", "     "]);
+    term.assert_cursor_pos(1, 0, Some("DCH does not move the cursor"));
+}
+
+#[test]
+fn test_ich_dch_within_margins() {
+    let mut term = TestTerm::new(1, 6, 0);
+    term.print("abcdef");
+    term.set_mode("?69", true);
+    term.set_left_and_right_margins(1, 4);
+    term.cup(2, 0);
+    term.print("\x1b[@");
+    assert_visible_contents(&term, &["ab cdf"]);
+    term.print("\x1b[2P");
+    assert_visible_contents(&term, &["abd  f"]);
+    term.cup(5, 0);
+    term.print("\x1b[P");
+    assert_visible_contents(&term, &["abd  f"]);
+}
+
+#[test]
+fn test_rep() {
+    let mut term = TestTerm::new(2, 5, 0);
+    term.print("\x1b[b");
+    assert_visible_contents(&term, &["     ", "     "]);
+    term.print("a\x1b[3b");
+    assert_visible_contents(&term, &["aaaa ", "     "]);
+    term.print("\x1b(0q\x1b(B\x1b[b");
+    assert_visible_contents(&term, &["aaaa─", "─    "]);
+}
+
+#[test]
+fn test_cha_hpa() {
+    let mut term = TestTerm::new(2, 5, 0);
+    term.cup(2, 1);
+    term.print("\x1b[4G");
+    term.assert_cursor_pos(3, 1, None);
+    term.print("\x1b[G");
+    term.assert_cursor_pos(0, 1, None);
+    term.print("\x1b[500`");
+    term.assert_cursor_pos(4, 1, None);
+    term.print("\x1b[2`");
+    term.assert_cursor_pos(1, 1, None);
+}
+
+#[test]
+fn test_cht_cbt() {
+    let mut term = TestTerm::new(1, 25, 0);
+    term.print("\x1b[I");
+    term.assert_cursor_pos(8, 0, None);
+    term.print("\x1b[2I");
+    term.assert_cursor_pos(24, 0, None);
+    term.print("\x1b[Z");
+    term.assert_cursor_pos(16, 0, None);
+    term.print("\x1b[3Z");
+    term.assert_cursor_pos(0, 0, None);
+    // A huge count must not stall the terminal
+    term.print("\x1b[9999999999999I");
+    term.assert_cursor_pos(24, 0, None);
+    term.print("\x1b[9999999999999Z");
+    term.assert_cursor_pos(0, 0, None);
+}
+
+#[test]
+fn test_tbc() {
+    let mut term = TestTerm::new(1, 25, 0);
+    term.cup(8, 0);
+    term.print("\x1b[g");
+    term.cup(0, 0);
+    term.print("\t");
+    term.assert_cursor_pos(16, 0, Some("tab stop at 8 was cleared"));
+    term.print("\x1b[3g");
+    term.cup(0, 0);
+    term.print("\t");
+    term.assert_cursor_pos(24, 0, Some("all tab stops were cleared"));
+    term.cup(4, 0);
+    term.print("\x1bH");
+    term.cup(0, 0);
+    term.print("\t");
+    term.assert_cursor_pos(4, 0, None);
+}
+
+#[test]
+fn test_sd() {
+    let mut term = TestTerm::new(3, 1, 0);
+    term.print("a\r\nb\r\nc");
+    term.print("\x1b[T");
+    assert_visible_contents(&term, &[" ", "a", "b"]);
+    term.print("\x1b[2T");
+    assert_visible_contents(&term, &[" ", " ", " "]);
+}
+
+#[test]
+fn test_irm() {
+    let mut term = TestTerm::new(1, 6, 0);
+    term.print("abcd");
+    term.cup(1, 0);
+    term.print("\x1b[4hXY");
+    assert_visible_contents(&term, &["aXYbcd"]);
+    term.assert_cursor_pos(3, 0, None);
+    term.print("\u{3000}");
+    assert_visible_contents(&term, &["aXY\u{3000}b"]);
+    term.print("\x1b[4lZ");
+    assert_visible_contents(&term, &["aXY\u{3000}Z"]);
+}
+
+#[test]
+fn test_synchronized_output() {
+    let mut term = TestTerm::new(2, 4, 0);
+    assert!(term.synchronized_output_deadline().is_none());
+    term.print("\x1b[?2026$p");
+    assert_eq!(term.take_written(), "\x1b[?2026;2$y");
+
+    term.set_mode("?2026", true);
+    assert!(term.synchronized_output_deadline().is_some());
+    term.print("\x1b[?2026$p");
+    assert_eq!(term.take_written(), "\x1b[?2026;1$y");
+
+    term.set_mode("?2026", false);
+    assert!(term.synchronized_output_deadline().is_none());
+
+    term.print("\x1b[?12345$p");
+    assert_eq!(term.take_written(), "\x1b[?12345;0$y", "unrecognized mode");
+}
+
+#[test]
+fn test_device_attributes() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.print("\x1b[c");
+    assert_eq!(term.take_written(), "\x1b[?62;4;22;52c");
+    term.print("\x1b[>c");
+    assert_eq!(term.take_written(), "\x1b[>1;100;0c");
+    term.print("\x1b[=c");
+    assert_eq!(term.take_written(), "\x1bP!|00000000\x1b\\");
+    term.print("\x1b[>q");
+    assert_eq!(term.take_written(), "\x1bP>|miro 0.1.0\x1b\\");
+}
+
+#[test]
+fn test_decrqm() {
+    let mut term = TestTerm::new(2, 4, 0);
+    term.print("\x1b[?1$p\x1b[?25$p\x1b[?1000$p\x1b[?1006$p");
+    assert_eq!(term.take_written(), "\x1b[?1;2$y\x1b[?25;1$y\x1b[?1000;2$y\x1b[?1006;2$y");
+
+    term.set_mode("?1", true);
+    term.set_mode("?25", false);
+    term.set_mode("?1000", true);
+    term.set_mode("?1006", true);
+    term.print("\x1b[?1$p\x1b[?25$p\x1b[?1000$p\x1b[?1002$p\x1b[?1006$p");
+    assert_eq!(
+        term.take_written(),
+        "\x1b[?1;1$y\x1b[?25;2$y\x1b[?1000;1$y\x1b[?1002;2$y\x1b[?1006;1$y"
+    );
+
+    term.print("\x1b[?7$p\x1b[?12$p");
+    assert_eq!(term.take_written(), "\x1b[?7;3$y\x1b[?12;4$y", "permanent modes");
+
+    term.print("\x1b[4$p\x1b[4h\x1b[4$p\x1b[20$p\x1b[3$p");
+    assert_eq!(term.take_written(), "\x1b[4;2$y\x1b[4;1$y\x1b[20;2$y\x1b[3;0$y");
+}
+
+#[test]
+fn test_decrqss() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.print("\x1bP$qm\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP1$r0m\x1b\\");
+
+    term.print("\x1b[1;4;31;102m\x1bP$qm\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP1$r0;1;4;31;102m\x1b\\");
+    term.print("\x1b[0;38;5;100;48;2;1;2;3m\x1bP$qm\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP1$r0;38;5;100;48;2;1;2;3m\x1b\\");
+
+    term.print("\x1b[2;4r\x1bP$qr\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP1$r2;4r\x1b\\");
+
+    term.set_mode("?69", true);
+    term.set_left_and_right_margins(3, 6);
+    term.print("\x1bP$qs\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP1$r4;7s\x1b\\");
+
+    term.print("\x1bP$qz\x1b\\");
+    assert_eq!(term.take_written(), "\x1bP0$r\x1b\\", "unknown setting");
+
+    // Only DECRQSS is answered
+    term.print("\x1bP1$tx\x1b\\");
+    assert_eq!(term.take_written(), "");
+}
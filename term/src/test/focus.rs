@@ -0,0 +1,22 @@
+//! Testing focus reporting
+
+use super::*;
+
+#[test]
+fn test_focus_tracking() {
+    let mut term = TestTerm::new(2, 4, 0);
+    assert!(term.has_focus());
+    term.focus_changed(false);
+    assert!(!term.has_focus());
+    assert_eq!(term.take_written(), "", "not reported unless enabled");
+
+    term.set_mode("?1004", true);
+    term.focus_changed(true);
+    term.focus_changed(true);
+    term.focus_changed(false);
+    assert_eq!(term.take_written(), "\x1b[I\x1b[O");
+
+    term.set_mode("?1004", false);
+    term.focus_changed(true);
+    assert_eq!(term.take_written(), "");
+}
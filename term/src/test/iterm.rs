@@ -0,0 +1,93 @@
+//! Testing iTerm2's inline image protocol, OSC 1337
+
+use super::*;
+
+/// A 4x2 PNG whose top row is red and whose bottom row is blue
+const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAQAAAACCAYAAAB/qH1jAAAAE0lEQVR4nGP4z8DwHxkzoPH/AwAHKQ/xs/M1WgAAAABJRU5ErkJggg==";
+
+/// The header of a PNG that claims to be 8000x8000
+const HUGE_PNG: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAH0AAAB9ACAYAAAAG8a30AAAACUlEQVR4nGMAAAABAAFe/335AAAAAElFTkSuQmCC";
+
+/// Returns the size at which an image of size `natural` would be
+/// displayed, given the args of the request, with 2x4 pixel cells on
+/// a 20x16 pixel screen
+fn target_size(args: &str, natural: (usize, usize)) -> (usize, usize) {
+    let osc = format!("File={}:", args);
+    let image = InlineImage::parse(osc.as_bytes(), DEFAULT_MAX_IMAGE_SIZE).unwrap();
+    image.target_size(natural, (2, 4), (20, 16))
+}
+
+#[test]
+fn test_inline_image() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    term.print("a");
+    term.print(format!("\x1b]1337;File=name=dGVzdA==;size=76;inline=1:{}\x07", PNG));
+
+    let slices = term.screen().lines[0].images().to_vec();
+    assert_eq!(slices.len(), 1);
+    let image = Rc::clone(&slices[0].image);
+    assert_eq!((image.width(), image.height()), (4, 2));
+    assert_eq!(image.pixel(3, 0), [255, 0, 0, 255]);
+    assert_eq!(image.pixel(0, 1), [0, 0, 255, 255]);
+    assert_eq!(
+        slices[0],
+        ImageSlice {
+            image: Rc::clone(&image),
+            x: 1,
+            cols: 2,
+            top: 0,
+            height: 2,
+            z_index: 0,
+            placement: None
+        }
+    );
+    term.assert_cursor_pos(1, 1, Some("cursor moves below the image"));
+}
+
+#[test]
+fn test_inline_image_rejected() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+
+    // Files that aren't inline would be downloaded
+    term.print(format!("\x1b]1337;File=:{}\x07", PNG));
+    term.print(format!("\x1b]1337;File=inline=0:{}\x07", PNG));
+    // Not an image
+    term.print("\x1b]1337;File=inline=1:aGVsbG8=\x07");
+    // Too many pixels to decode
+    term.print(format!("\x1b]1337;File=inline=1:{}\x07", HUGE_PNG));
+    // Too large, whether or not the size is declared
+    term.set_max_image_size(64);
+    term.print(format!("\x1b]1337;File=inline=1:{}\x07", PNG));
+    term.set_max_image_size(1024);
+    term.print(format!("\x1b]1337;File=size=2048;inline=1:{}\x07", PNG));
+
+    assert!(term.screen().lines.iter().all(|line| line.images().is_empty()));
+    term.assert_cursor_pos(0, 0, None);
+}
+
+#[test]
+fn test_inline_image_dimensions() {
+    assert_eq!(ImageDimension::parse("auto").unwrap(), ImageDimension::Auto);
+    assert_eq!(ImageDimension::parse("3").unwrap(), ImageDimension::Cells(3));
+    assert_eq!(ImageDimension::parse("12px").unwrap(), ImageDimension::Pixels(12));
+    assert_eq!(ImageDimension::parse("50%").unwrap(), ImageDimension::Percent(50));
+    assert!(ImageDimension::parse("wide").is_err());
+
+    // The natural size, unless that is wider than the screen
+    assert_eq!(target_size("", (4, 2)), (4, 2));
+    assert_eq!(target_size("", (40, 20)), (20, 10));
+
+    // One dimension follows the other, unless asked not to
+    assert_eq!(target_size("width=5", (4, 2)), (10, 5));
+    assert_eq!(target_size("width=50%", (4, 2)), (10, 5));
+    assert_eq!(target_size("height=8px", (4, 2)), (16, 8));
+    assert_eq!(target_size("height=8px;preserveAspectRatio=0", (4, 2)), (4, 8));
+
+    // Fit within the box, or fill it
+    assert_eq!(target_size("width=2;height=2", (4, 2)), (4, 2));
+    assert_eq!(target_size("width=10;height=1", (4, 2)), (8, 4));
+    assert_eq!(target_size("width=2;height=2;preserveAspectRatio=0", (4, 2)), (4, 8));
+}
@@ -0,0 +1,248 @@
+//! Testing the kitty keyboard protocol
+
+use super::*;
+use KeyCode::*;
+
+const NONE: KeyModifiers = KeyModifiers::empty();
+const SHIFT: KeyModifiers = KeyModifiers::SHIFT;
+const CTRL: KeyModifiers = KeyModifiers::CTRL;
+const ALT: KeyModifiers = KeyModifiers::ALT;
+
+/// Press and release a key, returning what was sent for each
+fn press(term: &mut TestTerm, key: KeyCode, mods: KeyModifiers) -> (String, String) {
+    term.key_down(key, mods);
+    let down = term.take_written();
+    term.key_up(key, mods);
+    (down, term.take_written())
+}
+
+/// Returns what is sent when the key is pressed
+fn down(term: &mut TestTerm, key: KeyCode, mods: KeyModifiers) -> String {
+    press(term, key, mods).0
+}
+
+fn query_flags(term: &mut TestTerm) -> String {
+    term.print("\x1b[?u");
+    term.take_written()
+}
+
+#[test]
+fn test_keyboard_flags_stack() {
+    let mut term = TestTerm::new(3, 10, 0);
+    assert_eq!(query_flags(&mut term), "\x1b[?0u");
+    term.print("\x1b[>1u");
+    assert_eq!(query_flags(&mut term), "\x1b[?1u");
+    term.print("\x1b[>3u");
+    assert_eq!(query_flags(&mut term), "\x1b[?3u");
+
+    // Set, add and remove flags at the top of the stack
+    term.print("\x1b[=4;2u");
+    assert_eq!(query_flags(&mut term), "\x1b[?7u");
+    term.print("\x1b[=2;3u");
+    assert_eq!(query_flags(&mut term), "\x1b[?5u");
+    term.print("\x1b[=8u");
+    assert_eq!(query_flags(&mut term), "\x1b[?8u");
+
+    term.print("\x1b[<u");
+    assert_eq!(query_flags(&mut term), "\x1b[?1u");
+    term.print("\x1b[<5u");
+    assert_eq!(query_flags(&mut term), "\x1b[?0u");
+
+    // The alternate screen has a stack of its own
+    term.print("\x1b[>1u");
+    term.set_mode("?1049", true);
+    assert_eq!(query_flags(&mut term), "\x1b[?0u");
+    term.print("\x1b[>8u");
+    assert_eq!(query_flags(&mut term), "\x1b[?8u");
+    term.set_mode("?1049", false);
+    assert_eq!(query_flags(&mut term), "\x1b[?1u");
+
+    // The oldest entries are discarded when the stack is full
+    for _ in 0..20 {
+        term.print("\x1b[>2u");
+    }
+    term.print("\x1b[<16u");
+    assert_eq!(query_flags(&mut term), "\x1b[?0u");
+
+    term.print("\x1b[>1u\x1bc");
+    assert_eq!(query_flags(&mut term), "\x1b[?0u");
+}
+
+#[test]
+fn test_keyboard_legacy() {
+    let mut term = TestTerm::new(3, 10, 0);
+    assert_eq!(press(&mut term, Char('i'), CTRL), ("\t".to_string(), String::new()));
+    assert_eq!(down(&mut term, Char('C'), CTRL | SHIFT), "\x03");
+    assert_eq!(down(&mut term, Char('['), CTRL), "\x1b");
+    assert_eq!(down(&mut term, Char(' '), CTRL), "\x00");
+    assert_eq!(down(&mut term, Char('1'), CTRL), "1");
+    assert_eq!(down(&mut term, Char('a'), ALT), "\x1ba");
+    assert_eq!(down(&mut term, Char('a'), CTRL | ALT), "\x1b\x01");
+    assert_eq!(down(&mut term, Tab, NONE), "\t");
+    assert_eq!(down(&mut term, Tab, SHIFT), "\x1b[Z");
+    assert_eq!(down(&mut term, Escape, NONE), "\x1b");
+    assert_eq!(down(&mut term, Enter, ALT), "\x1b\r");
+    assert_eq!(down(&mut term, Backspace, NONE), "\x08");
+}
+
+#[test]
+fn test_keyboard_xterm_modifiers() {
+    let mut term = TestTerm::new(3, 10, 0);
+    assert_eq!(down(&mut term, Left, NONE), "\x1b[D");
+    assert_eq!(down(&mut term, Left, CTRL), "\x1b[1;5D");
+    assert_eq!(down(&mut term, Right, ALT), "\x1b[1;3C");
+    assert_eq!(down(&mut term, Home, SHIFT), "\x1b[1;2H");
+    assert_eq!(down(&mut term, Delete, NONE), "\x1b[3~");
+    assert_eq!(down(&mut term, Delete, CTRL), "\x1b[3;5~");
+    assert_eq!(down(&mut term, PageUp, CTRL), "\x1b[5;5~");
+
+    assert_eq!(down(&mut term, Function(1), NONE), "\x1bOP");
+    assert_eq!(down(&mut term, Function(4), CTRL), "\x1b[1;5S");
+    assert_eq!(down(&mut term, Function(5), NONE), "\x1b[15~");
+    assert_eq!(down(&mut term, Function(5), SHIFT), "\x1b[15;2~");
+    assert_eq!(down(&mut term, Function(12), NONE), "\x1b[24~");
+    assert_eq!(down(&mut term, Function(13), NONE), "\x1b[1;2P");
+    assert_eq!(down(&mut term, Function(24), NONE), "\x1b[24;2~");
+
+    // Modifiers are still reported in application cursor mode
+    term.set_mode("?1", true);
+    assert_eq!(down(&mut term, Up, NONE), "\x1bOA");
+    assert_eq!(down(&mut term, Up, CTRL), "\x1b[1;5A");
+}
+
+#[test]
+fn test_keyboard_numpad() {
+    let mut term = TestTerm::new(3, 10, 0);
+    assert_eq!(down(&mut term, Numpad(7), NONE), "7");
+    assert_eq!(down(&mut term, NumpadAdd, NONE), "+");
+    assert_eq!(down(&mut term, NumpadEnter, NONE), "\r");
+
+    // DECKPAM
+    term.print("\x1b=");
+    assert_eq!(down(&mut term, Numpad(0), NONE), "\x1bOp");
+    assert_eq!(down(&mut term, Numpad(9), NONE), "\x1bOy");
+    assert_eq!(down(&mut term, NumpadDecimal, NONE), "\x1bOn");
+    assert_eq!(down(&mut term, NumpadEnter, NONE), "\x1bOM");
+    term.print("\x1b>");
+    assert_eq!(down(&mut term, NumpadDivide, NONE), "/");
+}
+
+#[test]
+fn test_modify_other_keys() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("\x1b[?4m");
+    assert_eq!(term.take_written(), "\x1b[>4;0m");
+
+    term.print("\x1b[>4;1m\x1b[?4m");
+    assert_eq!(term.take_written(), "\x1b[>4;1m");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x01");
+    assert_eq!(down(&mut term, Char('a'), ALT), "\x1ba");
+    assert_eq!(down(&mut term, Char('A'), CTRL | SHIFT), "\x1b[27;6;65~");
+    assert_eq!(down(&mut term, Char('1'), CTRL), "\x1b[27;5;49~");
+    assert_eq!(down(&mut term, Enter, CTRL), "\x1b[27;5;13~");
+    assert_eq!(down(&mut term, Tab, NONE), "\t");
+    assert_eq!(down(&mut term, Left, CTRL), "\x1b[1;5D");
+
+    term.print("\x1b[>4;2m");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x1b[27;5;97~");
+    assert_eq!(down(&mut term, Char('a'), ALT), "\x1b[27;3;97~");
+    assert_eq!(down(&mut term, Char('A'), SHIFT), "A");
+    assert_eq!(down(&mut term, Enter, SHIFT), "\x1b[27;2;13~");
+    assert_eq!(down(&mut term, Char('a'), NONE), "a");
+
+    // The kitty protocol takes precedence
+    term.print("\x1b[>1u");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x1b[97;5u");
+    term.print("\x1b[<u");
+
+    term.print("\x1b[>4m");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x01");
+    term.print("\x1b[>4;2m\x1b[>4n");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x01");
+}
+
+#[test]
+fn test_keyboard_disambiguate() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("\x1b[>1u");
+
+    assert_eq!(down(&mut term, Char('i'), CTRL), "\x1b[105;5u");
+    assert_eq!(down(&mut term, Tab, NONE), "\t");
+    assert_eq!(down(&mut term, Escape, NONE), "\x1b[27u");
+    assert_eq!(down(&mut term, Char('a'), NONE), "a");
+    assert_eq!(down(&mut term, Char('A'), SHIFT), "A");
+    assert_eq!(down(&mut term, Char('a'), ALT), "\x1b[97;3u");
+    assert_eq!(down(&mut term, Char('A'), CTRL | SHIFT), "\x1b[97;6u");
+    assert_eq!(down(&mut term, Tab, SHIFT), "\x1b[9;2u");
+    assert_eq!(down(&mut term, Enter, CTRL), "\x1b[13;5u");
+    assert_eq!(down(&mut term, Up, NONE), "\x1b[A");
+    assert_eq!(down(&mut term, Up, CTRL), "\x1b[1;5A");
+    assert_eq!(down(&mut term, PageDown, ALT), "\x1b[6;3~");
+    assert_eq!(down(&mut term, Delete, SHIFT), "\x1b[3;2~");
+    assert_eq!(down(&mut term, Function(1), NONE), "\x1bOP");
+    assert_eq!(down(&mut term, Function(3), CTRL), "\x1b[13;5~");
+    assert_eq!(down(&mut term, Function(5), ALT), "\x1b[15;3~");
+    assert_eq!(down(&mut term, Function(13), NONE), "\x1b[57376u");
+    assert_eq!(down(&mut term, Numpad(1), NONE), "1");
+    assert_eq!(down(&mut term, Numpad(1), CTRL), "\x1b[57400;5u");
+    // Releases aren't reported without REPORT_EVENT_TYPES
+    assert_eq!(press(&mut term, Char('a'), ALT).1, "");
+    // Shift+PageUp still scrolls the viewport
+    assert_eq!(down(&mut term, PageUp, SHIFT), "");
+}
+
+#[test]
+fn test_keyboard_event_types() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("\x1b[>3u");
+
+    term.key_down(Char('a'), NONE);
+    term.key_down(Char('a'), NONE);
+    assert_eq!(term.take_written(), "aa");
+    term.key_up(Char('a'), NONE);
+    assert_eq!(term.take_written(), "\x1b[97;1:3u");
+
+    term.key_down(Up, NONE);
+    term.key_down(Up, NONE);
+    term.key_up(Up, NONE);
+    assert_eq!(term.take_written(), "\x1b[A\x1b[1;1:2A\x1b[1;1:3A");
+
+    term.key_down(Char('x'), CTRL);
+    term.key_down(Char('x'), CTRL);
+    term.key_up(Char('x'), CTRL);
+    assert_eq!(term.take_written(), "\x1b[120;5u\x1b[120;5:2u\x1b[120;5:3u");
+
+    // Enter, Tab and Backspace releases are left out
+    assert_eq!(press(&mut term, Enter, NONE), ("\r".to_string(), String::new()));
+
+    // RIS forgets the held key, so pressing it again isn't a repeat
+    term.key_down(Up, NONE);
+    term.print("\x1bc\x1b[>3u");
+    term.key_down(Up, NONE);
+    assert_eq!(term.take_written(), "\x1b[A\x1b[A");
+}
+
+#[test]
+fn test_keyboard_all_keys() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("\x1b[>8u");
+    assert_eq!(down(&mut term, Char('a'), NONE), "\x1b[97u");
+    assert_eq!(down(&mut term, Char('A'), SHIFT), "\x1b[97;2u");
+    assert_eq!(down(&mut term, Enter, NONE), "\x1b[13u");
+    assert_eq!(down(&mut term, Backspace, NONE), "\x1b[127u");
+    assert_eq!(down(&mut term, Shift, NONE), "\x1b[57441u");
+    assert_eq!(down(&mut term, Up, NONE), "\x1b[A");
+    assert_eq!(down(&mut term, NumpadEnter, NONE), "\x1b[57414u");
+
+    // Alternate keys
+    term.print("\x1b[=4;2u");
+    assert_eq!(down(&mut term, Char('A'), SHIFT), "\x1b[97:65;2u");
+    assert_eq!(down(&mut term, Char('!'), SHIFT), "\x1b[49:33;2u");
+
+    // Associated text
+    term.print("\x1b[=16;2u");
+    assert_eq!(down(&mut term, Char('a'), NONE), "\x1b[97;;97u");
+    assert_eq!(down(&mut term, Char('A'), SHIFT), "\x1b[97:65;2;65u");
+    assert_eq!(down(&mut term, Char('a'), CTRL), "\x1b[97;5u");
+    assert_eq!(down(&mut term, NumpadAdd, NONE), "\x1b[57413;;43u");
+}
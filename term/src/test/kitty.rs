@@ -0,0 +1,322 @@
+//! Testing the kitty graphics protocol
+
+use super::*;
+use std::env;
+use std::ffi;
+use std::fs;
+use std::process;
+
+/// A 4x2 PNG whose top row is red and whose bottom row is blue
+const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAQAAAACCAYAAAB/qH1jAAAAE0lEQVR4nGP4z8DwHxkzoPH/AwAHKQ/xs/M1WgAAAABJRU5ErkJggg==";
+
+/// The header of a PNG that claims to be 8000x8000
+const HUGE_PNG: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAH0AAAB9ACAYAAAAG8a30AAAACUlEQVR4nGMAAAABAAFe/335AAAAAElFTkSuQmCC";
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+/// Send a graphics command, base64 encoding its payload
+fn graphics(term: &mut TestTerm, control: &str, payload: &[u8]) {
+    term.print(format!("\x1b_G{};{}\x1b\\", control, base64::encode(payload)));
+}
+
+/// Returns an image of the given size in RGBA pixels, whose left
+/// half is red and whose right half is blue
+fn red_and_blue(width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::new();
+    for _ in 0..height {
+        for x in 0..width {
+            pixels.extend_from_slice(if x < width / 2 { &RED } else { &BLUE });
+        }
+    }
+    pixels
+}
+
+/// Returns the ids of the placements on each line of the screen,
+/// along with the column of each
+fn placements(term: &TestTerm) -> Vec<Vec<(u32, u32, usize)>> {
+    term.screen()
+        .lines
+        .iter()
+        .map(|line| {
+            line.images()
+                .iter()
+                .filter_map(|slice| slice.placement.map(|p| (p.image_id, p.placement_id, slice.x)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the image displayed on a line
+fn image_on_line(term: &TestTerm, line: usize) -> Rc<ImageData> {
+    Rc::clone(&term.screen().lines[line].images()[0].image)
+}
+
+#[test]
+fn test_kitty_transmit_and_display() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    graphics(&mut term, "a=T,f=32,s=2,v=2,i=1", &red_and_blue(2, 2));
+    assert_eq!(term.take_written(), "\x1b_Gi=1;OK\x1b\\");
+
+    let slices = term.screen().lines[0].images().to_vec();
+    assert_eq!(slices.len(), 1);
+    assert_eq!((slices[0].image.width(), slices[0].image.height()), (2, 2));
+    assert_eq!(slices[0].image.pixel(1, 1), BLUE);
+    assert_eq!((slices[0].x, slices[0].cols, slices[0].z_index), (0, 1, 0));
+    term.assert_cursor_pos(1, 0, Some("cursor moves beyond the image"));
+
+    // Without an id there is no response
+    graphics(&mut term, "a=T,f=24,s=1,v=1,z=-5", &[0, 0, 0]);
+    assert_eq!(term.take_written(), "");
+    assert_eq!(placements(&term), vec![vec![(1, 0, 0), (0, 0, 1)], vec![], vec![], vec![]]);
+    assert_eq!(term.screen().lines[0].images()[1].z_index, -5);
+}
+
+#[test]
+fn test_kitty_chunks_and_placements() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    term.print(format!("\x1b_Ga=t,f=100,i=7,m=1;{}\x1b\\", &PNG[..64]));
+    assert_eq!(term.take_written(), "");
+    term.print(format!("\x1b_Gm=0;{}\x1b\\", &PNG[64..]));
+    assert_eq!(term.take_written(), "\x1b_Gi=7;OK\x1b\\");
+    // Transmitting doesn't display the image
+    assert_eq!(placements(&term), vec![vec![], vec![], vec![], vec![]]);
+
+    term.cup(1, 1);
+    term.print("\x1b_Ga=p,i=7,p=3\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=7,p=3;OK\x1b\\");
+    assert_eq!(image_on_line(&term, 1).pixel(0, 1), BLUE);
+    term.assert_cursor_pos(3, 1, None);
+
+    // Putting the same placement again moves it; another is added
+    term.cup(5, 2);
+    term.print("\x1b_Ga=p,i=7,p=3,q=1\x1b\\");
+    term.print("\x1b_Ga=p,i=7,q=1\x1b\\");
+    assert_eq!(term.take_written(), "");
+    assert_eq!(placements(&term), vec![vec![], vec![], vec![(7, 3, 5), (7, 0, 7)], vec![]]);
+
+    term.print("\x1b_Ga=p,i=8\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=8;ENOENT:the image was not found\x1b\\");
+    term.print("\x1b_Ga=p,i=8,q=2\x1b\\");
+    assert_eq!(term.take_written(), "");
+}
+
+#[test]
+fn test_kitty_full_reset() {
+    let mut term = TestTerm::new(4, 10, 0);
+    graphics(&mut term, "a=t,f=24,s=1,v=1,i=7", &[255, 0, 0]);
+    assert_eq!(term.take_written(), "\x1b_Gi=7;OK\x1b\\");
+    // Start a chunked upload that is never finished
+    graphics(&mut term, "a=t,f=24,s=1,v=1,i=8,m=1", &[0, 0]);
+
+    term.print("\x1bc");
+    term.print("\x1b_Ga=p,i=7\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=7;ENOENT:the image was not found\x1b\\");
+    // This isn't taken to be the rest of the abandoned upload
+    graphics(&mut term, "a=T,f=24,s=1,v=1,i=9", &[0, 0, 255]);
+    assert_eq!(term.take_written(), "\x1b_Gi=9;OK\x1b\\");
+    assert_eq!(image_on_line(&term, 0).pixel(0, 0), BLUE);
+}
+
+#[test]
+fn test_kitty_image_numbers() {
+    let mut term = TestTerm::new(4, 10, 0);
+    graphics(&mut term, "f=24,s=1,v=1,I=5", &[255, 0, 0]);
+    assert_eq!(term.take_written(), "\x1b_Gi=1,I=5;OK\x1b\\");
+    graphics(&mut term, "f=24,s=1,v=1,I=5", &[0, 0, 255]);
+    assert_eq!(term.take_written(), "\x1b_Gi=2,I=5;OK\x1b\\");
+
+    // The newest image with the number is displayed
+    term.print("\x1b_Ga=p,I=5\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=2,I=5;OK\x1b\\");
+    assert_eq!(image_on_line(&term, 0).pixel(0, 0), BLUE);
+}
+
+#[test]
+fn test_kitty_display_options() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    let pixels = red_and_blue(4, 2);
+
+    // Part of the image
+    graphics(&mut term, "a=T,f=32,s=4,v=2,x=2,w=2,C=1", &pixels);
+    let image = image_on_line(&term, 0);
+    assert_eq!((image.width(), image.height()), (2, 2));
+    assert_eq!(image.pixel(0, 0), BLUE);
+
+    // Scaled to fit a number of cells
+    term.cup(0, 1);
+    graphics(&mut term, "a=T,f=32,s=4,v=2,c=4,r=1,C=1", &pixels);
+    let image = image_on_line(&term, 1);
+    assert_eq!((image.width(), image.height()), (8, 4));
+    assert_eq!(term.screen().lines[1].images()[0].cols, 4);
+
+    term.cup(0, 2);
+    graphics(&mut term, "a=T,f=32,s=4,v=2,c=4,C=1", &pixels);
+    let image = image_on_line(&term, 2);
+    assert_eq!((image.width(), image.height()), (8, 4));
+
+    // Offset within the first cell
+    term.cup(0, 3);
+    graphics(&mut term, "a=T,f=32,s=4,v=2,X=1,Y=2,C=1", &pixels);
+    let image = image_on_line(&term, 3);
+    assert_eq!((image.width(), image.height()), (5, 4));
+    assert_eq!(image.pixel(0, 0), [0, 0, 0, 0]);
+    assert_eq!(image.pixel(1, 2), RED);
+    term.assert_cursor_pos(0, 3, Some("C=1 leaves the cursor alone"));
+}
+
+#[test]
+fn test_kitty_cursor_movement() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    let pixels = red_and_blue(2, 12);
+
+    // Images that don't move the cursor are cut off at the bottom
+    term.cup(0, 3);
+    graphics(&mut term, "a=T,f=32,s=2,v=12,C=1", &pixels);
+    assert_eq!(placements(&term), vec![vec![], vec![], vec![], vec![(0, 0, 0)]]);
+    term.assert_cursor_pos(0, 3, None);
+
+    // Otherwise the screen scrolls to make room
+    term.print("\x1b_Ga=d\x1b\\");
+    graphics(&mut term, "a=T,f=32,s=2,v=12", &pixels);
+    let tops: Vec<Vec<usize>> = term
+        .screen()
+        .lines
+        .iter()
+        .map(|line| line.images().iter().map(|slice| slice.top).collect())
+        .collect();
+    assert_eq!(tops, vec![vec![], vec![0], vec![4], vec![8]]);
+    term.assert_cursor_pos(1, 3, None);
+}
+
+#[test]
+fn test_kitty_delete() {
+    let mut term = TestTerm::new(4, 10, 0);
+    term.set_cell_size(2, 4);
+    graphics(&mut term, "f=32,s=2,v=4,i=1,q=1", &red_and_blue(2, 4));
+    for &(col, z) in &[(0, -1), (2, 0), (4, 1), (6, 1)] {
+        term.cup(col, 0);
+        term.print(format!("\x1b_Ga=p,i=1,z={},q=1\x1b\\", z));
+    }
+    term.cup(0, 2);
+    graphics(&mut term, "a=T,f=24,s=1,v=1,i=2,z=3,q=1", &[0, 0, 0]);
+    let columns = |term: &TestTerm| -> Vec<Vec<usize>> {
+        placements(term).iter().map(|line| line.iter().map(|p| p.2).collect()).collect()
+    };
+    assert_eq!(columns(&term), vec![vec![0, 2, 4, 6], vec![], vec![0], vec![]]);
+
+    // By z-index, by cell, by column and at the cursor
+    term.print("\x1b_Ga=d,d=z,z=0\x1b\\");
+    assert_eq!(columns(&term), vec![vec![0, 4, 6], vec![], vec![0], vec![]]);
+    term.print("\x1b_Ga=d,d=p,x=1,y=1\x1b\\");
+    assert_eq!(columns(&term), vec![vec![4, 6], vec![], vec![0], vec![]]);
+    term.print("\x1b_Ga=d,d=x,x=5\x1b\\");
+    assert_eq!(columns(&term), vec![vec![6], vec![], vec![0], vec![]]);
+    term.cup(0, 2);
+    term.print("\x1b_Ga=d,d=c\x1b\\");
+    assert_eq!(columns(&term), vec![vec![6], vec![], vec![], vec![]]);
+    assert_eq!(term.take_written(), "");
+
+    // Lowercase deletions keep the image, uppercase ones free it
+    term.print("\x1b_Ga=d,d=i,i=1\x1b\\");
+    assert_eq!(columns(&term), vec![vec![], vec![], vec![], vec![]]);
+    term.print("\x1b_Ga=p,i=1\x1b\\");
+    term.print("\x1b_Ga=d,d=I,i=1\x1b\\");
+    assert_eq!(columns(&term), vec![vec![], vec![], vec![], vec![]]);
+    term.print("\x1b_Ga=p,i=1\x1b\\");
+    assert_eq!(
+        term.take_written(),
+        "\x1b_Gi=1;OK\x1b\\\x1b_Gi=1;ENOENT:the image was not found\x1b\\"
+    );
+
+    // Everything on the screen
+    term.print("\x1b_Ga=p,i=2,q=1\x1b\\");
+    term.print("\x1b_Ga=d\x1b\\");
+    assert_eq!(columns(&term), vec![vec![], vec![], vec![], vec![]]);
+}
+
+#[test]
+fn test_kitty_files() {
+    let mut term = TestTerm::new(4, 10, 0);
+    let dir = env::temp_dir();
+    let temp = dir.join(format!("miro-tty-graphics-protocol-{}", process::id()));
+    let plain = dir.join(format!("miro-kitty-test-{}", process::id()));
+    fs::write(&temp, [255, 0, 0]).unwrap();
+    fs::write(&plain, [255, 0, 0]).unwrap();
+    let name = |path: &::std::path::Path| path.to_str().unwrap().as_bytes().to_vec();
+
+    // Temporary files are deleted once they have been read
+    graphics(&mut term, "a=T,t=t,f=24,s=1,v=1,i=3", &name(&temp));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;OK\x1b\\");
+    assert!(!temp.exists());
+    assert_eq!(image_on_line(&term, 0).pixel(0, 0), RED);
+
+    // Other files are not
+    graphics(&mut term, "a=t,t=f,f=24,s=1,v=1,i=3", &name(&plain));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;OK\x1b\\");
+    graphics(&mut term, "a=t,t=t,f=24,s=1,v=1,i=3", &name(&plain));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+    assert!(plain.exists());
+
+    // Errors don't reveal anything about the file
+    graphics(&mut term, "a=t,t=f,f=24,s=2,v=1,i=3", &name(&plain));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+    fs::remove_file(&plain).unwrap();
+    graphics(&mut term, "a=t,t=f,f=24,s=1,v=1,i=3", &name(&plain));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+
+    // Nor are the kernel's files read
+    graphics(&mut term, "a=t,t=f,f=24,s=1,v=1,i=3", b"/dev/zero");
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+    graphics(&mut term, "a=t,t=f,f=24,s=1,v=1,i=3", b"/proc/self/../self/environ");
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+
+    // Opening a FIFO would block until something writes to it
+    let fifo = dir.join(format!("miro-kitty-fifo-{}", process::id()));
+    let fifo_name = ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo_name.as_ptr(), 0o600) }, 0);
+    graphics(&mut term, "a=t,t=f,f=24,s=1,v=1,i=3", &name(&fifo));
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EBADF\x1b\\");
+    fs::remove_file(&fifo).unwrap();
+    // Queries don't read files at all, so that they can't be used to
+    // find out whether a file exists
+    graphics(&mut term, "a=q,t=f,f=24,s=1,v=1,i=3", b"/etc/passwd");
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EINVAL:queries can't read files\x1b\\");
+    graphics(&mut term, "a=q,t=t,f=24,s=1,v=1,i=3", b"/nonexistent");
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EINVAL:queries can't read files\x1b\\");
+    graphics(&mut term, "a=t,t=s,f=24,s=1,v=1,i=3", b"/shm");
+    assert_eq!(term.take_written(), "\x1b_Gi=3;EINVAL:shared memory is not supported\x1b\\");
+}
+
+#[test]
+fn test_kitty_data_errors() {
+    let mut term = TestTerm::new(4, 10, 0);
+    // zlib compressed RGB
+    term.print("\x1b_Ga=T,f=24,s=2,v=1,o=z,i=4;eJz7z8DA8J8BAAf+Af8=\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=4;OK\x1b\\");
+    assert_eq!(image_on_line(&term, 0).pixel(1, 0), [0, 255, 0, 255]);
+
+    // Queries check the data without storing the image
+    graphics(&mut term, "a=q,f=24,s=2,v=2,i=5", &[0, 0, 0]);
+    assert_eq!(
+        term.take_written(),
+        "\x1b_Gi=5;ENODATA:expected 12 bytes of image data but got 3\x1b\\"
+    );
+    graphics(&mut term, "a=q,f=24,s=1,v=1,i=5", &[0, 0, 0]);
+    assert_eq!(term.take_written(), "\x1b_Gi=5;OK\x1b\\");
+    term.print("\x1b_Ga=p,i=5,q=1\x1b\\");
+    assert_eq!(term.take_written(), "\x1b_Gi=5;ENOENT:the image was not found\x1b\\");
+
+    // The size of a PNG is checked before it is decoded
+    term.print(format!("\x1b_Ga=T,f=100,i=6;{}\x1b\\", HUGE_PNG));
+    assert_eq!(term.take_written(), "\x1b_Gi=6;EINVAL:the image is too large\x1b\\");
+
+    term.set_max_image_size(8);
+    graphics(&mut term, "a=T,f=24,s=2,v=2,i=6", &[0; 12]);
+    assert_eq!(term.take_written(), "\x1b_Gi=6;EFBIG:the image exceeds the limit of 8 bytes\x1b\\");
+}
@@ -4,13 +4,28 @@
 use super::*;
 mod c0;
 mod c1;
+mod charset;
+mod clipboard;
 mod csi;
+mod focus;
+mod iterm;
+mod keyboard;
+mod kitty;
+mod mouse;
+mod palette;
+mod prompt;
+mod reflow;
+mod search;
 mod selection;
+mod sixel;
 
 #[derive(Default, Debug)]
 struct TestHost {
     title: String,
+    cwd: Option<String>,
     clip: Option<String>,
+    /// Data that the terminal sent to the application
+    written: Vec<u8>,
 }
 
 impl TestHost {
@@ -23,6 +38,10 @@ impl TerminalHost for TestHost {
         self.title = title.into();
     }
 
+    fn set_current_dir(&mut self, cwd: &str) {
+        self.cwd = Some(cwd.into());
+    }
+
     fn set_clipboard(&mut self, clip: Option<String>) -> Result<(), Error> {
         self.clip = clip;
         Ok(())
@@ -33,7 +52,7 @@ impl TerminalHost for TestHost {
     }
 
     fn writer(&mut self) -> &mut std::io::Write {
-        panic!("no writer support in TestHost");
+        &mut self.written
     }
 
     fn click_link(&mut self, _link: &Rc<Hyperlink>) {}
@@ -66,6 +85,11 @@ impl TestTerm {
         self.print(format!("{};{}r", top + 1, bottom + 1));
     }
 
+    fn set_left_and_right_margins(&mut self, left: usize, right: usize) {
+        self.print(CSI);
+        self.print(format!("{};{}s", left + 1, right + 1));
+    }
+
     fn delete_lines(&mut self, n: isize) {
         self.print(CSI);
         self.print(format!("{}M", n));
@@ -119,6 +143,29 @@ impl TestTerm {
         self.term.mouse_event(event, &mut self.host)
     }
 
+    fn key_down(&mut self, key: KeyCode, mods: KeyModifiers) {
+        self.term.key_down(key, mods, &mut self.host).unwrap();
+    }
+
+    fn key_up(&mut self, key: KeyCode, mods: KeyModifiers) {
+        self.term.key_up(key, mods, &mut self.host).unwrap();
+    }
+
+    fn focus_changed(&mut self, focused: bool) {
+        self.term.focus_changed(focused, &mut self.host).unwrap();
+    }
+
+    fn answer_clipboard_read(&mut self, allow: bool) {
+        self.term.answer_clipboard_read(allow, &mut self.host).unwrap();
+    }
+
+    /// Returns the data sent to the application since the last call
+    fn take_written(&mut self) -> String {
+        let written = String::from_utf8(self.host.written.clone()).unwrap();
+        self.host.written.clear();
+        written
+    }
+
     fn get_clipboard(&self) -> Option<&String> {
         self.host.clip.as_ref()
     }
@@ -367,12 +414,13 @@ fn test_delete_lines() {
 
     term.cup(0, 3);
     term.print("aaa\r\nbbb");
-    term.cup(0, 1);
-    term.clean_dirty_lines();
     assert_visible_contents(&term, &["111", "444", "555", "aaa", "bbb"]);
 
-    // test with a scroll region smaller than the screen
+    // test with a scroll region smaller than the screen.
+    // DECSTBM homes the cursor, so position it afterwards.
     term.set_scroll_region(1, 3);
+    term.cup(0, 1);
+    term.clean_dirty_lines();
     print_all_lines(&term);
     term.delete_lines(2);
 
@@ -381,6 +429,7 @@ fn test_delete_lines() {
 
     // expand the scroll region to fill the screen
     term.set_scroll_region(0, 4);
+    term.cup(0, 1);
     term.clean_dirty_lines();
     term.delete_lines(1);
 
@@ -391,24 +440,40 @@ fn test_delete_lines() {
 #[test]
 fn test_scrollup() {
     let mut term = TestTerm::new(2, 1, 4);
-    term.print("1\n");
+    term.print("1\r\n");
     assert_all_contents(&term, &["1", " "]);
-    term.print("2\n");
+    term.print("2\r\n");
     assert_all_contents(&term, &["1", "2", " "]);
-    term.print("3\n");
+    term.print("3\r\n");
     assert_all_contents(&term, &["1", "2", "3", " "]);
-    term.print("4\n");
+    term.print("4\r\n");
     assert_all_contents(&term, &["1", "2", "3", "4", " "]);
-    term.print("5\n");
+    term.print("5\r\n");
     assert_all_contents(&term, &["1", "2", "3", "4", "5", " "]);
-    term.print("6\n");
+    term.print("6\r\n");
     assert_all_contents(&term, &["2", "3", "4", "5", "6", " "]);
-    term.print("7\n");
+    term.print("7\r\n");
     assert_all_contents(&term, &["3", "4", "5", "6", "7", " "]);
-    term.print("8\n");
+    term.print("8\r\n");
     assert_all_contents(&term, &["4", "5", "6", "7", "8", " "]);
 }
 
+#[test]
+fn test_set_scrollback_size() {
+    let mut term = TestTerm::new(2, 1, 4);
+    term.print("1\r\n2\r\n3\r\n4\r\n5\r\n");
+    assert_all_contents(&term, &["1", "2", "3", "4", "5", " "]);
+
+    term.set_scrollback_size(2);
+    assert_all_contents(&term, &["3", "4", "5", " "]);
+    term.print("6\r\n");
+    assert_all_contents(&term, &["4", "5", "6", " "]);
+
+    term.set_scrollback_size(3);
+    term.print("7\r\n8\r\n");
+    assert_all_contents(&term, &["5", "6", "7", "8", " "]);
+}
+
 #[test]
 fn test_hyperlinks() {
     let mut term = TestTerm::new(3, 5, 0);
@@ -457,3 +522,78 @@ fn test_hyperlinks() {
         Compare::TEXT | Compare::ATTRS,
     );
 }
+
+#[test]
+fn test_current_dir() {
+    let mut term = TestTerm::new(2, 4, 0);
+    assert_eq!(term.current_dir(), None);
+
+    term.print("\x1b]7;file://localhost/home/user/my%20project\x1b\\");
+    assert_eq!(term.current_dir(), Some("/home/user/my project"));
+    assert_eq!(term.host.cwd, Some("/home/user/my project".to_owned()));
+
+    term.print("\x1b]7;file:///tmp/a;b\x07");
+    assert_eq!(term.current_dir(), Some("/tmp/a;b"));
+
+    let hostname = local_hostname().unwrap();
+    term.print(format!("\x1b]7;file://{}/var\x07", hostname));
+    assert_eq!(term.current_dir(), Some("/var"));
+
+    // Invalid URLs and directories on other hosts are ignored
+    term.print("\x1b]7;http://localhost/home\x07");
+    term.print("\x1b]7;file://localhost/bad%2\x07");
+    term.print(format!("\x1b]7;file://not-{}/home\x07", hostname));
+    assert_eq!(term.current_dir(), Some("/var"));
+    assert_eq!(term.host.cwd, Some("/var".to_owned()));
+}
+
+#[test]
+fn test_soft_reset() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.print("\x1b(0\x1b[1m");
+    term.set_scroll_region(1, 1);
+    term.set_mode("?6", true);
+    term.soft_reset();
+
+    term.assert_cursor_pos(0, 1, Some("DECSTR does not move the cursor"));
+    term.cup(0, 2);
+    term.assert_cursor_pos(0, 2, Some("DECSTR resets DECOM"));
+    // The whole screen scrolls now that the region was reset
+    term.print("q\r\n\r\n");
+    assert_lines_equal(
+        &term.screen().visible_lines(),
+        &["q   ".into(), "    ".into(), "    ".into()],
+        Compare::TEXT | Compare::ATTRS,
+    );
+}
+
+#[test]
+fn test_full_reset() {
+    let mut term = TestTerm::new(2, 4, 2);
+    term.print("\x1b]0;hello\x07");
+    assert_eq!(term.host.title, "hello");
+    term.print("a\r\nb\r\nc\x1b(0");
+    term.set_mode("?1049", true);
+    term.print("\x1b[1mx\x1b[4;5H");
+    term.print("\x1b]7;file:///tmp\x07");
+    term.set_clipboard_access(ClipboardAccess::Ask);
+    term.print("\x1b]52;c;?\x07");
+    term.search(&Pattern::Literal("a".to_owned()));
+
+    term.print("\x1bc");
+    assert_eq!(term.current_dir(), None);
+    assert!(!term.has_pending_clipboard_read());
+    assert_eq!(term.search_matches(), &[]);
+    assert_eq!(term.host.title, "", "RIS restores the default title");
+    assert_all_contents(&term, &["    ", "    "]);
+    term.assert_cursor_pos(0, 0, None);
+
+    term.print("q\r\na\r\nb\r\nc");
+    assert_lines_equal(
+        &term.screen().lines.iter().cloned().collect::<Vec<_>>(),
+        &["q   ".into(), "a   ".into(), "b   ".into(), "c   ".into()],
+        Compare::TEXT | Compare::ATTRS,
+    );
+    term.refresh_search();
+    assert_eq!(term.search_matches(), &[], "the search was stopped");
+}
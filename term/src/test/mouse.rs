@@ -0,0 +1,120 @@
+//! Testing mouse reporting
+
+use super::*;
+
+fn event(kind: MouseEventKind, button: MouseButton, x: usize, y: i64) -> MouseEvent {
+    MouseEvent { kind, x, y, button, modifiers: KeyModifiers::default() }
+}
+
+fn press(term: &mut TestTerm, button: MouseButton, x: usize, y: i64) {
+    term.mouse(event(MouseEventKind::Press, button, x, y)).unwrap();
+}
+
+fn release(term: &mut TestTerm, button: MouseButton, x: usize, y: i64) {
+    term.mouse(event(MouseEventKind::Release, button, x, y)).unwrap();
+}
+
+fn motion(term: &mut TestTerm, x: usize, y: i64) {
+    term.mouse(event(MouseEventKind::Move, MouseButton::None, x, y)).unwrap();
+}
+
+#[test]
+fn test_mouse_tracking_off() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.set_mode("?1006", true);
+    press(&mut term, MouseButton::Left, 1, 1);
+    release(&mut term, MouseButton::Left, 1, 1);
+    assert_eq!(term.take_written(), "", "an encoding alone doesn't enable reports");
+}
+
+#[test]
+fn test_normal_mouse() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.set_mode("?1000", true);
+    press(&mut term, MouseButton::Right, 1, 2);
+    motion(&mut term, 3, 2);
+    release(&mut term, MouseButton::Right, 3, 2);
+    assert_eq!(term.take_written(), "\x1b[M\"\"#\x1b[M#$#");
+
+    press(&mut term, MouseButton::WheelUp, 0, 0);
+    release(&mut term, MouseButton::WheelUp, 0, 0);
+    assert_eq!(term.take_written(), "\x1b[M`!!");
+
+    term.set_mode("?1000", false);
+    press(&mut term, MouseButton::Right, 1, 2);
+    assert_eq!(term.take_written(), "");
+}
+
+#[test]
+fn test_x10_mouse() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.set_mode("?9", true);
+    term.mouse(MouseEvent {
+        kind: MouseEventKind::Press,
+        x: 0,
+        y: 0,
+        button: MouseButton::Left,
+        modifiers: KeyModifiers::CTRL,
+    })
+    .unwrap();
+    release(&mut term, MouseButton::Left, 0, 0);
+    assert_eq!(term.take_written(), "\x1b[M !!", "X10 reports presses without modifiers");
+}
+
+#[test]
+fn test_button_and_any_event_mouse() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.set_mode("?1006", true);
+    term.set_mode("?1002", true);
+    motion(&mut term, 1, 1);
+    press(&mut term, MouseButton::Left, 1, 1);
+    motion(&mut term, 1, 1);
+    motion(&mut term, 2, 1);
+    release(&mut term, MouseButton::Left, 2, 1);
+    assert_eq!(term.take_written(), "\x1b[<0;2;2M\x1b[<32;3;2M\x1b[<0;3;2m");
+
+    term.set_mode("?1003", true);
+    motion(&mut term, 4, 4);
+    assert_eq!(term.take_written(), "\x1b[<35;5;5M");
+}
+
+#[test]
+fn test_mouse_modifiers() {
+    let mut term = TestTerm::new(5, 10, 0);
+    term.set_mode("?1000", true);
+    term.set_mode("?1006", true);
+    term.mouse(MouseEvent {
+        kind: MouseEventKind::Press,
+        x: 0,
+        y: 0,
+        button: MouseButton::Middle,
+        modifiers: KeyModifiers::CTRL | KeyModifiers::ALT,
+    })
+    .unwrap();
+    assert_eq!(term.take_written(), "\x1b[<25;1;1M");
+}
+
+#[test]
+fn test_mouse_encodings() {
+    let mut term = TestTerm::new(5, 300, 0);
+    term.set_mode("?1000", true);
+
+    press(&mut term, MouseButton::Left, 250, 0);
+    assert_eq!(term.take_written(), "", "X10 cannot encode columns beyond 223");
+
+    term.set_mode("?1005", true);
+    press(&mut term, MouseButton::Left, 250, 0);
+    assert_eq!(term.take_written(), "\x1b[M \u{11b}!");
+
+    term.set_mode("?1015", true);
+    press(&mut term, MouseButton::Left, 250, 0);
+    release(&mut term, MouseButton::Left, 250, 0);
+    assert_eq!(term.take_written(), "\x1b[32;251;1M\x1b[35;251;1M");
+
+    term.set_mode("?1005", false);
+    press(&mut term, MouseButton::Left, 1, 0);
+    assert_eq!(term.take_written(), "\x1b[32;2;1M", "resetting another encoding has no effect");
+    term.set_mode("?1015", false);
+    press(&mut term, MouseButton::Left, 1, 0);
+    assert_eq!(term.take_written(), "\x1b[M \"!");
+}
@@ -0,0 +1,81 @@
+//! Testing the OSC sequences that change the color palette
+
+use super::*;
+use color::{ColorPalette, RgbColor};
+
+#[test]
+fn test_x11_color_spec() {
+    let red = Some(RgbColor::new(0xff, 0, 0));
+    assert_eq!(RgbColor::from_x11_spec("rgb:ff/00/00"), red);
+    assert_eq!(RgbColor::from_x11_spec("rgb:f/0/0"), red);
+    assert_eq!(RgbColor::from_x11_spec("rgb:ffff/0000/0000"), red);
+    assert_eq!(RgbColor::from_x11_spec("#f00"), red);
+    assert_eq!(RgbColor::from_x11_spec("#ff0000"), red);
+    assert_eq!(RgbColor::from_x11_spec("#ffff00000000"), red);
+    assert_eq!(RgbColor::from_x11_spec("Red"), red);
+    assert_eq!(RgbColor::from_x11_spec("rgb:80/40/20"), Some(RgbColor::new(0x80, 0x40, 0x20)));
+
+    assert_eq!(RgbColor::from_x11_spec("rgb:ff/00"), None);
+    assert_eq!(RgbColor::from_x11_spec("rgb:fffff/0/0"), None);
+    assert_eq!(RgbColor::from_x11_spec("#ff00"), None);
+    assert_eq!(RgbColor::from_x11_spec("#gg0000"), None);
+
+    assert_eq!(RgbColor::new(0x12, 0xab, 0xff).to_x11_spec(), "rgb:1212/abab/ffff");
+}
+
+#[test]
+fn test_palette_colors() {
+    let mut term = TestTerm::new(2, 4, 0);
+    let default = term.palette().colors[1];
+
+    term.print("\x1b]4;1;rgb:12/34/56;2;#abcdef\x07");
+    assert_eq!(term.palette().colors[1], RgbColor::new(0x12, 0x34, 0x56));
+    assert_eq!(term.palette().colors[2], RgbColor::new(0xab, 0xcd, 0xef));
+
+    term.print("\x1b]4;1;?\x1b\\");
+    assert_eq!(term.take_written(), "\x1b]4;1;rgb:1212/3434/5656\x1b\\");
+
+    term.print("\x1b]104;1\x07");
+    assert_eq!(term.palette().colors[1], default);
+    assert_eq!(term.palette().colors[2], RgbColor::new(0xab, 0xcd, 0xef));
+
+    term.print("\x1b]104\x07");
+    assert_eq!(term.palette().colors[2], ColorPalette::default().colors[2]);
+}
+
+#[test]
+fn test_dynamic_colors() {
+    let mut term = TestTerm::new(2, 4, 0);
+    let default = ColorPalette::default();
+
+    term.print("\x1b]10;#ffffff;#000080\x07");
+    assert_eq!(term.palette().foreground, RgbColor::new(0xff, 0xff, 0xff));
+    assert_eq!(term.palette().background, RgbColor::new(0, 0, 0x80));
+
+    term.print("\x1b]12;green\x07");
+    assert_eq!(term.palette().cursor, RgbColor::new(0, 0x80, 0));
+
+    term.print("\x1b]11;?\x07");
+    assert_eq!(term.take_written(), "\x1b]11;rgb:0000/0000/8080\x1b\\");
+
+    term.print("\x1b]110\x07\x1b]111\x07");
+    assert_eq!(term.palette().foreground, default.foreground);
+    assert_eq!(term.palette().background, default.background);
+    assert_eq!(term.palette().cursor, RgbColor::new(0, 0x80, 0));
+
+    term.print("\x1b]112\x07");
+    assert_eq!(term.palette().cursor, default.cursor);
+}
+
+#[test]
+fn test_full_reset_restores_palette() {
+    let mut term = TestTerm::new(2, 4, 0);
+    let mut configured = ColorPalette::default();
+    configured.background = RgbColor::new(1, 2, 3);
+    term.set_default_palette(configured);
+
+    term.print("\x1b]11;#ffffff\x07\x1b]4;0;#ffffff\x07");
+    term.print("\x1bc");
+    assert_eq!(term.palette().background, RgbColor::new(1, 2, 3));
+    assert_eq!(term.palette().colors[0], ColorPalette::default().colors[0]);
+}
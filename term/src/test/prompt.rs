@@ -0,0 +1,110 @@
+//! Testing the OSC 133 semantic prompt marks
+
+use super::*;
+
+/// Emulate a shell with OSC 133 integration running a command
+fn run_command(term: &mut TestTerm, command: &str, output: &str, status: i32) {
+    term.print("\x1b]133;A\x07$ \x1b]133;B\x07");
+    term.print(command);
+    term.print("\r\n\x1b]133;C\x07");
+    term.print(output);
+    term.print(format!("\x1b]133;D;{}\x07", status));
+}
+
+fn marks(term: &TestTerm) -> Vec<SemanticMarks> {
+    term.screen().lines.iter().map(|line| line.semantic_marks()).collect()
+}
+
+#[test]
+fn test_marks_are_recorded() {
+    let mut term = TestTerm::new(3, 10, 10);
+    run_command(&mut term, "ls", "a\r\nb\r\n", 0);
+    run_command(&mut term, "false", "", 1);
+
+    // The first lines have moved into the scrollback
+    assert_all_contents(
+        &term,
+        &["$ ls      ", "a         ", "b         ", "$ false   ", "          "],
+    );
+    assert_eq!(
+        marks(&term),
+        vec![
+            SemanticMarks::PROMPT | SemanticMarks::INPUT,
+            SemanticMarks::OUTPUT,
+            SemanticMarks::empty(),
+            SemanticMarks::COMMAND_END | SemanticMarks::PROMPT | SemanticMarks::INPUT,
+            SemanticMarks::OUTPUT | SemanticMarks::COMMAND_END,
+        ]
+    );
+    assert_eq!(term.screen().lines[3].exit_status(), Some(0));
+    assert_eq!(term.screen().lines[4].exit_status(), Some(1));
+}
+
+#[test]
+fn test_marks_survive_rewrap() {
+    let mut term = TestTerm::new(3, 10, 10);
+    run_command(&mut term, "echo", "0123456789abc\r\n", 2);
+    term.print("\x1b]133;A\x07$ ");
+
+    term.resize(3, 5);
+    assert_all_contents(&term, &["$ ech", "o    ", "01234", "56789", "abc  ", "$    "]);
+    let marks = marks(&term);
+    assert_eq!(marks[0], SemanticMarks::PROMPT | SemanticMarks::INPUT);
+    assert_eq!(marks[1], SemanticMarks::empty());
+    assert_eq!(marks[2], SemanticMarks::OUTPUT);
+    assert_eq!(marks[5], SemanticMarks::COMMAND_END | SemanticMarks::PROMPT);
+    assert_eq!(term.screen().lines[5].exit_status(), Some(2));
+}
+
+#[test]
+fn test_scroll_to_prompt() {
+    let mut term = TestTerm::new(2, 10, 20);
+    run_command(&mut term, "one", "1\r\n1\r\n1\r\n", 0);
+    run_command(&mut term, "two", "2\r\n2\r\n2\r\n", 0);
+    term.print("\x1b]133;A\x07$ ");
+    assert_visible_contents(&term, &["2         ", "$         "]);
+
+    // The prompts are at rows -7, -3 and 1 relative to the top of the screen
+    assert!(term.scroll_to_prev_prompt());
+    assert_eq!(term.get_viewport_offset(), 3);
+    assert!(term.scroll_to_prev_prompt());
+    assert_eq!(term.get_viewport_offset(), 7);
+    assert!(!term.scroll_to_prev_prompt());
+
+    assert!(term.scroll_to_next_prompt());
+    assert_eq!(term.get_viewport_offset(), 3);
+    assert!(term.scroll_to_next_prompt());
+    assert_eq!(term.get_viewport_offset(), 0);
+    assert!(!term.scroll_to_next_prompt());
+}
+
+#[test]
+fn test_select_last_command_output() {
+    let mut term = TestTerm::new(4, 10, 20);
+    assert_eq!(term.select_last_command_output(), None);
+
+    run_command(&mut term, "ls", "a\r\nb\r\nc\r\n", 0);
+    run_command(&mut term, "make", "error\r\n", 2);
+    term.print("\x1b]133;A\x07$ ");
+
+    let output = term.select_last_command_output().unwrap();
+    assert_eq!(output, CommandOutput { rows: 2..3, exit_status: Some(2) });
+    assert_eq!(term.get_selection_text(), "error");
+
+    run_command(&mut term, "true", "", 0);
+    let output = term.select_last_command_output().unwrap();
+    assert_eq!(output.rows.len(), 0);
+    assert_eq!(output.exit_status, Some(0));
+
+    // Pressing enter at an empty prompt doesn't produce a C mark,
+    // so the previous command is still the last one
+    term.print("\x1b]133;A\x07$ \x1b]133;B\x07\r\n\x1b]133;D\x07");
+    term.print("\x1b]133;A\x07$ ");
+    let output = term.select_last_command_output().unwrap();
+    assert_eq!(output.exit_status, Some(0));
+
+    term.print("\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07a\r\nb\r\n\x1b]133;D\x07");
+    let output = term.select_last_command_output().unwrap();
+    assert_eq!(output.exit_status, None);
+    assert_eq!(term.get_selection_text(), "a\nb");
+}
@@ -0,0 +1,117 @@
+//! Testing the rewrapping of lines when the terminal is resized
+
+use super::*;
+
+#[test]
+fn test_reflow_narrower() {
+    let mut term = TestTerm::new(3, 10, 5);
+    term.print("abcdefghijkl");
+    assert_visible_contents(&term, &["abcdefghij", "kl        ", "          "]);
+    term.assert_cursor_pos(2, 1, None);
+
+    term.resize(3, 5);
+    assert_visible_contents(&term, &["abcde", "fghij", "kl   "]);
+    term.assert_cursor_pos(2, 2, Some("cursor follows the text"));
+
+    term.resize(3, 10);
+    assert_visible_contents(&term, &["abcdefghij", "kl        ", "          "]);
+    term.assert_cursor_pos(2, 1, None);
+
+    term.resize(3, 15);
+    assert_visible_contents(&term, &["abcdefghijkl   ", "               ", "               "]);
+    term.assert_cursor_pos(12, 0, None);
+}
+
+#[test]
+fn test_reflow_preserves_newlines() {
+    let mut term = TestTerm::new(3, 5, 0);
+    term.print("ab\r\ncd");
+    term.resize(3, 10);
+    assert_visible_contents(&term, &["ab        ", "cd        ", "          "]);
+    term.assert_cursor_pos(2, 1, None);
+
+    term.resize(3, 1);
+    assert_visible_contents(&term, &["c", "d", " "]);
+    term.assert_cursor_pos(0, 2, Some("lines below the cursor are dropped"));
+}
+
+#[test]
+fn test_reflow_into_scrollback() {
+    let mut term = TestTerm::new(2, 4, 10);
+    term.print("abcdefghij");
+    assert_all_contents(&term, &["abcd", "efgh", "ij  "]);
+
+    term.resize(2, 2);
+    assert_all_contents(&term, &["ab", "cd", "ef", "gh", "ij", "  "]);
+    term.assert_cursor_pos(0, 1, None);
+
+    term.resize(2, 6);
+    assert_all_contents(&term, &["abcdef", "ghij  "]);
+    term.assert_cursor_pos(4, 1, None);
+}
+
+#[test]
+fn test_reflow_pending_wrap() {
+    let mut term = TestTerm::new(2, 4, 10);
+    term.print("abcdefgh");
+    term.assert_cursor_pos(3, 1, Some("wrap is pending"));
+
+    term.resize(2, 10);
+    assert_visible_contents(&term, &["abcdefgh  ", "          "]);
+    term.assert_cursor_pos(8, 0, Some("cursor moves past the last character"));
+    term.print("i");
+    assert_visible_contents(&term, &["abcdefghi ", "          "]);
+}
+
+#[test]
+fn test_reflow_wide_chars() {
+    let mut term = TestTerm::new(3, 6, 0);
+    term.print("abcd\u{65e5}x");
+    assert_visible_contents(&term, &["abcd\u{65e5}", "x     ", "      "]);
+
+    term.resize(3, 5);
+    assert_visible_contents(&term, &["abcd ", "\u{65e5}x  ", "     "]);
+    term.assert_cursor_pos(3, 1, None);
+}
+
+#[test]
+fn test_reflow_alt_screen() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.print("abcdef");
+    term.set_mode("?1049", true);
+    term.print("12345");
+    term.resize(3, 2);
+    term.set_mode("?1049", false);
+    assert_visible_contents(&term, &["cd", "ef", "  "]);
+    term.assert_cursor_pos(0, 2, Some("primary cursor was rewrapped"));
+}
+
+#[test]
+fn test_resize_to_nothing() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.print("abcdef");
+    term.resize(0, 0);
+    term.assert_cursor_pos(0, 0, None);
+    term.print("x");
+    term.resize(3, 4);
+    term.print("y");
+    // The text that no longer fitted at 1x1 is gone
+    assert_visible_contents(&term, &["xy  ", "    ", "    "]);
+    term.assert_cursor_pos(2, 0, None);
+}
+
+#[test]
+fn test_reflow_wide_char_at_width_one() {
+    let mut term = TestTerm::new(3, 4, 0);
+    term.print("a\u{65e5}b");
+    term.resize(3, 1);
+    assert_visible_contents(&term, &["a", "\u{65e5}", "b"]);
+    term.assert_cursor_pos(0, 2, None);
+    // A character that is wider than the screen keeps its padding, so
+    // that the text is restored intact when the screen grows again
+    assert_eq!(term.screen().lines[1].cells.len(), 2);
+
+    term.resize(3, 4);
+    assert_visible_contents(&term, &["a\u{65e5}b", "    ", "    "]);
+    term.assert_cursor_pos(3, 0, None);
+}
@@ -0,0 +1,193 @@
+//! Testing searching the screen and scrollback
+
+use super::*;
+
+#[test]
+fn test_search_literal() {
+    let mut term = TestTerm::new(3, 10, 10);
+    term.print("error: a\r\nok\r\nerror: b\r\nok\r\nok");
+    assert_all_contents(
+        &term,
+        &["error: a  ", "ok        ", "error: b  ", "ok        ", "ok        "],
+    );
+
+    let matches = term.search(&Pattern::Literal("error".to_owned()));
+    assert_eq!(
+        matches,
+        vec![
+            SearchMatch {
+                start: SelectionCoordinate { x: 0, y: -2 },
+                end: SelectionCoordinate { x: 4, y: -2 },
+            },
+            SearchMatch {
+                start: SelectionCoordinate { x: 0, y: 0 },
+                end: SelectionCoordinate { x: 4, y: 0 },
+            },
+        ]
+    );
+    assert_eq!(term.current_search_match(), Some(matches[1]));
+    assert_eq!(term.get_viewport_offset(), 0, "current match was already in view");
+
+    assert_eq!(term.prev_search_match(), Some(matches[0]));
+    assert_eq!(term.get_viewport_offset(), 2, "scrolled back to the match");
+
+    assert_eq!(term.prev_search_match(), Some(matches[1]), "wraps around");
+    assert_eq!(term.next_search_match(), Some(matches[0]), "wraps around");
+
+    term.clear_search();
+    assert_eq!(term.search_matches(), &[]);
+    assert_eq!(term.next_search_match(), None);
+}
+
+#[test]
+fn test_search_regex() {
+    let mut term = TestTerm::new(3, 12, 0);
+    term.print("foo.rs:12\r\nbar.rs:345");
+
+    let pattern = Pattern::regex(r"\w+\.rs:\d+").unwrap();
+    let matches = term.search(&pattern);
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[1].start, SelectionCoordinate { x: 0, y: 1 });
+    assert_eq!(matches[1].end, SelectionCoordinate { x: 9, y: 1 });
+
+    assert!(Pattern::regex("(").is_err());
+
+    // Patterns that match the empty string don't produce matches
+    let matches = term.search(&Pattern::regex("x*").unwrap());
+    assert_eq!(matches, vec![]);
+}
+
+#[test]
+fn test_search_wrapped() {
+    let mut term = TestTerm::new(3, 5, 5);
+    term.print("see https://x.com ok");
+    assert_all_contents(&term, &["see h", "ttps:", "//x.c", "om ok"]);
+
+    let matches = term.search(&Pattern::Literal("https://x.com".to_owned()));
+    assert_eq!(
+        matches,
+        vec![SearchMatch {
+            start: SelectionCoordinate { x: 4, y: -1 },
+            end: SelectionCoordinate { x: 1, y: 2 },
+        }]
+    );
+}
+
+#[test]
+fn test_search_highlights() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("ab ab\r\n\u{65e5}ab");
+    term.clean_dirty_lines();
+
+    term.search(&Pattern::Literal("ab".to_owned()));
+    let highlights: Vec<(usize, Vec<SearchHighlight>)> =
+        term.get_dirty_lines().into_iter().map(|(idx, _, _, h)| (idx, h)).collect();
+    assert_eq!(
+        highlights,
+        vec![
+            (
+                0,
+                vec![
+                    SearchHighlight { cols: 0..2, current: false },
+                    SearchHighlight { cols: 3..5, current: false },
+                ]
+            ),
+            (1, vec![SearchHighlight { cols: 2..4, current: true }]),
+            (2, vec![]),
+        ]
+    );
+
+    // The search tracks new output once it is refreshed
+    term.print("\r\nab");
+    assert_eq!(term.search_matches().len(), 3);
+    term.refresh_search();
+    assert_eq!(term.search_matches().len(), 4);
+}
+
+#[test]
+fn test_search_refresh() {
+    let mut term = TestTerm::new(3, 10, 10);
+    term.print("ab\r\nab\r\nxx");
+    term.search(&Pattern::Literal("ab".to_owned()));
+    let first = term.prev_search_match().unwrap();
+    assert_eq!(first.start, SelectionCoordinate { x: 0, y: 0 });
+    term.clean_dirty_lines();
+
+    // Output that doesn't touch the matches leaves their lines alone
+    term.print("x");
+    term.refresh_search();
+    let dirty: Vec<usize> = term.get_dirty_lines().into_iter().map(|(idx, ..)| idx).collect();
+    assert_eq!(dirty, vec![2]);
+
+    // The current match follows its text as the screen scrolls, even
+    // though new matches are added below it
+    term.print("\r\nab\r\nab");
+    term.clean_dirty_lines();
+    term.refresh_search();
+    assert_eq!(term.search_matches().len(), 4);
+    assert_eq!(
+        term.current_search_match().map(|m| m.start),
+        Some(SelectionCoordinate { x: 0, y: -2 })
+    );
+    // Only the rows with new matches are repainted
+    let dirty: Vec<usize> = term.get_dirty_lines().into_iter().map(|(idx, ..)| idx).collect();
+    assert_eq!(dirty, vec![1, 2]);
+}
+
+#[test]
+fn test_search_resize() {
+    let mut term = TestTerm::new(3, 10, 10);
+    term.print("xx ab\r\nxxxxxxx ab\r\nyy");
+    term.search(&Pattern::Literal("ab".to_owned()));
+    let first = term.prev_search_match().unwrap();
+    assert_eq!(first.start, SelectionCoordinate { x: 3, y: 0 });
+
+    let starts = |term: &TestTerm| -> Vec<SelectionCoordinate> {
+        term.search_matches().iter().map(|m| m.start).collect()
+    };
+
+    // Reflowing moves the matches, and the current match stays with
+    // its text
+    term.resize(3, 5);
+    assert_all_contents(&term, &["xx ab", "xxxxx", "xx ab", "yy   "]);
+    term.refresh_search();
+    assert_eq!(
+        starts(&term),
+        vec![SelectionCoordinate { x: 3, y: -1 }, SelectionCoordinate { x: 3, y: 1 }]
+    );
+    assert_eq!(
+        term.current_search_match().map(|m| m.start),
+        Some(SelectionCoordinate { x: 3, y: -1 })
+    );
+
+    term.resize(3, 10);
+    term.refresh_search();
+    assert_eq!(
+        starts(&term),
+        vec![SelectionCoordinate { x: 3, y: 0 }, SelectionCoordinate { x: 8, y: 1 }]
+    );
+    assert_eq!(
+        term.current_search_match().map(|m| m.start),
+        Some(SelectionCoordinate { x: 3, y: 0 })
+    );
+
+    // The search starts over on the other screen, and scrolling the
+    // alternate screen doesn't move the matches on the primary screen
+    term.print("\x1b[?1049hab\r\n\r\n\r\n\r\nab");
+    term.refresh_search();
+    assert_eq!(starts(&term), vec![SelectionCoordinate { x: 0, y: 2 }]);
+    assert_eq!(
+        term.current_search_match().map(|m| m.start),
+        Some(SelectionCoordinate { x: 0, y: 2 })
+    );
+    term.print("\x1b[?1049l");
+    term.refresh_search();
+    assert_eq!(
+        starts(&term),
+        vec![SelectionCoordinate { x: 3, y: 0 }, SelectionCoordinate { x: 8, y: 1 }]
+    );
+    assert_eq!(
+        term.current_search_match().map(|m| m.start),
+        Some(SelectionCoordinate { x: 8, y: 1 })
+    );
+}
@@ -4,7 +4,7 @@ use super::*;
 #[test]
 fn drag_selection() {
     let mut term = TestTerm::new(3, 12, 0);
-    term.print("hello world\n");
+    term.print("hello world\r\n");
     assert_visible_contents(&term, &["hello world ", "            ", "            "]);
 
     term.drag_select(1, 0, 4, 0);
@@ -12,7 +12,7 @@ fn drag_selection() {
 
     // Now check that we respect double-width boundaries reasonably sanely;
     // here we're dragging from the middle of the skull emoji
-    term.print("\u{1F480}skull\n");
+    term.print("\u{1F480}skull\r\n");
     assert_visible_contents(&term, &["hello world ", "\u{1F480}skull     ", "            "]);
     term.drag_select(1, 1, 5, 1);
     assert_eq!(term.get_clipboard().unwrap(), "skul");
@@ -56,3 +56,34 @@ fn triple_click_selection() {
 
     assert_eq!(term.get_clipboard().unwrap(), "hello worl");
 }
+
+/// Test that text wrapped by the terminal is copied as a single line
+#[test]
+fn wrapped_line_selection() {
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print("hello world, wide\r\nnext");
+    assert_visible_contents(&term, &["hello worl", "d, wide   ", "next      "]);
+
+    term.drag_select(0, 0, 3, 2);
+    assert_eq!(term.get_clipboard().unwrap(), "hello world, wide\nnext");
+
+    term.drag_select(6, 0, 1, 1);
+    assert_eq!(term.get_clipboard().unwrap(), "world,");
+}
+
+/// The wrapped state is retained as lines move into the scrollback,
+/// and is cleared when lines are recycled
+#[test]
+fn wrapped_line_scrollback() {
+    let mut term = TestTerm::new(2, 5, 5);
+    term.print("abcdefg\r\nxy\r\nz");
+    assert_all_contents(&term, &["abcde", "fg   ", "xy   ", "z    "]);
+    let wrapped: Vec<bool> = term.screen().lines.iter().map(Line::is_wrapped).collect();
+    assert_eq!(wrapped, vec![true, false, false, false]);
+
+    let mut term = TestTerm::new(2, 5, 0);
+    term.print("abcdefgh\r\n12");
+    assert_all_contents(&term, &["fgh  ", "12   "]);
+    let wrapped: Vec<bool> = term.screen().lines.iter().map(Line::is_wrapped).collect();
+    assert_eq!(wrapped, vec![false, false]);
+}
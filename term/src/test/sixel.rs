@@ -0,0 +1,149 @@
+//! Testing the decoding and placement of sixel images
+
+use super::*;
+
+/// Returns the image slices on each line of the screen, including
+/// the scrollback
+fn images(term: &TestTerm) -> Vec<Vec<ImageSlice>> {
+    term.screen().lines.iter().map(|line| line.images().to_vec()).collect()
+}
+
+/// Returns the number of image slices on each line of the screen
+fn image_counts(term: &TestTerm) -> Vec<usize> {
+    term.screen().lines.iter().map(|line| line.images().len()).collect()
+}
+
+#[test]
+fn test_sixel_placement() {
+    let mut term = TestTerm::new(4, 5, 0);
+    term.set_cell_size(2, 4);
+    term.print("a");
+    // Two columns of six red pixels
+    term.print("\x1bPq#1;2;100;0;0#1~~\x1b\\");
+
+    let lines = images(&term);
+    let image = Rc::clone(&lines[0][0].image);
+    assert_eq!((image.width(), image.height()), (2, 6));
+    assert_eq!(image.pixel(1, 5), [255, 0, 0, 255]);
+
+    assert_eq!(
+        lines,
+        vec![
+            vec![ImageSlice {
+                image: Rc::clone(&image),
+                x: 1,
+                cols: 1,
+                top: 0,
+                height: 4,
+                z_index: 0,
+                placement: None
+            }],
+            vec![ImageSlice {
+                image: Rc::clone(&image),
+                x: 1,
+                cols: 1,
+                top: 4,
+                height: 2,
+                z_index: 0,
+                placement: None
+            }],
+            vec![],
+            vec![],
+        ]
+    );
+    term.assert_cursor_pos(1, 2, Some("cursor moves below the image"));
+}
+
+#[test]
+fn test_sixel_decoding() {
+    let mut term = TestTerm::new(4, 5, 0);
+    // P2 == 1 leaves the undrawn pixels transparent.  The raster
+    // attributes make the image larger than the pixels drawn.
+    term.print("\x1bP0;1q\"1;1;4;13#2!3@$#1;1;120;50;100A-@\x1b\\");
+    let image = Rc::clone(&term.screen().lines[0].images()[0].image);
+    assert_eq!((image.width(), image.height()), (4, 13));
+    // Register 2 is VT340 red, until it is redefined
+    assert_eq!(image.pixel(0, 0), [204, 33, 33, 255]);
+    assert_eq!(image.pixel(2, 0), [204, 33, 33, 255]);
+    assert_eq!(image.pixel(3, 0), [0, 0, 0, 0]);
+    // `A` sets the second bit, in HLS red, which is hue 120
+    assert_eq!(image.pixel(0, 1), [255, 0, 0, 255]);
+    assert_eq!(image.pixel(1, 1), [0, 0, 0, 0]);
+    // The second band, following `-`
+    assert_eq!(image.pixel(0, 6), [255, 0, 0, 255]);
+    assert_eq!(image.pixel(0, 12), [0, 0, 0, 0]);
+
+    // Otherwise the undrawn pixels have the color of register 0
+    term.print("\x1bPq#1;2;0;0;100!2@\x1b\\");
+    let image = Rc::clone(&term.screen().lines[1].images()[0].image);
+    assert_eq!((image.width(), image.height()), (2, 1));
+    assert_eq!(image.pixel(1, 0), [0, 0, 255, 255]);
+
+    term.print("\x1bPq#1;2;0;0;100!2A\x1b\\");
+    let image = Rc::clone(&term.screen().lines[2].images()[0].image);
+    assert_eq!(image.pixel(0, 0), [0, 0, 0, 255]);
+
+    // Parameters saturate, and a huge hue wraps around the circle.
+    // The screen has scrolled, so the image is on the third line.
+    term.print("\x1bPq#1;1;4294967295;50;100@\x1b\\");
+    let image = Rc::clone(&term.screen().lines[2].images()[0].image);
+    assert_eq!(image.pixel(0, 0), [0, 255, 64, 255]);
+}
+
+#[test]
+fn test_sixel_scrolls_into_scrollback() {
+    let mut term = TestTerm::new(3, 5, 5);
+    term.set_cell_size(2, 4);
+    term.print("\x1bPq~~-~~\x1b\\");
+    // Moving the cursor below the image scrolled the screen
+    assert_eq!(image_counts(&term), vec![1, 1, 1, 0]);
+
+    term.print("\r\n\r\n\r\n");
+    assert_eq!(image_counts(&term), vec![1, 1, 1, 0, 0, 0, 0]);
+    assert_eq!(term.visible_images().len(), 0);
+
+    term.scroll_viewport(-3);
+    let rows: Vec<usize> = term.visible_images().iter().map(|&(row, _)| row).collect();
+    assert_eq!(rows, vec![0, 1]);
+}
+
+#[test]
+fn test_sixel_image_budget() {
+    let mut term = TestTerm::new(5, 5, 0);
+    term.set_cell_size(2, 8);
+    // Each image is one pixel wide and six high, taking 24 bytes, and
+    // moves the cursor to the next line
+    term.set_line_image_budget(48);
+    term.print("\x1bPq~\x1b\\\x1bPq~\x1b\\");
+    assert_eq!(image_counts(&term), vec![1, 1, 0, 0, 0]);
+
+    // The oldest image is removed from the lines to make room
+    term.print("\x1bPq~\x1b\\");
+    assert_eq!(image_counts(&term), vec![0, 1, 1, 0, 0]);
+
+    // The newest is kept even if it doesn't fit on its own
+    term.set_line_image_budget(0);
+    term.print("\x1bPq~\x1b\\");
+    assert_eq!(image_counts(&term), vec![0, 0, 0, 1, 0]);
+}
+
+#[test]
+fn test_sixel_erase() {
+    let mut term = TestTerm::new(4, 5, 0);
+    term.set_cell_size(2, 4);
+    term.print("\x1bPq~~~~-~~~~\x1b\\");
+    assert_eq!(image_counts(&term), vec![1, 1, 1, 0]);
+
+    // Erasing text beside the image leaves it in place
+    term.cup(3, 0);
+    term.print("\x1b[K");
+    assert_eq!(image_counts(&term), vec![1, 1, 1, 0]);
+
+    // Erasing part of it removes it from that line
+    term.cup(1, 0);
+    term.print("\x1b[K");
+    assert_eq!(image_counts(&term), vec![0, 1, 1, 0]);
+
+    term.print("\x1b[2J");
+    assert_eq!(image_counts(&term), vec![0, 0, 0, 0]);
+}